@@ -1,7 +1,13 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{bail, Result};
+use clap::{Args, Parser, Subcommand};
 
-use crate::run::{run_file, run_prompt};
+use crate::{
+    debugger::{run_dap, run_debug},
+    fmt::{self, fmt_files},
+    lsp::run_lsp,
+    repl::run_prompt,
+    run::{run_file, run_file_instrumented, BackendKind, CoverageFormat},
+};
 
 /// The command line options to be collected.
 #[derive(Debug, Parser)]
@@ -10,11 +16,77 @@ use crate::run::{run_file, run_prompt};
     author = clap::crate_authors!(),
     about = clap::crate_description!(),
 )]
+#[clap(args_conflicts_with_subcommands = true)]
 #[allow(clippy::struct_excessive_bools)]
 pub struct Dolores {
+    #[clap(subcommand)]
+    pub(crate) cmd: Option<Command>,
+
     /// Package name or (sometimes) regex.
     #[clap(name = "FILE")]
     pub(crate) file: Option<String>,
+
+    /// The backend to run Lox code with.
+    #[clap(long, value_enum, default_value_t)]
+    pub(crate) backend: BackendKind,
+
+    /// Run FILE in the step debugger, which requires the tree backend.
+    #[clap(long, requires = "FILE")]
+    pub(crate) debug: bool,
+
+    /// Report the calls and the time spent in each function after running
+    /// FILE, which requires the tree backend.
+    #[clap(long, requires = "FILE")]
+    pub(crate) profile: bool,
+
+    /// Report the lines run after running FILE, which requires the tree
+    /// backend.
+    #[clap(
+        long,
+        value_enum,
+        requires = "FILE",
+        min_values = 0,
+        max_values = 1,
+        require_equals = true,
+        default_missing_value = "text",
+        value_name = "FORMAT"
+    )]
+    pub(crate) coverage: Option<CoverageFormat>,
+
+    /// Write the coverage report to this file instead of the standard error.
+    #[clap(long, requires = "coverage", value_name = "PATH")]
+    pub(crate) coverage_output: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Format Lox source files.
+    Fmt(Fmt),
+    /// Run a language server over the standard input and output.
+    Lsp,
+    /// Run a Debug Adapter Protocol server over the standard input and output.
+    Dap,
+}
+
+/// The options of `dolores fmt`.
+#[derive(Debug, Args)]
+pub(crate) struct Fmt {
+    /// The files to format in place. The standard input is formatted to the
+    /// standard output if none is given.
+    #[clap(name = "FILES")]
+    pub(crate) files: Vec<String>,
+
+    /// Exit with an error if any file is not formatted, without changing it.
+    #[clap(long)]
+    pub(crate) check: bool,
+
+    /// The number of spaces per indentation level.
+    #[clap(long, default_value_t = fmt::Options::default().indent)]
+    pub(crate) indent: usize,
+
+    /// The maximum line width.
+    #[clap(long, default_value_t = fmt::Options::default().width)]
+    pub(crate) width: usize,
 }
 
 impl Dolores {
@@ -23,7 +95,39 @@ impl Dolores {
     }
 
     pub(crate) fn dispatch(self) -> Result<()> {
-        let file = self.file;
-        file.map_or_else(run_prompt, run_file)
+        match self.cmd {
+            Some(Command::Fmt(cmd)) => {
+                let opts = fmt::Options {
+                    indent: cmd.indent,
+                    width: cmd.width,
+                };
+                return fmt_files(&cmd.files, cmd.check, opts);
+            }
+            Some(Command::Lsp) => return run_lsp(),
+            Some(Command::Dap) => return run_dap(),
+            None => (),
+        }
+        let backend = self.backend;
+        if self.debug {
+            if backend != BackendKind::Tree {
+                bail!("the debugger only supports the tree backend");
+            }
+            // This is a safe unwrap since `--debug` requires `FILE`.
+            return run_debug(self.file.unwrap());
+        }
+        if self.profile || self.coverage.is_some() {
+            if backend != BackendKind::Tree {
+                bail!("profiling and coverage only support the tree backend");
+            }
+            // This is a safe unwrap since both options require `FILE`.
+            return run_file_instrumented(
+                self.file.unwrap(),
+                self.profile,
+                self.coverage,
+                self.coverage_output.as_deref(),
+            );
+        }
+        self.file
+            .map_or_else(|| run_prompt(backend), |file| run_file(file, backend))
     }
 }
@@ -0,0 +1,221 @@
+mod dap;
+mod tests;
+
+use std::{
+    cell::RefCell,
+    fmt::{Debug, Display},
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use anyhow::Result;
+use itertools::Itertools;
+
+pub(crate) use self::dap::run_dap;
+use crate::{
+    error::{render_error, Diagnostic},
+    interpreter::{
+        debug::{Debugger, Frontend, Paused, Resume, Stop, StopReason, Terminated},
+        Interpreter,
+    },
+    module,
+    run::run_str,
+    source::SourceMap,
+};
+
+const HELP: &str = "\
+c, continue           run until the next breakpoint or runtime error
+s, step               step to the next statement, entering calls
+n, next               step to the next statement, skipping calls
+o, out                step out of the current call
+b, break [FILE:]LINE  set a breakpoint, or list them without argument
+d, delete [FILE:]LINE remove a breakpoint
+catch on|off          stop on runtime errors or not
+bt, backtrace         show the calls in progress
+env                   show the local environments
+g, globals            show the global variables
+p, print EXPR         evaluate an expression in the current scope
+q, quit               terminate the program
+h, help               show this help
+An empty line repeats the last command.";
+
+/// Runs the Lox source `src` named `name` with the given debugger, returning
+/// the rendered error if it fails, unless it has been terminated from the
+/// debugger.
+///
+/// The warnings found are passed to `warn` before running the source.
+pub(crate) fn debug_str(
+    name: &str,
+    src: &str,
+    debugger: Debugger,
+    warn: impl FnMut(&Diagnostic, &SourceMap),
+) -> Option<String> {
+    let mut interpreter = Interpreter::default();
+    interpreter.debugger = Some(Rc::new(debugger));
+    let res = module::run_entry(&mut interpreter, Path::new(name), |it| {
+        run_str(name, src, it, false, warn)
+    });
+    match res {
+        Err(e) if !e.is::<Terminated>() => Some(render_error(&e, &interpreter.sources)),
+        _ => None,
+    }
+}
+
+/// Runs the file at `path` in the step debugger on the console, stopping
+/// before its first statement.
+pub(crate) fn run_debug(path: impl AsRef<Path>) -> Result<()> {
+    let name = path.as_ref().display().to_string();
+    let src = fs::read_to_string(path)?;
+    let console = Rc::new(Console::new(io::stdin().lock(), io::stdout()));
+    let debugger = Debugger::new(Rc::clone(&console) as _);
+    debugger.stop_on_entry();
+    let warn = |it: &Diagnostic, sources: &SourceMap| console.output(&it.render(sources));
+    if let Some(e) = debug_str(&name, &src, debugger, warn) {
+        console.output(&e);
+    }
+    Ok(())
+}
+
+/// A front end reading the commands of the debugger from `input`, and writing
+/// its reports to `output` together with the output of the program.
+#[derive(Debug)]
+pub(crate) struct Console<R, W> {
+    input: RefCell<R>,
+    output: RefCell<W>,
+    /// The last command, which is repeated on an empty line.
+    last: RefCell<String>,
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    pub(crate) fn new(input: R, output: W) -> Self {
+        Self {
+            input: RefCell::new(input),
+            output: RefCell::new(output),
+            last: RefCell::default(),
+        }
+    }
+
+    fn say(&self, msg: impl Display) {
+        let mut output = self.output.borrow_mut();
+        let _ = writeln!(output, "{msg}");
+        let _ = output.flush();
+    }
+
+    /// Reads the next command, or returns `None` at the end of `input`.
+    fn read_command(&self) -> Result<Option<String>> {
+        {
+            let mut output = self.output.borrow_mut();
+            write!(output, "(dbg) ")?;
+            output.flush()?;
+        }
+        let mut line = String::new();
+        if self.input.borrow_mut().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let mut last = self.last.borrow_mut();
+        if !line.trim().is_empty() {
+            line.trim().clone_into(&mut last);
+        }
+        Ok(Some(last.clone()))
+    }
+
+    /// Adds or removes the breakpoint at `arg`, which is `[FILE:]LINE`.
+    fn toggle_breakpoint(&self, paused: &Paused<'_>, arg: &str, on: bool) {
+        let (file, line) = match arg.rsplit_once(':') {
+            Some((file, line)) => (file, line),
+            None => (paused.location().file.as_str(), arg),
+        };
+        let Ok(line) = line.parse() else {
+            self.say(format_args!("expected `[FILE:]LINE`, found `{arg}`"));
+            return;
+        };
+        let changed = paused.debugger().toggle_breakpoint(file, line, on);
+        match (on, changed) {
+            (true, true) => self.say(format_args!("breakpoint set at {file}:{line}")),
+            (true, false) => self.say(format_args!("breakpoint already set at {file}:{line}")),
+            (false, true) => self.say(format_args!("breakpoint removed at {file}:{line}")),
+            (false, false) => self.say(format_args!("no breakpoint at {file}:{line}")),
+        }
+    }
+}
+
+impl<R: BufRead + Debug, W: Write + Debug> Frontend for Console<R, W> {
+    fn stopped(&self, stop: &Stop, paused: &mut Paused<'_>) -> Result<Resume> {
+        let why = match &stop.reason {
+            StopReason::Entry | StopReason::Step => "stopped".into(),
+            StopReason::Breakpoint => "stopped at a breakpoint".into(),
+            StopReason::Error(msg) => format!("stopped on a runtime error: {msg}"),
+        };
+        self.say(format_args!("{why} at {}", stop.loc));
+        self.say(format_args!("{:>4} | {}", stop.loc.line, stop.text));
+        while let Some(cmd) = self.read_command()? {
+            let (cmd, arg) = cmd.split_once(' ').unwrap_or((&cmd, ""));
+            let arg = arg.trim();
+            match cmd {
+                "c" | "continue" => return Ok(Resume::Continue),
+                "s" | "step" => return Ok(Resume::StepIn),
+                "n" | "next" => return Ok(Resume::StepOver),
+                "o" | "out" => return Ok(Resume::StepOut),
+                "q" | "quit" => return Ok(Resume::Terminate),
+                "b" | "break" if arg.is_empty() => {
+                    let breakpoints = paused.debugger().breakpoints();
+                    if breakpoints.is_empty() {
+                        self.say("no breakpoints");
+                    }
+                    for (file, line) in breakpoints {
+                        self.say(format_args!("{}:{line}", file.display()));
+                    }
+                }
+                "b" | "break" => self.toggle_breakpoint(paused, arg, true),
+                "d" | "delete" => self.toggle_breakpoint(paused, arg, false),
+                "catch" if ["on", "off"].contains(&arg) => {
+                    paused.debugger().break_on_error(arg == "on");
+                }
+                "bt" | "backtrace" => {
+                    for (i, frame) in paused.backtrace().into_iter().enumerate() {
+                        let loc = frame
+                            .loc
+                            .map_or_else(|| "<unknown>".into(), |it| it.to_string());
+                        self.say(format_args!("#{i} {loc}, in {}", frame.name));
+                    }
+                }
+                "env" | "g" | "globals" => {
+                    let mut scopes = paused.scopes();
+                    let globals = scopes.pop().into_iter();
+                    let scopes = if cmd == "env" {
+                        scopes
+                    } else {
+                        globals.collect()
+                    };
+                    if scopes.is_empty() {
+                        self.say("no local environments");
+                    }
+                    for scope in scopes {
+                        self.say(format_args!("{}:", scope.name));
+                        for (name, val) in scope.vars {
+                            self.say(format_args!("  {name} = {val}"));
+                        }
+                    }
+                }
+                "p" | "print" => match paused.eval(arg) {
+                    Ok(val) => self.say(val),
+                    Err(e) => self.say(paused.render_error(&e).trim_end()),
+                },
+                "h" | "help" => self.say(HELP),
+                _ => self.say(format_args!(
+                    "unknown command `{}`, see `help`",
+                    [cmd, arg].iter().filter(|it| !it.is_empty()).join(" ")
+                )),
+            }
+        }
+        Ok(Resume::Terminate)
+    }
+
+    fn output(&self, text: &str) {
+        let mut output = self.output.borrow_mut();
+        let _ = write!(output, "{text}");
+        let _ = output.flush();
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Debug,
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use anyhow::{anyhow, Context, Result};
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use super::debug_str;
+use crate::{
+    error::Diagnostic,
+    interpreter::debug::{Debugger, Frontend, Paused, Resume, Scope, Stop, StopReason},
+    lsp::{read_message, write_message},
+    source::SourceMap,
+};
+
+/// The only thread of a Lox program.
+const THREAD_ID: u64 = 1;
+
+/// Runs a Debug Adapter Protocol server over the standard input and output.
+pub(crate) fn run_dap() -> Result<()> {
+    serve(io::stdin().lock(), io::stdout())
+}
+
+/// Serves a debugging session with the DAP messages read from `input`,
+/// writing the responses and events to `output`.
+///
+/// The program given by the `launch` request is run once the configuration is
+/// done, and the requests are only read while it is stopped.
+pub(crate) fn serve<R, W>(input: R, output: W) -> Result<()>
+where
+    R: BufRead + Debug + 'static,
+    W: Write + Debug + 'static,
+{
+    let dap = Rc::new(Dap {
+        input: RefCell::new(input),
+        output: RefCell::new(output),
+        seq: Cell::new(0),
+        scopes: RefCell::default(),
+        disconnected: Cell::new(false),
+    });
+    let debugger = Debugger::new(Rc::clone(&dap) as _);
+    let (mut program, mut configured) = (None, false);
+    while program.is_none() || !configured {
+        let Some(req) = dap.request()? else {
+            return Ok(());
+        };
+        let args = &req["arguments"];
+        let res = match req["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                let caps = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsEvaluateForHovers": true,
+                    "exceptionBreakpointFilters": [
+                        { "filter": "runtime", "label": "Runtime errors", "default": true },
+                    ],
+                });
+                dap.respond(&req, Ok(caps))?;
+                dap.event("initialized", &json!({}))?;
+                continue;
+            }
+            "launch" => (args["program"].as_str())
+                .context("missing the `program` to launch")
+                .map(|it| {
+                    program = Some(it.to_owned());
+                    if args["stopOnEntry"].as_bool() == Some(true) {
+                        debugger.stop_on_entry();
+                    }
+                    Value::Null
+                }),
+            "configurationDone" => {
+                configured = true;
+                Ok(Value::Null)
+            }
+            "disconnect" | "terminate" => return dap.respond(&req, Ok(Value::Null)),
+            cmd => configure(&debugger, &req, cmd),
+        };
+        dap.respond(&req, res)?;
+    }
+    let program = program.unwrap_or_default();
+    let warn = |it: &Diagnostic, sources: &SourceMap| dap.print("console", &it.render(sources));
+    let err = match fs::read_to_string(&program) {
+        Ok(src) => debug_str(&program, &src, debugger, warn),
+        Err(e) => Some(format!("failed to read `{program}`: {e}\n")),
+    };
+    if dap.disconnected.get() {
+        return Ok(());
+    }
+    if let Some(err) = &err {
+        dap.print("stderr", err);
+    }
+    dap.event("exited", &json!({ "exitCode": i32::from(err.is_some()) }))?;
+    dap.event("terminated", &json!({}))?;
+    while let Some(req) = dap.request()? {
+        match req["command"].as_str().unwrap_or_default() {
+            "disconnect" | "terminate" => return dap.respond(&req, Ok(Value::Null)),
+            _ => dap.respond(&req, Err(anyhow!("the program has exited")))?,
+        }
+    }
+    Ok(())
+}
+
+/// Handles the request `cmd`, which does not depend on whether the program
+/// is running.
+fn configure(debugger: &Debugger, req: &Value, cmd: &str) -> Result<Value> {
+    let args = &req["arguments"];
+    match cmd {
+        "setBreakpoints" => {
+            let path = (args.pointer("/source/path").and_then(Value::as_str))
+                .context("missing the `source` of the breakpoints")?;
+            let lines = (args["breakpoints"].as_array().into_iter().flatten())
+                .filter_map(|it| it["line"].as_u64()?.try_into().ok())
+                .collect_vec();
+            debugger.set_breakpoints(path, lines.iter().copied());
+            let breakpoints = (lines.iter())
+                .map(|it| json!({ "verified": true, "line": it }))
+                .collect_vec();
+            Ok(json!({ "breakpoints": breakpoints }))
+        }
+        "setExceptionBreakpoints" => {
+            let filters = args["filters"].as_array().into_iter().flatten();
+            debugger.break_on_error(filters.into_iter().any(|it| it == "runtime"));
+            Ok(Value::Null)
+        }
+        "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+        _ => Err(anyhow!("unsupported command `{cmd}`")),
+    }
+}
+
+#[derive(Debug)]
+struct Dap<R, W> {
+    input: RefCell<R>,
+    output: RefCell<W>,
+    /// The sequence number of the last message sent.
+    seq: Cell<u64>,
+    /// The scopes listed since the program stopped, whose
+    /// `variablesReference`s are their indices plus one.
+    scopes: RefCell<Vec<Scope>>,
+    /// If the client has disconnected while the program is running.
+    disconnected: Cell<bool>,
+}
+
+impl<R: BufRead, W: Write> Dap<R, W> {
+    /// Reads the next request, or returns `None` at the end of `input`.
+    fn request(&self) -> Result<Option<Value>> {
+        let Some(msg) = read_message(&mut *self.input.borrow_mut())? else {
+            return Ok(None);
+        };
+        Ok(Some(serde_json::from_str(&msg)?))
+    }
+
+    fn send(&self, mut msg: Value) -> Result<()> {
+        self.seq.set(self.seq.get() + 1);
+        msg["seq"] = json!(self.seq.get());
+        write_message(&mut *self.output.borrow_mut(), &msg)
+    }
+
+    fn respond(&self, req: &Value, res: Result<Value>) -> Result<()> {
+        let mut msg = json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": res.is_ok(),
+        });
+        match res {
+            Ok(body) if body.is_null() => (),
+            Ok(body) => msg["body"] = body,
+            Err(e) => msg["message"] = json!(format!("{e:#}")),
+        }
+        self.send(msg)
+    }
+
+    fn event(&self, event: &str, body: &Value) -> Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    /// Sends `text` to be shown in the `category` of the client's output.
+    fn print(&self, category: &str, text: &str) {
+        let _ = self.event("output", &json!({ "category": category, "output": text }));
+    }
+
+    /// Handles the requests inspecting the stopped program.
+    fn inspect(&self, paused: &mut Paused<'_>, req: &Value, cmd: &str) -> Result<Value> {
+        let args = &req["arguments"];
+        match cmd {
+            "stackTrace" => {
+                let frames = (paused.backtrace().into_iter().enumerate())
+                    .map(|(i, frame)| {
+                        let mut res =
+                            json!({ "id": i, "name": frame.name, "line": 0, "column": 0 });
+                        if let Some(loc) = frame.loc {
+                            let name = Path::new(&loc.file).file_name().map_or_else(
+                                || loc.file.clone(),
+                                |it| it.to_string_lossy().into_owned(),
+                            );
+                            res["source"] = json!({ "name": name, "path": loc.file });
+                            res["line"] = json!(loc.line);
+                            res["column"] = json!(loc.column);
+                        }
+                        res
+                    })
+                    .collect_vec();
+                Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+            }
+            "scopes" => {
+                let mut scopes = paused.scopes();
+                // Only the environments of the innermost call are known.
+                if args["frameId"].as_u64().unwrap_or_default() != 0 {
+                    scopes.drain(..scopes.len() - 1);
+                }
+                let mut known = self.scopes.borrow_mut();
+                let res = (scopes.iter().enumerate())
+                    .map(|(i, it)| {
+                        json!({
+                            "name": it.name,
+                            "variablesReference": known.len() + i + 1,
+                            "expensive": false,
+                        })
+                    })
+                    .collect_vec();
+                known.extend(scopes);
+                Ok(json!({ "scopes": res }))
+            }
+            "variables" => {
+                let known = self.scopes.borrow();
+                let scope = (args["variablesReference"].as_u64())
+                    .and_then(|it| known.get(usize::try_from(it).ok()?.checked_sub(1)?))
+                    .context("unknown `variablesReference`")?;
+                let vars = (scope.vars.iter())
+                    .map(|(name, val)| {
+                        json!({ "name": name, "value": val.to_string(), "variablesReference": 0 })
+                    })
+                    .collect_vec();
+                Ok(json!({ "variables": vars }))
+            }
+            "evaluate" => {
+                let expr = args["expression"].as_str().unwrap_or_default();
+                match paused.eval(expr) {
+                    Ok(val) => Ok(json!({ "result": val.to_string(), "variablesReference": 0 })),
+                    Err(e) => Err(anyhow!("{}", paused.render_error(&e).trim_end())),
+                }
+            }
+            _ => configure(paused.debugger(), req, cmd),
+        }
+    }
+}
+
+impl<R: BufRead + Debug, W: Write + Debug> Frontend for Dap<R, W> {
+    fn stopped(&self, stop: &Stop, paused: &mut Paused<'_>) -> Result<Resume> {
+        let (reason, text) = match &stop.reason {
+            StopReason::Entry => ("entry", None),
+            StopReason::Step => ("step", None),
+            StopReason::Breakpoint => ("breakpoint", None),
+            StopReason::Error(msg) => ("exception", Some(msg)),
+        };
+        let body = json!({
+            "reason": reason,
+            "text": text,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        self.event("stopped", &body)?;
+        self.scopes.borrow_mut().clear();
+        while let Some(req) = self.request()? {
+            let cmd = req["command"].as_str().unwrap_or_default();
+            let resume = match cmd {
+                "continue" => Resume::Continue,
+                "next" => Resume::StepOver,
+                "stepIn" => Resume::StepIn,
+                "stepOut" => Resume::StepOut,
+                "disconnect" | "terminate" => {
+                    self.disconnected.set(true);
+                    Resume::Terminate
+                }
+                _ => {
+                    let res = self.inspect(paused, &req, cmd);
+                    self.respond(&req, res)?;
+                    continue;
+                }
+            };
+            let body = (resume == Resume::Continue).then(|| json!({ "allThreadsContinued": true }));
+            self.respond(&req, Ok(body.unwrap_or_default()))?;
+            return Ok(resume);
+        }
+        Ok(Resume::Terminate)
+    }
+
+    fn output(&self, text: &str) {
+        self.print("stdout", text);
+    }
+}
@@ -0,0 +1,440 @@
+#![cfg(test)]
+
+use std::io::Cursor;
+
+use indoc::indoc;
+use pretty_assertions::assert_eq;
+use serde_json::{json, Value};
+
+use super::*;
+use crate::lsp::read_message;
+
+const SRC: &str = indoc! {"
+    fun add(a, b) {
+      var sum = a + b;
+      return sum;
+    }
+    var x = add(1, 2);
+    print x;
+    {
+      var y = x * 2;
+      print y;
+    }
+    print nil + y;
+"};
+
+/// Runs `src` as `main.lox` in the console debugger with the given commands,
+/// returning the transcript of the session.
+fn console(src: &str, cmds: &str) -> String {
+    let console = Rc::new(Console::new(Cursor::new(cmds.to_owned()), vec![]));
+    let debugger = Debugger::new(Rc::clone(&console) as _);
+    debugger.stop_on_entry();
+    let err = debug_str("main.lox", src, debugger, |_, _| ());
+    let mut res = String::from_utf8(console.output.borrow().clone()).unwrap();
+    res.extend(err);
+    res.replace("(dbg) ", "> ")
+}
+
+#[test]
+fn step() {
+    let cmds = "s\ns\n\nn\nn\nn\nn\nq\n";
+    let expected = indoc! {"
+        stopped at main.lox:1:5
+           1 | fun add(a, b) {
+        > stopped at main.lox:5:5
+           5 | var x = add(1, 2);
+        > stopped at main.lox:2:7
+           2 | var sum = a + b;
+        > stopped at main.lox:3:3
+           3 | return sum;
+        > stopped at main.lox:6:1
+           6 | print x;
+        > 3
+        stopped at main.lox:8:7
+           8 | var y = x * 2;
+        > stopped at main.lox:9:3
+           9 | print y;
+        > 6
+        stopped at main.lox:11:1
+          11 | print nil + y;
+        > "};
+    assert_eq!(expected, console(SRC, cmds));
+}
+
+#[test]
+fn step_out() {
+    let cmds = "s\ns\ns\no\nq\n";
+    let got = console(SRC, cmds);
+    assert!(got.ends_with("> stopped at main.lox:6:1\n   6 | print x;\n> "));
+}
+
+#[test]
+fn breakpoints() {
+    let cmds = "b 3\nb 9\nb main.lox:9\nb x\nb\nd 3\nd 3\nc\nenv\ng\nbt\nc\n";
+    let expected = indoc! {"
+        stopped at main.lox:1:5
+           1 | fun add(a, b) {
+        > breakpoint set at main.lox:3
+        > breakpoint set at main.lox:9
+        > breakpoint already set at main.lox:9
+        > expected `[FILE:]LINE`, found `x`
+        > main.lox:3
+        main.lox:9
+        > breakpoint removed at main.lox:3
+        > no breakpoint at main.lox:3
+        > 3
+        stopped at a breakpoint at main.lox:9:3
+           9 | print y;
+        > Locals:
+          y = 6
+        > Globals:
+          add = <fun: add@native>
+          x = 3
+        > #0 main.lox:9:3, in <script>
+        > 6
+        stopped on a runtime error: identifier `y` is undefined at main.lox:11:13
+          11 | print nil + y;
+        > "};
+    let got = console(SRC, cmds);
+    assert!(got.starts_with(expected), "{got}");
+}
+
+#[test]
+fn inspect_call() {
+    let cmds = "b 3\nc\nenv\np a + sum * 10\np b +\np undefined\nbt\nq\n";
+    let expected = indoc! {"
+        stopped at main.lox:1:5
+           1 | fun add(a, b) {
+        > breakpoint set at main.lox:3
+        > stopped at a breakpoint at main.lox:3:3
+           3 | return sum;
+        > Locals:
+          a = 1
+          b = 2
+          sum = 3
+        > 31
+        > Error[E0101]: unexpected end of input"};
+    let got = console(SRC, cmds);
+    assert!(got.starts_with(expected), "{got}");
+    assert!(got.contains("identifier `undefined` is undefined"), "{got}");
+    assert!(
+        got.ends_with("> #0 main.lox:3:3, in add\n#1 main.lox:5:17, in <script>\n> "),
+        "{got}"
+    );
+}
+
+#[test]
+fn runtime_errors() {
+    // The error is reported after the debugger has stopped on it.
+    let got = console(SRC, "c\nc\n");
+    let expected = indoc! {"
+          11 | print nil + y;
+        > Runtime Error[E0301]: identifier `y` is undefined
+    "};
+    assert!(got.contains(expected), "{got}");
+
+    // Errors caught by the program itself also stop the debugger.
+    let src = indoc! {r#"
+        try { throw "oops"; } catch (e) { print e; }
+    "#};
+    let got = console(src, "c\nc\n");
+    assert!(
+        got.contains("stopped on a runtime error: uncaught exception `\"oops\"`"),
+        "{got}"
+    );
+    assert!(got.ends_with("> \"oops\"\n"), "{got}");
+
+    // ... unless turned off.
+    let expected = indoc! {r#"
+        stopped at main.lox:1:1
+           1 | try { throw "oops"; } catch (e) { print e; }
+        > > "oops"
+    "#};
+    assert_eq!(expected, console(src, "catch off\nc\n"));
+}
+
+#[test]
+fn quit() {
+    // Quitting is not reported as an error, and neither is reaching the end
+    // of the commands.
+    for cmds in ["q\n", ""] {
+        let got = console(SRC, cmds);
+        assert_eq!("stopped at main.lox:1:5\n   1 | fun add(a, b) {\n> ", got);
+    }
+}
+
+/// A shared buffer collecting the output of a DAP session.
+#[derive(Debug, Clone, Default)]
+struct Sink(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs a DAP session with the given requests, returning the messages sent
+/// by the adapter.
+fn dap(reqs: &[Value]) -> Vec<Value> {
+    let input = (reqs.iter().enumerate())
+        .map(|(i, it)| {
+            let mut it = it.clone();
+            it["seq"] = json!(i + 1);
+            it["type"] = json!("request");
+            let it = it.to_string();
+            format!("Content-Length: {}\r\n\r\n{it}", it.len())
+        })
+        .join("");
+    let sink = Sink::default();
+    dap::serve(Cursor::new(input), sink.clone()).unwrap();
+    let output = sink.0.borrow();
+    let mut output = &output[..];
+    let mut msgs = vec![];
+    while let Some(msg) = read_message(&mut output).unwrap() {
+        msgs.push(serde_json::from_str(&msg).unwrap());
+    }
+    msgs
+}
+
+fn request(command: &str, arguments: &Value) -> Value {
+    json!({ "command": command, "arguments": arguments })
+}
+
+/// Summarizes the given DAP messages, one per line.
+fn summary(msgs: &[Value]) -> Vec<String> {
+    (msgs.iter())
+        .map(|it| match it["type"].as_str().unwrap() {
+            "event" => match it["event"].as_str().unwrap() {
+                "output" => format!("output {}", it["body"]["output"]),
+                "stopped" => format!("stopped {}", it["body"]["reason"]),
+                "exited" => format!("exited {}", it["body"]["exitCode"]),
+                event => event.to_owned(),
+            },
+            _ if it["success"] == false => format!("{} failed", it["command"].as_str().unwrap()),
+            _ => format!("{} {}", it["command"].as_str().unwrap(), it["body"]),
+        })
+        .collect()
+}
+
+/// Writes `SRC` into the `main.lox` of a new temporary directory, returning
+/// its path.
+fn main_file() -> String {
+    let dir = std::env::temp_dir().join(format!("dolores-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("main.lox").display().to_string();
+    std::fs::write(&path, SRC).unwrap();
+    path
+}
+
+/// The number of messages sent by the adapter in a session started by
+/// [`dap_stopped`], up to the first stop.
+const STOPPED: usize = 7;
+
+/// Runs a DAP session on the `main.lox` at `path`, which stops at the
+/// breakpoint on line 3 before the given requests are sent, and is
+/// disconnected after them.
+fn dap_stopped(path: &str, reqs: &[Value]) -> Vec<Value> {
+    let mut all = vec![
+        request("initialize", &json!({ "adapterID": "dolores" })),
+        request("launch", &json!({ "program": path })),
+        request(
+            "setBreakpoints",
+            &json!({ "source": { "path": path }, "breakpoints": [{ "line": 3 }] }),
+        ),
+        request("setExceptionBreakpoints", &json!({ "filters": [] })),
+        request("configurationDone", &json!({})),
+    ];
+    all.extend_from_slice(reqs);
+    all.push(request("disconnect", &json!({})));
+    dap(&all)
+}
+
+#[test]
+fn dap_launch() {
+    let res = dap_stopped(&main_file(), &[]);
+    let expected = vec![
+        format!("initialize {}", res[0]["body"]),
+        "initialized".into(),
+        "launch null".into(),
+        format!(
+            "setBreakpoints {}",
+            json!({ "breakpoints": [{ "verified": true, "line": 3 }] })
+        ),
+        "setExceptionBreakpoints null".into(),
+        "configurationDone null".into(),
+        "stopped \"breakpoint\"".into(),
+        "disconnect null".into(),
+    ];
+    assert_eq!(expected, summary(&res));
+    let caps = &res[0]["body"];
+    assert_eq!(json!(true), caps["supportsConfigurationDoneRequest"]);
+}
+
+#[test]
+fn dap_inspect() {
+    let path = main_file();
+    let res = dap_stopped(
+        &path,
+        &[
+            request("threads", &json!({})),
+            request("stackTrace", &json!({ "threadId": 1 })),
+            request("scopes", &json!({ "frameId": 0 })),
+            request("variables", &json!({ "variablesReference": 1 })),
+            request("scopes", &json!({ "frameId": 1 })),
+            request("variables", &json!({ "variablesReference": 3 })),
+        ],
+    );
+    let frames = json!([
+        {
+            "id": 0,
+            "name": "add",
+            "line": 3,
+            "column": 3,
+            "source": { "name": "main.lox", "path": path },
+        },
+        {
+            "id": 1,
+            "name": "<script>",
+            "line": 5,
+            "column": 17,
+            "source": { "name": "main.lox", "path": path },
+        },
+    ]);
+    let expected = vec![
+        format!(
+            "threads {}",
+            json!({ "threads": [{ "id": 1, "name": "main" }] })
+        ),
+        format!(
+            "stackTrace {}",
+            json!({ "stackFrames": frames, "totalFrames": 2 })
+        ),
+        format!(
+            "scopes {}",
+            json!({ "scopes": [
+                { "name": "Locals", "variablesReference": 1, "expensive": false },
+                { "name": "Globals", "variablesReference": 2, "expensive": false },
+            ] })
+        ),
+        format!(
+            "variables {}",
+            json!({ "variables": [
+                { "name": "a", "value": "1", "variablesReference": 0 },
+                { "name": "b", "value": "2", "variablesReference": 0 },
+                { "name": "sum", "value": "3", "variablesReference": 0 },
+            ] })
+        ),
+        format!(
+            "scopes {}",
+            json!({ "scopes": [
+                { "name": "Globals", "variablesReference": 3, "expensive": false },
+            ] })
+        ),
+        format!(
+            "variables {}",
+            json!({ "variables": [
+                { "name": "add", "value": "<fun: add@native>", "variablesReference": 0 },
+            ] })
+        ),
+        "disconnect null".into(),
+    ];
+    assert_eq!(expected, summary(&res[STOPPED..]));
+}
+
+#[test]
+fn dap_evaluate() {
+    let res = dap_stopped(
+        &main_file(),
+        &[
+            request(
+                "evaluate",
+                &json!({ "expression": "sum * 2", "frameId": 0 }),
+            ),
+            request("evaluate", &json!({ "expression": "sum *", "frameId": 0 })),
+        ],
+    );
+    let expected = vec![
+        format!(
+            "evaluate {}",
+            json!({ "result": "6", "variablesReference": 0 })
+        ),
+        "evaluate failed".into(),
+        "disconnect null".into(),
+    ];
+    assert_eq!(expected, summary(&res[STOPPED..]));
+    assert!(res[STOPPED + 1]["message"]
+        .as_str()
+        .unwrap()
+        .contains("unexpected end of input"));
+}
+
+#[test]
+fn dap_step() {
+    let res = dap_stopped(
+        &main_file(),
+        &[
+            request("next", &json!({ "threadId": 1 })),
+            request("stepIn", &json!({ "threadId": 1 })),
+            request("continue", &json!({ "threadId": 1 })),
+        ],
+    );
+    let error = &res[STOPPED + 7]["body"]["output"];
+    let expected = vec![
+        "next null".into(),
+        "stopped \"step\"".into(),
+        "stepIn null".into(),
+        "output \"3\\n\"".into(),
+        "stopped \"step\"".into(),
+        format!("continue {}", json!({ "allThreadsContinued": true })),
+        "output \"6\\n\"".into(),
+        format!("output {error}"),
+        "exited 1".into(),
+        "terminated".into(),
+        "disconnect null".into(),
+    ];
+    assert_eq!(expected, summary(&res[STOPPED..]));
+    assert!(error
+        .as_str()
+        .unwrap()
+        .contains("identifier `y` is undefined"));
+}
+
+#[test]
+fn dap_disconnect() {
+    let path = main_file();
+
+    // Disconnecting while stopped terminates the program silently.
+    let res = dap(&[
+        request("initialize", &json!({})),
+        request("configurationDone", &json!({})),
+        request("launch", &json!({ "program": path, "stopOnEntry": true })),
+        request("disconnect", &json!({})),
+    ]);
+    let got = summary(&res);
+    let expected = vec![
+        format!("initialize {}", res[0]["body"]),
+        "initialized".into(),
+        "configurationDone null".into(),
+        "launch null".into(),
+        "stopped \"entry\"".into(),
+        "disconnect null".into(),
+    ];
+    assert_eq!(expected, got);
+
+    // A missing program is reported as it exits.
+    let nope = Path::new(&path).with_file_name("nope.lox");
+    let res = dap(&[
+        request("launch", &json!({})),
+        request("launch", &json!({ "program": nope })),
+        request("configurationDone", &json!({})),
+        request("disconnect", &json!({})),
+    ]);
+    let got = summary(&res);
+    assert_eq!("launch failed", got[0]);
+    assert!(got[3].starts_with("output \"failed to read"), "{}", got[3]);
+    assert_eq!(vec!["exited 1", "terminated", "disconnect null"], got[4..]);
+}
@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Result};
+
+use crate::{
+    error::{render_error, Code},
+    interpreter::{Arity, Env, Interpreter, Object},
+    lexer::Lexer,
+    parser::Parser,
+    report,
+    source::Span,
+};
+
+/// A Lox interpreter instance to be embedded in Rust applications.
+///
+/// All sources evaluated by the same `Engine` share the same global
+/// environment, just like in a REPL session.
+///
+/// ```
+/// use dolores::{Engine, Object};
+///
+/// let mut engine = Engine::new();
+/// engine.run("fun add(a, b) { return a + b; }").unwrap();
+/// engine.set_global("base", 40.);
+/// let res = engine.eval("add(base, 2)").unwrap();
+/// assert_eq!(42., f64::try_from(res).unwrap());
+/// let sum = engine.call("add", [Object::from(1.5), Object::from(2.)]);
+/// assert_eq!(Object::Number(3.5), sum.unwrap());
+/// ```
+#[derive(Debug, Default)]
+pub struct Engine {
+    interpreter: Interpreter,
+}
+
+impl Engine {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs a Lox program, i.e. a sequence of declarations.
+    pub fn run(&mut self, src: &str) -> Result<()> {
+        let stmts = Parser::new(self.lex(src)).parse()?;
+        self.interpreter.resolve_stmts(stmts.clone())?;
+        let res = self.interpreter.exec_stmts(stmts).map(|_| ());
+        self.interpreter.thrown.clear();
+        res
+    }
+
+    /// Evaluates a single Lox expression, returning its value.
+    pub fn eval(&mut self, src: &str) -> Result<Object> {
+        let expr = Parser::new(self.lex(src)).parse_expr()?;
+        self.interpreter.resolve_expr(expr.clone())?;
+        let res = self.interpreter.eval(expr);
+        self.interpreter.thrown.clear();
+        res
+    }
+
+    fn lex<'s>(&mut self, src: &'s str) -> Lexer<'s> {
+        let offset = self.interpreter.sources.add("<input>", src);
+        Lexer::with_offset(src, offset)
+    }
+
+    /// Renders an error returned by this engine, showing the offending source
+    /// lines if the error is a [`Diagnostic`](crate::Diagnostic).
+    #[must_use]
+    pub fn render(&self, err: &anyhow::Error) -> String {
+        render_error(err, &self.interpreter.sources)
+    }
+
+    /// Returns the value of the global variable `name`, if defined.
+    #[must_use]
+    pub fn global(&self, name: &str) -> Option<Object> {
+        Env::lookup(&self.interpreter.globals, name)
+    }
+
+    /// Defines or overwrites the global variable `name`.
+    pub fn set_global(&mut self, name: &str, val: impl Into<Object>) {
+        self.interpreter
+            .globals
+            .borrow_mut()
+            .insert_val(name, val.into());
+    }
+
+    /// Registers a host function as the global variable `name`, callable from
+    /// Lox with the given number of arguments.
+    ///
+    /// The number of arguments is checked at the call site, so `fun` can rely
+    /// on it. Since `fun` is opaque to the garbage collector, it should not
+    /// capture any [`Object`].
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        fun: impl Fn(Vec<Object>) -> Result<Object> + 'static,
+    ) {
+        self.interpreter.define_foreign(name, arity, fun);
+    }
+
+    /// Calls the global function (or class) `name` with the given arguments.
+    pub fn call(&mut self, name: &str, args: impl IntoIterator<Item = Object>) -> Result<Object> {
+        let callee = self.global(name).ok_or_else(|| {
+            anyhow!(report!(
+                Code::UndefinedVariable,
+                Span::default(),
+                "while calling a global function",
+                "identifier `{name}` is undefined",
+            ))
+        })?;
+        self.call_value(&callee, args)
+    }
+
+    /// Calls the given callable object with the given arguments.
+    pub fn call_value(
+        &mut self,
+        callee: &Object,
+        args: impl IntoIterator<Item = Object>,
+    ) -> Result<Object> {
+        self.interpreter
+            .call(callee, args.into_iter().collect(), Span::default())
+    }
+}
@@ -1,55 +1,575 @@
-use std::fmt::Display;
+use std::fmt::{Display, Write};
 
-pub(crate) fn report(pos: (usize, usize), ctx: &str, msg: impl Display) -> String {
-    format!("[L{}:{}] Error {}: {}", pos.0, pos.1, ctx, msg)
+use itertools::Itertools;
+use unicode_width::UnicodeWidthStr;
+
+use crate::source::{SourceMap, Span};
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
 }
 
-pub(crate) fn runtime_report(pos: (usize, usize), ctx: &str, msg: impl Display) -> String {
-    format!("[L{}:{}] Runtime Error {}: {}", pos.0, pos.1, ctx, msg)
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Warning => write!(f, "Warning"),
+        }
+    }
 }
 
-pub(crate) fn semantic_report(pos: (usize, usize), ctx: &str, msg: impl Display) -> String {
-    format!("[L{}:{}] Semantic Error {}: {}", pos.0, pos.1, ctx, msg)
+/// The kind of a [`Diagnostic`].
+///
+/// Codes starting with `E01` are reported by the lexer and the parser, `E02`
+/// by the resolver and the bytecode compiler, and `E03` at runtime. Codes
+/// starting with `W` are for warnings, which are reported by the resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    /// A piece of source code that doesn't form a token.
+    InvalidToken,
+    /// A token that doesn't fit in the grammar.
+    UnexpectedToken,
+    /// A binary operator used without its LHS.
+    MissingOperand,
+    /// An assignment to something that is not a variable, a property or an
+    /// element.
+    InvalidAssignTarget,
+    /// Too many parameters or arguments in a function.
+    TooManyParams,
+    /// A malformed Number literal.
+    InvalidNumber,
+    /// A malformed escape sequence in a String literal.
+    InvalidEscape,
+
+    /// `this` used outside of any class.
+    ThisOutOfClass,
+    /// `super` used outside of any subclass.
+    SuperOutOfSubclass,
+    /// `break` or `continue` used outside of any loop.
+    JumpOutOfLoop,
+    /// `return` used outside of any function.
+    ReturnOutOfFun,
+    /// A value returned from an initializer.
+    ReturnInInit,
+    /// A local variable read in its own initializer.
+    SelfInit,
+    /// A class inheriting from itself.
+    SelfInherit,
+    /// Too many local variables in a function.
+    TooManyLocals,
+    /// `break` or `continue` targeting a label of no enclosing loop.
+    UndefinedLabel,
+
+    /// A variable used without being defined.
+    UndefinedVariable,
+    /// A property not found on an object.
+    UndefinedProperty,
+    /// An operation applied to objects of the wrong type.
+    TypeMismatch,
+    /// A function called with the wrong number of arguments.
+    ArityMismatch,
+    /// An index out of bounds, or a key not found.
+    BadIndex,
+    /// An error raised by a foreign function.
+    Foreign,
+    /// Too many nested function calls.
+    StackOverflow,
+    /// An integer operation overflowing the range of `Int`.
+    IntOverflow,
+    /// An `Int` divided by zero.
+    DivisionByZero,
+    /// A module that cannot be found or read.
+    ImportFailed,
+    /// A module importing itself, directly or not.
+    CyclicImport,
+    /// A value thrown by `throw` and never caught.
+    UncaughtException,
+
+    /// A local variable that is never used.
+    UnusedVariable,
+    /// A function parameter that is never used.
+    UnusedParam,
+    /// A local variable shadowing another one in an enclosing scope.
+    ShadowedLocal,
+    /// Statements following a `return`, `throw`, `break` or `continue` in a
+    /// block.
+    UnreachableCode,
+    /// A local variable declared again in the same scope.
+    RedeclaredLocal,
 }
 
-#[macro_export]
-macro_rules! bail {
-    ($pos:expr, $ctx:expr, $msg:expr $(,)?) => {
-        anyhow::bail!("{}", $crate::error::report($pos, $ctx, $msg))
-    };
-    ($pos:expr, $ctx:expr, $msg:expr, $( $arg:expr ),+ $(,)?) => {
-        anyhow::bail!("{}", $crate::error::report(
-            $pos,
-            $ctx,
-            format!($msg, $( $arg ),+),
-        ))
-    };
+impl Code {
+    /// Returns the compilation phase reporting this code, as prefixed to the
+    /// severity in reports.
+    #[must_use]
+    fn phase(self) -> &'static str {
+        match self.as_str().get(..3) {
+            Some("E02") => "Semantic ",
+            Some("E03") => "Runtime ",
+            _ => "",
+        }
+    }
+
+    /// Returns whether this code is for a runtime error, which can be caught
+    /// by a `try` statement.
+    #[must_use]
+    pub(crate) fn is_runtime(self) -> bool {
+        self.as_str().starts_with("E03")
+    }
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::InvalidToken => "E0100",
+            Code::UnexpectedToken => "E0101",
+            Code::MissingOperand => "E0102",
+            Code::InvalidAssignTarget => "E0103",
+            Code::TooManyParams => "E0104",
+            Code::InvalidNumber => "E0105",
+            Code::InvalidEscape => "E0106",
+            Code::ThisOutOfClass => "E0201",
+            Code::SuperOutOfSubclass => "E0202",
+            Code::JumpOutOfLoop => "E0203",
+            Code::ReturnOutOfFun => "E0204",
+            Code::ReturnInInit => "E0205",
+            Code::SelfInit => "E0206",
+            Code::SelfInherit => "E0207",
+            Code::TooManyLocals => "E0208",
+            Code::UndefinedLabel => "E0209",
+            Code::UndefinedVariable => "E0301",
+            Code::UndefinedProperty => "E0302",
+            Code::TypeMismatch => "E0303",
+            Code::ArityMismatch => "E0304",
+            Code::BadIndex => "E0305",
+            Code::Foreign => "E0306",
+            Code::StackOverflow => "E0307",
+            Code::IntOverflow => "E0308",
+            Code::DivisionByZero => "E0309",
+            Code::ImportFailed => "E0310",
+            Code::CyclicImport => "E0311",
+            Code::UncaughtException => "E0312",
+            Code::UnusedVariable => "W0001",
+            Code::UnusedParam => "W0002",
+            Code::ShadowedLocal => "W0003",
+            Code::UnreachableCode => "W0004",
+            Code::RedeclaredLocal => "W0005",
+        }
+    }
+}
+
+impl Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A message attached to a piece of source code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub msg: String,
+}
+
+/// A function call in progress, as recorded in a traceback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallFrame {
+    /// The name of the callee, or `<lambda>` for anonymous functions.
+    pub name: String,
+    /// The span of the call expression.
+    pub call_site: Span,
+    /// The span of the name of the callee in its definition.
+    pub defn: Span,
+}
+
+/// A problem found in a Lox program, together with where it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    /// What was being done when the problem was found, e.g. `while parsing a
+    /// Call expression`.
+    pub ctx: String,
+    pub msg: String,
+    /// The span where the problem was found.
+    pub primary: Span,
+    /// Other spans related to the problem.
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    /// The calls in progress when a runtime error was raised, innermost call
+    /// last.
+    pub traceback: Vec<CallFrame>,
+    /// The key of the value thrown if this is an uncaught exception, under
+    /// which the backend keeps the value until it is caught.
+    pub(crate) exception: Option<u64>,
+}
+
+impl Diagnostic {
+    #[must_use]
+    pub fn error(code: Code, span: Span, ctx: impl Display, msg: impl Display) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            ctx: ctx.to_string(),
+            msg: msg.to_string(),
+            primary: span,
+            secondary: vec![],
+            notes: vec![],
+            traceback: vec![],
+            exception: None,
+        }
+    }
+
+    #[must_use]
+    pub fn warning(code: Code, span: Span, ctx: impl Display, msg: impl Display) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(code, span, ctx, msg)
+        }
+    }
+
+    #[must_use]
+    pub fn with_label(mut self, span: Span, msg: impl Display) -> Self {
+        self.secondary.push(Label {
+            span,
+            msg: msg.to_string(),
+        });
+        self
+    }
+
+    #[must_use]
+    pub fn with_note(mut self, note: impl Display) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    fn heading(&self) -> String {
+        format!("{}{}[{}]", self.code.phase(), self.severity, self.code)
+    }
+
+    /// Renders this diagnostic with the offending source lines, whose spans
+    /// are looked up in `sources`.
+    #[must_use]
+    pub(crate) fn render(&self, sources: &SourceMap) -> String {
+        let mut res = self.render_traceback(sources);
+        let _ = writeln!(res, "{}: {}", self.heading(), self.msg);
+        let labels = std::iter::once((self.primary, self.ctx.as_str(), '^'))
+            .chain((self.secondary.iter()).map(|it| (it.span, it.msg.as_str(), '-')))
+            .filter_map(|(span, msg, mark)| {
+                let file = sources.file(span.start)?;
+                let (line, col) = file.pos(span.start);
+                Some((file, line, col, span, msg, mark))
+            })
+            .sorted_by_key(|it| it.3.start)
+            .collect_vec();
+        let Some(width) = labels.iter().map(|it| it.1.to_string().len()).max() else {
+            // The source is unknown, so only the context can be shown.
+            let _ = writeln!(res, "  = {}", self.ctx);
+            return res + &self.render_notes(1);
+        };
+        let gutter = " ".repeat(width);
+        let (mut last_file, mut last_line) = (None, None);
+        for &(file, line, col, span, msg, mark) in &labels {
+            if last_file != Some(file.start) {
+                last_file = Some(file.start);
+                // The header points at the primary label if it is in this file.
+                let (line, col) = (labels.iter())
+                    .find(|it| it.0.start == file.start && it.3 == self.primary)
+                    .map_or((line, col), |it| (it.1, it.2));
+                let _ = writeln!(res, "{gutter}--> {}:{line}:{col}", file.name);
+                let _ = writeln!(res, "{gutter} |");
+            }
+            let Some((text, line_start)) = file.line(line) else {
+                continue;
+            };
+            if last_line != Some((file.start, line)) {
+                let _ = writeln!(res, "{line:>width$} | {}", expand_tabs(text));
+                last_line = Some((file.start, line));
+            }
+            // Underline the span within the current line, using at least one mark.
+            let span_end = span.end.min(line_start + text.len()).max(span.start);
+            let indent = (text.get(..span.start - line_start)).map_or(col - 1, display_width);
+            let len = text
+                .get(span.start - line_start..span_end - line_start)
+                .map_or(0, display_width)
+                .max(1);
+            let marks = mark.to_string().repeat(len);
+            let _ = writeln!(res, "{gutter} | {}{marks} {msg}", " ".repeat(indent));
+        }
+        res + &self.render_notes(width)
+    }
+
+    /// Renders the traceback in the style of Python, innermost call last,
+    /// where each entry shows where the execution was in a function.
+    fn render_traceback(&self, sources: &SourceMap) -> String {
+        /// The number of times an entry is shown before the rest of its
+        /// consecutive repetitions are collapsed, e.g. in a deep recursion.
+        const MAX_REPEAT: usize = 3;
+
+        if self.traceback.is_empty() {
+            return String::new();
+        }
+        let pos = |span: Span| {
+            let file = sources.file(span.start)?;
+            let (line, col) = file.pos(span.start);
+            Some((file, line, col))
+        };
+        let callers = std::iter::once(("<script>", None))
+            .chain((self.traceback.iter()).map(|it| (it.name.as_str(), Some(it.defn))));
+        let sites = (self.traceback.iter())
+            .map(|it| it.call_site)
+            .chain(std::iter::once(self.primary));
+        let entries = callers.zip(sites).map(|((name, defn), site)| {
+            let Some((file, line, col)) = pos(site) else {
+                return format!("  <unknown>, in {name}\n");
+            };
+            let mut entry = format!("  {}:{line}:{col}, in {name}", file.name);
+            if let Some((file, line, col)) = defn.and_then(pos) {
+                let _ = write!(entry, " (defined at {}:{line}:{col})", file.name);
+            }
+            entry.push('\n');
+            if let Some((text, _)) = file.line(line) {
+                let _ = writeln!(entry, "    {}", text.trim());
+            }
+            entry
+        });
+        let mut res = "Traceback (most recent call last):\n".to_owned();
+        for (count, entry) in entries.dedup_with_count() {
+            res += &entry.repeat(count.min(MAX_REPEAT));
+            if count > MAX_REPEAT {
+                let more = count - MAX_REPEAT;
+                let _ = writeln!(res, "  [previous entry repeated {more} more times]");
+            }
+        }
+        res
+    }
+
+    /// Renders the notes aligned with the gutter of `width` columns.
+    fn render_notes(&self, width: usize) -> String {
+        let gutter = " ".repeat(width);
+        self.notes
+            .iter()
+            .map(|it| format!("{gutter} = note: {it}\n"))
+            .join("")
+    }
 }
 
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}: {}", self.heading(), self.ctx, self.msg)?;
+        self.notes
+            .iter()
+            .try_for_each(|it| write!(f, "\n  = note: {it}"))
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Several errors found in one pass, e.g. all the syntax errors in a file.
+#[derive(Debug)]
+pub struct Errors(pub Vec<anyhow::Error>);
+
+impl Errors {
+    /// Combines the given errors into one, which is an `Errors` only if there
+    /// are more than one of them.
+    #[must_use]
+    pub(crate) fn combine(mut errors: Vec<anyhow::Error>) -> anyhow::Error {
+        match errors.len() {
+            0 => unreachable!("no error to combine"),
+            1 => errors.pop().unwrap(),
+            _ => Self(errors).into(),
+        }
+    }
+}
+
+impl Display for Errors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.0.iter().map(|it| format!("{it:#}")).join("\n")
+        )
+    }
+}
+
+impl std::error::Error for Errors {}
+
+/// Attaches the calls in progress to the runtime error `e`, unless it has a
+/// traceback already, i.e. it has been raised in a deeper call.
+pub(crate) fn with_traceback(
+    mut e: anyhow::Error,
+    frames: impl FnOnce() -> Vec<CallFrame>,
+) -> anyhow::Error {
+    if let Some(diag) = e.downcast_mut::<Diagnostic>() {
+        if diag.traceback.is_empty() {
+            diag.traceback = frames();
+        }
+    }
+    e
+}
+
+/// The number of columns a tab is rendered with in source snippets.
+const TAB_WIDTH: usize = 4;
+
+/// Replaces the tabs in `text` with spaces, so that the marks below a source
+/// line can be aligned with it.
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+/// Returns the number of columns `text` takes in a terminal, where wide
+/// characters such as emojis take two.
+fn display_width(text: &str) -> usize {
+    expand_tabs(text).width()
+}
+
+/// Renders the error `e`, showing the source lines of its diagnostics if any.
+pub(crate) fn render_error(e: &anyhow::Error, sources: &SourceMap) -> String {
+    if let Some(Errors(errors)) = e.downcast_ref() {
+        let rendered = errors.iter().map(|it| render_error(it, sources)).join("\n");
+        return format!("{rendered}\nfound {} errors\n", errors.len());
+    }
+    let causes = e.chain().count();
+    e.chain()
+        .enumerate()
+        .map(|(i, cause)| {
+            // The outermost error might be a context, which is only accessible
+            // from `e` itself.
+            let diag = match i {
+                0 => e.downcast_ref::<Diagnostic>(),
+                _ => cause.downcast_ref::<Diagnostic>(),
+            };
+            let rendered = diag.map_or_else(|| format!("{cause}\n"), |it| it.render(sources));
+            if i + 1 < causes {
+                rendered + "Caused by:\n"
+            } else {
+                rendered
+            }
+        })
+        .join("")
+}
+
+/// Creates an error [`Diagnostic`] with the given code and span, where the
+/// message can be formatted in place.
 #[macro_export]
-macro_rules! runtime_bail {
-    ($pos:expr, $ctx:expr, $msg:expr $(,)?) => {
-        anyhow::bail!("{}", $crate::error::runtime_report($pos, $ctx, $msg))
+macro_rules! report {
+    ($code:expr, $span:expr, $ctx:expr, $msg:literal $(,)?) => {
+        $crate::error::Diagnostic::error($code, $span, $ctx, format!($msg))
     };
-    ($pos:expr, $ctx:expr, $msg:expr, $( $arg:expr ),+ $(,)?) => {
-        anyhow::bail!("{}", $crate::error::runtime_report(
-            $pos,
-            $ctx,
-            format!($msg, $( $arg ),+),
-        ))
+    ($code:expr, $span:expr, $ctx:expr, $msg:expr $(,)?) => {
+        $crate::error::Diagnostic::error($code, $span, $ctx, $msg)
+    };
+    ($code:expr, $span:expr, $ctx:expr, $msg:expr, $( $arg:expr ),+ $(,)?) => {
+        $crate::error::Diagnostic::error($code, $span, $ctx, format!($msg, $( $arg ),+))
     };
 }
 
+/// Returns early with an error [`Diagnostic`], see [`report!`].
 #[macro_export]
-macro_rules! semantic_bail {
-    ($pos:expr, $ctx:expr, $msg:expr $(,)?) => {
-        anyhow::bail!("{}", $crate::error::semantic_report($pos, $ctx, $msg))
-    };
-    ($pos:expr, $ctx:expr, $msg:expr, $( $arg:expr ),+ $(,)?) => {
-        anyhow::bail!("{}", $crate::error::semantic_report(
-            $pos,
-            $ctx,
-            format!($msg, $( $arg ),+),
-        ))
+macro_rules! bail {
+    ($code:expr, $span:expr, $ctx:expr, $( $msg:expr ),+ $(,)?) => {
+        return Err($crate::report!($code, $span, $ctx, $( $msg ),+).into())
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn render_labels() {
+        let mut sources = SourceMap::default();
+        let start = sources.add("main.lox", "var a = 1;\nprint a(2);");
+        let diag = report!(
+            Code::TypeMismatch,
+            Span::new(start + 17, start + 21),
+            "while evaluating a function Call expression",
+            "the object `{}` is not callable",
+            1,
+        )
+        .with_label(Span::new(start + 4, start + 5), "defined here")
+        .with_note("only functions and classes can be called");
+        assert_eq!(
+            indoc! {"
+                Runtime Error[E0303]: the object `1` is not callable
+                 --> main.lox:2:7
+                  |
+                1 | var a = 1;
+                  |     - defined here
+                2 | print a(2);
+                  |       ^^^^ while evaluating a function Call expression
+                  = note: only functions and classes can be called
+            "},
+            diag.render(&sources),
+        );
+        assert_eq!(
+            "Runtime Error[E0303] while evaluating a function Call expression: \
+                the object `1` is not callable\n  \
+                = note: only functions and classes can be called",
+            diag.to_string(),
+        );
+
+        // Tabs and wide characters before the span take more than one column.
+        let start = sources.add("lib.lox", "fun f(x) {\n\treturn \"🦀\" + x.y;\n}");
+        let diag = report!(
+            Code::UndefinedProperty,
+            Span::new(start + 30, start + 31),
+            "while evaluating a Get expression",
+            "property `y` is undefined",
+        );
+        assert_eq!(
+            indoc! {r#"
+                Runtime Error[E0302]: property `y` is undefined
+                 --> lib.lox:2:17
+                  |
+                2 |     return "🦀" + x.y;
+                  |                     ^ while evaluating a Get expression
+            "#},
+            diag.render(&sources),
+        );
+    }
+
+    #[test]
+    fn render_labels_across_files() {
+        let mut sources = SourceMap::default();
+        let lib = sources.add("lib.lox", &("\n".repeat(9) + "fun f() {}"));
+        let main = sources.add("main.lox", "import \"lib.lox\";\nf(1);");
+        let diag = report!(
+            Code::ArityMismatch,
+            Span::new(main + 18, main + 22),
+            "while evaluating a function Call expression",
+            "expected 0 arguments, got 1",
+        )
+        .with_label(Span::new(lib + 13, lib + 14), "defined here")
+        .with_note("remove the argument");
+        assert_eq!(
+            indoc! {"
+                Runtime Error[E0304]: expected 0 arguments, got 1
+                  --> lib.lox:10:5
+                   |
+                10 | fun f() {}
+                   |     - defined here
+                  --> main.lox:2:1
+                   |
+                 2 | f(1);
+                   | ^^^^ while evaluating a function Call expression
+                   = note: remove the argument
+            "},
+            diag.render(&sources),
+        );
+    }
+
+    #[test]
+    fn render_unknown_source() {
+        let diag = report!(Code::Foreign, Span::default(), "while calling", "oops");
+        assert_eq!(
+            "Runtime Error[E0306]: oops\n  = while calling\n",
+            diag.render(&SourceMap::default()),
+        );
+    }
+}
@@ -0,0 +1,498 @@
+mod doc;
+mod tests;
+
+use std::{
+    fs,
+    io::{self, Read},
+    mem,
+};
+
+use anyhow::{bail, Result};
+
+use self::doc::Doc;
+#[allow(clippy::enum_glob_use)]
+use crate::{
+    error::render_error,
+    lexer::{
+        Lexer, Token,
+        TokenType::{self, *},
+    },
+    parser::Parser,
+    source::SourceMap,
+};
+
+/// The options of the formatter.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Options {
+    /// The number of spaces per indentation level.
+    pub(crate) indent: usize,
+    /// The maximum width of a line, which is only exceeded when there is no
+    /// bracket or binary operator to break the line at.
+    pub(crate) width: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            indent: 4,
+            width: 100,
+        }
+    }
+}
+
+/// Formats the Lox source `src`, which starts at `offset` in its `SourceMap`.
+///
+/// The source is parsed first, so that only valid code gets formatted. The
+/// layout is then computed from the tokens rather than the syntax tree, so
+/// that the comments, the spelling of literals and the syntactic sugar like
+/// `for` loops and compound assignments are kept as they are.
+pub(crate) fn format(src: &str, offset: usize, opts: Options) -> Result<String> {
+    Parser::new(Lexer::with_offset(src, offset)).parse()?;
+    let mut formatter = Formatter::new(src, offset);
+    Lexer::with_offset(src, offset).for_each(|it| formatter.token(&it));
+    Ok(doc::render(&formatter.finish(), opts))
+}
+
+/// Formats the given files in place, or the standard input to the standard
+/// output if there is none.
+///
+/// In `check` mode, the files are left untouched, and an error is returned if
+/// any of them is not formatted.
+pub(crate) fn fmt_files(files: &[String], check: bool, opts: Options) -> Result<()> {
+    let mut sources = SourceMap::default();
+    let (mut unformatted, mut failed) = (0, 0);
+    let inputs: Vec<_> = if files.is_empty() {
+        let mut src = String::new();
+        io::stdin().read_to_string(&mut src)?;
+        vec![(None, src)]
+    } else {
+        (files.iter())
+            .map(|it| Ok((Some(it), fs::read_to_string(it)?)))
+            .collect::<Result<_>>()?
+    };
+    for (file, src) in inputs {
+        let name = file.map_or("<stdin>", |it| it);
+        let offset = sources.add(name, &src);
+        let formatted = match format(&src, offset, opts) {
+            Ok(it) => it,
+            Err(e) => {
+                print!("{}", render_error(&e, &sources));
+                failed += 1;
+                continue;
+            }
+        };
+        match file {
+            _ if check && formatted != src => {
+                println!("{name}: not formatted");
+                unformatted += 1;
+            }
+            _ if check => (),
+            Some(file) if formatted != src => fs::write(file, formatted)?,
+            Some(_) => (),
+            None => print!("{formatted}"),
+        }
+    }
+    if failed > 0 {
+        bail!("failed to format {failed} file(s)");
+    }
+    if unformatted > 0 {
+        bail!("found {unformatted} unformatted file(s)");
+    }
+    Ok(())
+}
+
+/// A separator to be put before the next token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sep {
+    None,
+    Space,
+    SoftLine,
+    Line,
+    /// A line break, unless the next token continues the statement ended by a
+    /// block, e.g. `else`.
+    StmtEnd,
+    Newline,
+}
+
+/// The kind of a bracket pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// The top level or a block of statements, which might be the body of a
+    /// lambda expression.
+    Block {
+        lambda: bool,
+    },
+    /// Parentheses following the keyword `kw`, e.g. the condition of an `if`.
+    Paren {
+        kw: Option<TokenType>,
+    },
+    Bracket,
+    Map,
+}
+
+/// The docs between a pair of brackets.
+#[derive(Debug)]
+struct Frame {
+    kind: Kind,
+    docs: Vec<Doc>,
+    /// If a comment in this frame forces it to be broken.
+    broken: bool,
+    /// The number of `?`s waiting for their `:`s.
+    ternaries: usize,
+    /// If nothing but separators has been put in this frame.
+    empty: bool,
+    /// The index in `docs` where the current expression starts.
+    expr_start: Option<usize>,
+    /// The line breaks before the binary operators of the current expression,
+    /// as their indices in `docs` and the precedences of the operators.
+    ops: Vec<(usize, u8)>,
+}
+
+impl Frame {
+    fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            docs: vec![],
+            broken: false,
+            ternaries: 0,
+            empty: true,
+            expr_start: None,
+            ops: vec![],
+        }
+    }
+
+    fn is_block(&self) -> bool {
+        matches!(self.kind, Kind::Block { .. })
+    }
+
+    /// Starts an expression at the next doc, unless one has been started.
+    fn start_expr(&mut self) {
+        self.expr_start.get_or_insert(self.docs.len());
+    }
+
+    /// Ends the current expression, which is grouped if it has binary
+    /// operators, so that it is broken before the loosest ones if it doesn't
+    /// fit in the line width.
+    fn end_expr(&mut self) {
+        let start = self.expr_start.take();
+        let ops = mem::take(&mut self.ops);
+        let (Some(start), Some(loosest)) = (start, ops.iter().map(|it| it.1).min()) else {
+            return;
+        };
+        for (i, prec) in ops {
+            if prec > loosest {
+                self.docs[i] = Doc::text(" ");
+            }
+        }
+        let docs = self.docs.split_off(start);
+        self.docs.push(Doc::Group {
+            docs: vec![Doc::Nest(docs)],
+            broken: false,
+        });
+    }
+}
+
+/// Returns the precedence of `ty` as a binary operator if it is one, where
+/// the loosest operator comes first.
+fn binary_prec(ty: TokenType) -> Option<u8> {
+    Some(match ty {
+        QuestionQuestion => 0,
+        Or => 1,
+        And => 2,
+        EqualEqual | BangEqual => 3,
+        Less | LessEqual | Greater | GreaterEqual => 4,
+        Pipe => 5,
+        Caret => 6,
+        Amp => 7,
+        LessLess | GreaterGreater => 8,
+        Plus | Minus => 9,
+        Star | Slash | Percent => 10,
+        StarStar => 11,
+        _ => return None,
+    })
+}
+
+/// The previous non-trivia token.
+#[derive(Debug, Clone, Copy)]
+struct Prev {
+    ty: TokenType,
+    /// If this token ends an operand, after which `(` begins a call, `[` an
+    /// index and `-` is binary.
+    operand: bool,
+    /// If this token is a prefix operator.
+    unary: bool,
+    /// The kind of the brackets opened or closed by this token.
+    bracket: Option<Kind>,
+}
+
+/// Builds up the docs to be rendered from a stream of tokens.
+struct Formatter<'s> {
+    src: &'s str,
+    /// The offset of `src` in its `SourceMap`.
+    offset: usize,
+    frames: Vec<Frame>,
+    sep: Sep,
+    prev: Option<Prev>,
+    /// The end of the previous token, comments included.
+    prev_end: Option<usize>,
+    /// If the previous token is a block comment.
+    after_comment: bool,
+    /// If a class declaration is being put before its body, where `<` is not
+    /// a binary operator.
+    class_header: bool,
+}
+
+impl<'s> Formatter<'s> {
+    fn new(src: &'s str, offset: usize) -> Self {
+        Self {
+            src,
+            offset,
+            frames: vec![Frame::new(Kind::Block { lambda: false })],
+            sep: Sep::None,
+            prev: None,
+            prev_end: None,
+            after_comment: false,
+            class_header: false,
+        }
+    }
+
+    fn finish(mut self) -> Vec<Doc> {
+        let mut frame = self.frames.pop().unwrap();
+        frame.end_expr();
+        frame.docs
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Returns the number of line breaks between the previous token and `t`.
+    fn newlines_before(&self, t: &Token) -> usize {
+        self.prev_end.map_or(0, |end| {
+            self.src[end - self.offset..t.span.start - self.offset]
+                .matches('\n')
+                .count()
+        })
+    }
+
+    fn token(&mut self, t: &Token) {
+        let newlines = self.newlines_before(t);
+        if t.ty.is_trivia() {
+            self.comment(t, newlines);
+        } else {
+            match t.ty {
+                LeftParen | LeftBracket | LeftBrace => self.open(t, newlines),
+                RightParen | RightBracket | RightBrace => self.close(t),
+                _ => self.plain(t, newlines),
+            }
+            self.prev_end = Some(t.span.end);
+            self.after_comment = false;
+        }
+    }
+
+    /// Returns if `t` should be separated from the previous token by a space
+    /// when they are on the same line.
+    fn spaced(&self, t: &Token) -> bool {
+        let Some(prev) = self.prev else {
+            return false;
+        };
+        if prev.unary
+            || matches!(prev.ty, LeftParen | LeftBracket | Dot | QuestionDot)
+            || prev.ty == LeftBrace && prev.bracket == Some(Kind::Map)
+        {
+            return false;
+        }
+        match t.ty {
+            Comma | Semicolon | Dot | QuestionDot => false,
+            LeftParen | LeftBracket => !prev.operand,
+            // Labels and Map keys are followed by `:` directly.
+            Colon => self.frames.last().unwrap().ternaries > 0,
+            _ => true,
+        }
+    }
+
+    /// Puts the pending separator before `t`, which follows `newlines` line
+    /// breaks in the source.
+    fn put_sep(&mut self, t: &Token, newlines: usize) {
+        let sep = match mem::replace(&mut self.sep, Sep::None) {
+            // A token following a block comment stays on its line in the source.
+            _ if self.after_comment && newlines > 0 => {
+                self.frame().broken = true;
+                Sep::Newline
+            }
+            Sep::StmtEnd if matches!(t.ty, Else | Catch | Finally) => Sep::Space,
+            Sep::StmtEnd => Sep::Newline,
+            // Empty clauses in the header of a `for` loop are kept tight.
+            Sep::SoftLine | Sep::Line if t.ty == Semicolon => Sep::None,
+            Sep::SoftLine if self.after_comment => Sep::Line,
+            Sep::None | Sep::Space if self.spaced(t) || self.after_comment => Sep::Space,
+            Sep::None | Sep::Space => Sep::None,
+            sep => sep,
+        };
+        self.put(sep, newlines);
+    }
+
+    fn put(&mut self, sep: Sep, newlines: usize) {
+        let frame = self.frame();
+        let doc = match sep {
+            Sep::None => return,
+            Sep::Space => Doc::text(" "),
+            Sep::SoftLine => Doc::SoftLine,
+            Sep::Line => Doc::Line,
+            // At most one empty line is kept between statements.
+            Sep::StmtEnd | Sep::Newline if newlines > 1 && frame.is_block() && !frame.empty => {
+                Doc::BlankLine
+            }
+            Sep::StmtEnd | Sep::Newline => Doc::HardLine,
+        };
+        frame.docs.push(doc);
+    }
+
+    fn comment(&mut self, t: &Token, newlines: usize) {
+        let text = t.lexeme.trim_end();
+        if self.prev_end.is_some() && newlines == 0 {
+            // A trailing comment stays on the line of the previous token.
+            self.frame().docs.push(Doc::text(" "));
+        } else {
+            self.put(Sep::Newline, newlines);
+            self.sep = Sep::None;
+            self.frame().broken = true;
+        }
+        let frame = self.frame();
+        frame.docs.push(Doc::text(text));
+        frame.empty = false;
+        if t.ty == SingleLineComment {
+            frame.broken = true;
+            self.sep = Sep::Newline;
+        }
+        self.after_comment = t.ty == MultiLineComment;
+        self.prev_end = Some(t.span.start + text.len());
+    }
+
+    fn open(&mut self, t: &Token, newlines: usize) {
+        let prev = self.prev;
+        let kind = match t.ty {
+            LeftParen => Kind::Paren {
+                kw: prev
+                    .map(|it| it.ty)
+                    .filter(|ty| matches!(ty, If | While | For | Catch | Fun)),
+            },
+            LeftBracket => Kind::Bracket,
+            // At the beginning of a statement, `{` is always a Block.
+            _ => match prev {
+                None => Kind::Block { lambda: false },
+                Some(prev) => match prev.ty {
+                    RightParen => Kind::Block {
+                        lambda: prev.bracket == Some(Kind::Paren { kw: Some(Fun) }),
+                    },
+                    Else | Try | Finally | Identifier | Semicolon => Kind::Block { lambda: false },
+                    LeftBrace | RightBrace if matches!(prev.bracket, Some(Kind::Block { .. })) => {
+                        Kind::Block { lambda: false }
+                    }
+                    _ => Kind::Map,
+                },
+            },
+        };
+        let block = matches!(kind, Kind::Block { .. });
+        if block {
+            self.frame().end_expr();
+            self.class_header = false;
+        }
+        self.put_sep(t, newlines);
+        let frame = self.frame();
+        if !block {
+            frame.start_expr();
+        }
+        frame.docs.push(Doc::text(&t.lexeme));
+        frame.empty = false;
+        self.frames.push(Frame::new(kind));
+        self.sep = match kind {
+            Kind::Block { .. } => Sep::Newline,
+            _ => Sep::SoftLine,
+        };
+        self.prev = Some(Prev {
+            ty: t.ty,
+            operand: false,
+            unary: false,
+            bracket: Some(kind),
+        });
+    }
+
+    fn close(&mut self, t: &Token) {
+        let mut frame = self.frames.pop().unwrap();
+        frame.end_expr();
+        let parent = self.frame();
+        if let Kind::Block { lambda } = frame.kind {
+            if !frame.empty {
+                parent.docs.push(Doc::Indent(frame.docs));
+                parent.docs.push(Doc::HardLine);
+            }
+            parent.docs.push(Doc::text(&t.lexeme));
+            self.sep = if lambda { Sep::None } else { Sep::StmtEnd };
+        } else {
+            let mut docs = vec![Doc::Nest(frame.docs)];
+            if !frame.empty {
+                docs.push(Doc::SoftLine);
+            }
+            docs.push(Doc::text(&t.lexeme));
+            parent.docs.push(Doc::Group {
+                docs,
+                broken: frame.broken,
+            });
+            parent.broken |= frame.broken;
+            self.sep = Sep::None;
+        }
+        self.prev = Some(Prev {
+            ty: t.ty,
+            operand: !matches!(
+                frame.kind,
+                Kind::Block { lambda: false } | Kind::Paren { kw: Some(_) }
+            ),
+            unary: false,
+            bracket: Some(frame.kind),
+        });
+    }
+
+    fn plain(&mut self, t: &Token, newlines: usize) {
+        let unary = match t.ty {
+            Bang | Tilde => true,
+            Minus => !self.prev.is_some_and(|it| it.operand),
+            _ => false,
+        };
+        // Long expressions are broken before their binary operators.
+        let superclass = t.ty == Less && self.class_header;
+        let prec = binary_prec(t.ty).filter(|_| !unary && !superclass);
+        if prec.is_some() && matches!(self.sep, Sep::None | Sep::Space) {
+            self.sep = Sep::Line;
+        }
+        self.class_header |= t.ty == Class;
+        self.put_sep(t, newlines);
+        let frame = self.frames.last_mut().unwrap();
+        match (prec, frame.docs.last()) {
+            (Some(prec), Some(Doc::Line)) => frame.ops.push((frame.docs.len() - 1, prec)),
+            _ if matches!(t.ty, Semicolon | Comma) => frame.end_expr(),
+            _ => frame.start_expr(),
+        }
+        frame.docs.push(Doc::text(&t.lexeme));
+        frame.empty = false;
+        match t.ty {
+            Semicolon if frame.is_block() => {
+                frame.ternaries = 0;
+                self.sep = Sep::Newline;
+            }
+            Semicolon | Comma if !frame.is_block() => self.sep = Sep::Line,
+            Question => frame.ternaries += 1,
+            Colon if frame.ternaries > 0 => frame.ternaries -= 1,
+            _ => (),
+        }
+        self.prev = Some(Prev {
+            ty: t.ty,
+            operand: matches!(
+                t.ty,
+                Identifier | Str | Number | True | False | Nil | This | Super
+            ),
+            unary,
+            bracket: None,
+        });
+    }
+}
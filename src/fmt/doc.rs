@@ -0,0 +1,180 @@
+use super::Options;
+
+/// A document to be laid out by [`render`], in the spirit of Wadler's
+/// "prettier printer".
+#[derive(Debug, Clone)]
+pub(crate) enum Doc {
+    /// A piece of text, which only contains newlines if it's a block comment.
+    Text(String),
+    /// A space, or a line break if the enclosing group is broken.
+    Line,
+    /// Nothing, or a line break if the enclosing group is broken.
+    SoftLine,
+    /// A line break.
+    HardLine,
+    /// A line break leaving an empty line behind.
+    BlankLine,
+    /// Docs indented by one more level, which are always broken, e.g. the
+    /// statements of a block.
+    Indent(Vec<Doc>),
+    /// Docs indented by one more level if the enclosing group is broken.
+    Nest(Vec<Doc>),
+    /// Docs put on a single line if they fit in the line width, or broken
+    /// otherwise. A `broken` group is never put on a single line.
+    Group { docs: Vec<Doc>, broken: bool },
+}
+
+impl Doc {
+    pub(crate) fn text(s: impl Into<String>) -> Self {
+        Self::Text(s.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// A command of the printer, which lays out a doc at some indentation level in
+/// some mode.
+type Cmd<'d> = (usize, Mode, &'d Doc);
+
+/// Lays out the given docs, returning the resulting text, which ends with a
+/// newline unless it's empty.
+pub(crate) fn render(docs: &[Doc], opts: Options) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        col: 0,
+        line_start: Some(0),
+        opts,
+    };
+    let mut cmds = vec![];
+    push(&mut cmds, docs, 0, Mode::Break);
+    while let Some((indent, mode, doc)) = cmds.pop() {
+        match doc {
+            Doc::Text(s) => printer.text(s),
+            Doc::Line if mode == Mode::Flat => printer.text(" "),
+            Doc::SoftLine if mode == Mode::Flat => (),
+            Doc::Line | Doc::SoftLine | Doc::HardLine => printer.newline(indent),
+            Doc::BlankLine => printer.blank_line(indent),
+            Doc::Indent(docs) => push(&mut cmds, docs, indent + 1, Mode::Break),
+            Doc::Nest(docs) => {
+                let indent = indent + usize::from(mode == Mode::Break);
+                push(&mut cmds, docs, indent, mode);
+            }
+            Doc::Group { docs, broken } => {
+                let mode = if *broken {
+                    Mode::Break
+                } else if mode == Mode::Flat || fits(docs, &cmds, printer.remaining()) {
+                    Mode::Flat
+                } else {
+                    Mode::Break
+                };
+                push(&mut cmds, docs, indent, mode);
+            }
+        }
+    }
+    let mut out = printer.out;
+    out.truncate(out.trim_end().len());
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+fn push<'d>(cmds: &mut Vec<Cmd<'d>>, docs: &'d [Doc], indent: usize, mode: Mode) {
+    cmds.extend(docs.iter().rev().map(|it| (indent, mode, it)));
+}
+
+/// Returns if the flat `docs` followed by the `rest` of the commands fit in
+/// `width` till the next line break.
+///
+/// A group containing a block is deemed to fit if its text before the block
+/// does, so that a lambda can be passed as an argument without breaking the
+/// call.
+fn fits(docs: &[Doc], rest: &[Cmd], width: isize) -> bool {
+    let mut width = width;
+    let mut docs: Vec<_> = docs.iter().rev().map(|it| (Mode::Flat, it)).collect();
+    let mut rest = rest.iter().rev().map(|&(_, mode, doc)| (mode, doc));
+    while width >= 0 {
+        let Some((mode, doc)) = docs.pop().or_else(|| rest.next()) else {
+            return true;
+        };
+        match doc {
+            Doc::Text(s) => {
+                let line = s.split('\n').next().unwrap_or_default();
+                width -= isize::try_from(line.chars().count()).unwrap();
+                if line.len() < s.len() {
+                    return width >= 0;
+                }
+            }
+            Doc::Line if mode == Mode::Flat => width -= 1,
+            Doc::SoftLine if mode == Mode::Flat => (),
+            Doc::Line | Doc::SoftLine | Doc::HardLine | Doc::BlankLine => return true,
+            Doc::Indent(inner) => docs.extend(inner.iter().rev().map(|it| (Mode::Break, it))),
+            Doc::Nest(inner) => docs.extend(inner.iter().rev().map(|it| (mode, it))),
+            Doc::Group {
+                docs: inner,
+                broken,
+            } => {
+                let mode = if *broken { Mode::Break } else { mode };
+                docs.extend(inner.iter().rev().map(|it| (mode, it)));
+            }
+        }
+    }
+    false
+}
+
+struct Printer {
+    out: String,
+    /// The width of the current line.
+    col: usize,
+    /// The indentation level of the current line, if nothing has been written
+    /// to it yet.
+    line_start: Option<usize>,
+    opts: Options,
+}
+
+impl Printer {
+    /// Returns the width left on the current line.
+    fn remaining(&self) -> isize {
+        let col = self
+            .line_start
+            .map_or(self.col, |indent| indent * self.opts.indent);
+        isize::try_from(self.opts.width).unwrap() - isize::try_from(col).unwrap()
+    }
+
+    fn text(&mut self, s: &str) {
+        if let Some(indent) = self.line_start {
+            // Lines never begin with spaces other than the indentation.
+            if s == " " {
+                return;
+            }
+            let indent = indent * self.opts.indent;
+            self.out.push_str(&" ".repeat(indent));
+            self.col = indent;
+            self.line_start = None;
+        }
+        self.out.push_str(s);
+        match s.rfind('\n') {
+            Some(i) => self.col = s[i + 1..].chars().count(),
+            None => self.col += s.chars().count(),
+        }
+    }
+
+    fn newline(&mut self, indent: usize) {
+        if self.line_start.is_none() {
+            self.out.truncate(self.out.trim_end_matches(' ').len());
+            self.out.push('\n');
+        }
+        self.line_start = Some(indent);
+    }
+
+    fn blank_line(&mut self, indent: usize) {
+        self.newline(indent);
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+}
@@ -0,0 +1,243 @@
+#![cfg(test)]
+
+use indoc::indoc;
+use itertools::Itertools;
+use pretty_assertions::assert_eq;
+
+use super::*;
+use crate::error::{Code, Diagnostic, Errors};
+
+fn fmt_with(src: &str, opts: Options) -> String {
+    let got = format(src, 0, opts).unwrap();
+    assert_eq!(
+        got,
+        format(&got, 0, opts).unwrap(),
+        "formatting is not idempotent"
+    );
+    let tokens = |src| {
+        Lexer::with_offset(src, 0)
+            .map(|it| (it.ty, it.lexeme.trim_end().to_owned()))
+            .collect_vec()
+    };
+    assert_eq!(tokens(src), tokens(&got), "formatting changed the tokens");
+    got
+}
+
+fn assert_fmt(src: &str, expected: &str) {
+    assert_eq!(expected, fmt_with(src, Options::default()));
+}
+
+#[test]
+fn spacing() {
+    assert_fmt(
+        indoc! {r#"
+            var x=1+2*-y;print !x and -x or ~x;
+            a.b=c?d:e?f:g; a -= - b; print xs[0](1)[2] ?? nil;
+            print {"a":1,"b":[1,2,],"c":x?.y};print -(-x);
+            import "m.lox" as m;from "m.lox" import a,b;
+        "#},
+        indoc! {r#"
+            var x = 1 + 2 * -y;
+            print !x and -x or ~x;
+            a.b = c ? d : e ? f : g;
+            a -= -b;
+            print xs[0](1)[2] ?? nil;
+            print {"a": 1, "b": [1, 2,], "c": x?.y};
+            print -(-x);
+            import "m.lox" as m;
+            from "m.lox" import a, b;
+        "#},
+    );
+}
+
+#[test]
+fn literals_kept() {
+    assert_fmt(
+        r#"var n=0xFF+1_000+1.50;var s='it\'s ${ n+1 }\u{1F600}';"#,
+        indoc! {r#"
+            var n = 0xFF + 1_000 + 1.50;
+            var s = 'it\'s ${ n+1 }\u{1F600}';
+        "#},
+    );
+}
+
+#[test]
+fn blocks() {
+    assert_fmt(
+        indoc! {"
+            class A<B{init(a){this.a=a;super.init();}
+            get(){return this.a;}}
+            fun f(a){if(a>1){return a;}else if(a==1)return 0;else{return -a;}}
+            try{throw 1;}catch(e){print e;}finally{}
+            var g=fun(x){return x*2;};{}
+        "},
+        indoc! {"
+            class A < B {
+                init(a) {
+                    this.a = a;
+                    super.init();
+                }
+                get() {
+                    return this.a;
+                }
+            }
+            fun f(a) {
+                if (a > 1) {
+                    return a;
+                } else if (a == 1) return 0;
+                else {
+                    return -a;
+                }
+            }
+            try {
+                throw 1;
+            } catch (e) {
+                print e;
+            } finally {}
+            var g = fun (x) {
+                return x * 2;
+            };
+            {}
+        "},
+    );
+}
+
+#[test]
+fn loops() {
+    assert_fmt(
+        indoc! {"
+            outer:for(var i=0;i<3;i+=1){for(;;){if(i==1)continue outer;break;}}
+            while(true)break;
+        "},
+        indoc! {"
+            outer: for (var i = 0; i < 3; i += 1) {
+                for (;;) {
+                    if (i == 1) continue outer;
+                    break;
+                }
+            }
+            while (true) break;
+        "},
+    );
+}
+
+#[test]
+fn comments() {
+    assert_fmt(
+        indoc! {"
+            // Header.
+            var a = 1;   // Trailing.
+
+
+
+            /* Block
+               comment. */
+            fun f() { // After `{`.
+              // Before `}`.
+            }
+            print f(
+              1, // One.
+              /* Two. */ 2
+            );
+        "},
+        indoc! {"
+            // Header.
+            var a = 1; // Trailing.
+
+            /* Block
+               comment. */
+            fun f() { // After `{`.
+                // Before `}`.
+            }
+            print f(
+                1, // One.
+                /* Two. */ 2
+            );
+        "},
+    );
+}
+
+#[test]
+fn line_width() {
+    assert_fmt(
+        indoc! {"
+            var xs = [aaaaaaaaaaaaaaaaaaaaaaaaa, bbbbbbbbbbbbbbbbbbbbbbbbb, ccccccccccccccccccccccccc, ddddddddddddddddddddddddd];
+            xs.map(fun (x) { return x; });
+        "},
+        indoc! {"
+            var xs = [
+                aaaaaaaaaaaaaaaaaaaaaaaaa,
+                bbbbbbbbbbbbbbbbbbbbbbbbb,
+                ccccccccccccccccccccccccc,
+                ddddddddddddddddddddddddd
+            ];
+            xs.map(fun (x) {
+                return x;
+            });
+        "},
+    );
+    let opts = Options {
+        indent: 2,
+        width: 20,
+    };
+    assert_eq!(
+        indoc! {"
+            fun f(a) {
+              return g(
+                a,
+                [1, 2, 3]
+              );
+            }
+        "},
+        fmt_with("fun f(a) { return g(a, [1, 2, 3]); }", opts),
+    );
+}
+
+#[test]
+fn line_width_binary() {
+    let opts = Options {
+        indent: 4,
+        width: 40,
+    };
+    // Only the loosest operators are broken before, and brackets get their
+    // own groups.
+    assert_eq!(
+        indoc! {"
+            var total = alpha
+                + beta * gamma
+                + delta
+                - epsilon * zeta
+                + eta;
+            print f(
+                aaaaaaaaaa + bbbbbbbbbbbb,
+                cccccccccccc * dddddddddddd
+                    + eeeeeeeeee
+            );
+            class LongClassName < AnotherLongSuperclass {}
+            print short + expr;
+        "},
+        fmt_with(
+            indoc! {"
+                var total = alpha + beta * gamma + delta - epsilon * zeta + eta;
+                print f(aaaaaaaaaa + bbbbbbbbbbbb, cccccccccccc * dddddddddddd + eeeeeeeeee);
+                class LongClassName < AnotherLongSuperclass {}
+                print short
+                    + expr;
+            "},
+            opts,
+        ),
+    );
+}
+
+#[test]
+fn syntax_error() {
+    let err = format("print 1", 0, Options::default()).unwrap_err();
+    let code = err
+        .downcast_ref::<Diagnostic>()
+        .map(|it| it.code)
+        .or_else(|| {
+            let Errors(errs) = err.downcast_ref()?;
+            errs[0].downcast_ref::<Diagnostic>().map(|it| it.code)
+        });
+    assert_eq!(Some(Code::UnexpectedToken), code);
+}
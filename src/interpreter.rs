@@ -1,13 +1,21 @@
 mod class;
 pub(crate) mod closure;
+pub(crate) mod coverage;
+pub(crate) mod debug;
 pub(crate) mod env;
 mod expr;
+pub(crate) mod foreign;
 mod jump;
+mod list;
+mod map;
+mod module;
 pub(crate) mod object;
+mod prelude;
+pub(crate) mod profile;
 mod stmt;
 mod tests;
 
-use std::{collections::HashMap, mem};
+use std::{collections::HashMap, mem, rc::Rc};
 
 use anyhow::Result;
 use gc::Gc;
@@ -15,14 +23,28 @@ use gc::Gc;
 pub(crate) use self::{
     class::{Class, Instance},
     closure::Closure,
+    coverage::Coverage,
+    debug::Debugger,
     env::Env,
-    jump::{BreakMarker, ContinueMarker, ReturnMarker},
+    expr::{
+        binary_op, check_arity, foreign_error, get_index, get_property, new_map, set_index,
+        unary_op,
+    },
+    foreign::{Arity, Foreign},
+    jump::{catch, ControlFlow, Thrown},
+    list::List,
+    map::{Map, MapKey},
+    module::Module,
     object::Object,
+    profile::Profiler,
 };
 use crate::{
+    error::{CallFrame, Diagnostic},
     lexer::Token,
+    module::Modules,
     parser::{Expr, Stmt},
     resolver::Resolver,
+    source::SourceMap,
     util::MutCell,
 };
 
@@ -33,6 +55,19 @@ pub(crate) struct Interpreter {
     env: MutCell<Env>,
     pub(crate) globals: MutCell<Env>,
     pub(crate) locals: HashMap<Token, usize>,
+    /// The calls in progress, innermost call last.
+    pub(crate) frames: Vec<CallFrame>,
+    /// The sources evaluated so far, memorized for error reports.
+    pub(crate) sources: SourceMap,
+    pub(crate) modules: Modules,
+    /// The values thrown and not caught yet.
+    pub(crate) thrown: Thrown,
+    /// The debugger checked before executing each statement, if any.
+    pub(crate) debugger: Option<Rc<Debugger>>,
+    /// The profiler checked around each closure application, if any.
+    pub(crate) profiler: Option<Rc<Profiler>>,
+    /// The coverage recorder checked before executing each statement, if any.
+    pub(crate) coverage: Option<Rc<Coverage>>,
 }
 
 impl Interpreter {
@@ -42,24 +77,78 @@ impl Interpreter {
             env: Gc::clone(env),
             globals: Gc::clone(env),
             locals: HashMap::new(),
+            frames: vec![],
+            sources: SourceMap::default(),
+            modules: Modules::default(),
+            thrown: Thrown::default(),
+            debugger: None,
+            profiler: None,
+            coverage: None,
         }
     }
 
-    pub(crate) fn resolve_expr(&mut self, expr: Expr) -> Result<()> {
-        let mut resolver = Resolver::new(mem::take(self));
-        resolver.resolve_expr(expr)?;
-        *self = resolver.interpreter;
-        Ok(())
+    /// Defines a host function as a builtin, which is visible from the
+    /// global environments of all modules.
+    pub(crate) fn define_foreign(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        fun: impl Fn(Vec<Object>) -> Result<Object> + 'static,
+    ) {
+        let foreign = Object::ForeignFn(Foreign::new(name, arity, fun));
+        self.modules.builtins.borrow_mut().insert_val(name, foreign);
     }
 
-    pub(crate) fn resolve_stmts(&mut self, stmts: impl IntoIterator<Item = Stmt>) -> Result<()> {
-        *self = Resolver::new(mem::take(self)).resolve(stmts)?;
-        Ok(())
+    /// Takes the interpreter out of `self`, leaving a placeholder sharing the
+    /// same globals behind.
+    fn take(&mut self) -> Self {
+        let placeholder = Self::new(&self.globals);
+        mem::replace(self, placeholder)
+    }
+
+    /// Resolves the given expression, returning the warnings found.
+    pub(crate) fn resolve_expr(&mut self, expr: Expr) -> Result<Vec<Diagnostic>> {
+        let mut resolver = Resolver::new(self.take());
+        resolver.resolve_expr(expr);
+        // The interpreter is put back even on errors, so that the sources are
+        // still available for error reports.
+        let (this, res) = resolver.finish();
+        *self = this;
+        res
+    }
+
+    /// Resolves the given statements, returning the warnings found.
+    pub(crate) fn resolve_stmts(
+        &mut self,
+        stmts: impl IntoIterator<Item = Stmt>,
+    ) -> Result<Vec<Diagnostic>> {
+        let (this, res) = Resolver::new(self.take()).resolve(stmts);
+        *self = this;
+        res
+    }
+
+    /// Resolves and runs the given statements of a module in a new global
+    /// environment, which is returned.
+    pub(crate) fn exec_module(&mut self, stmts: Vec<Stmt>) -> Result<MutCell<Env>> {
+        self.resolve_stmts(stmts.clone())?;
+        let globals = self.modules.new_globals();
+        let old_env = mem::replace(&mut self.env, Gc::clone(&globals));
+        let old_globals = mem::replace(&mut self.globals, Gc::clone(&globals));
+        let res = self.exec_stmts(stmts);
+        self.env = old_env;
+        self.globals = old_globals;
+        res.map(|_| globals)
     }
 }
 
 impl Default for Interpreter {
+    /// Creates a new interpreter with the native functions defined as
+    /// builtins.
     fn default() -> Self {
-        Self::new(&Env::default().shared())
+        let modules = Modules::default();
+        let mut res = Self::new(&modules.new_globals());
+        res.modules = modules;
+        prelude::install(&mut res);
+        res
     }
 }
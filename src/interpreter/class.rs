@@ -10,7 +10,7 @@ use super::{MutCell, Object};
 use crate::util::rc_cell_of;
 
 #[derive(Debug, Clone, Trace, Finalize)]
-pub(crate) struct Class {
+pub struct Class {
     #[unsafe_ignore_trace]
     pub(crate) uid: Uuid,
     pub(crate) name: String,
@@ -58,7 +58,7 @@ impl PartialEq for Class {
 impl Eq for Class {}
 
 #[derive(Debug, Clone, Trace, Finalize)]
-pub(crate) struct Instance {
+pub struct Instance {
     #[unsafe_ignore_trace]
     pub(crate) uid: Uuid,
     pub(crate) class: Class,
@@ -78,15 +78,8 @@ impl From<Class> for Instance {
 impl Instance {
     #[must_use]
     pub(crate) fn get(&self, name: &str) -> Option<Object> {
-        self.fields.borrow().get(name).cloned().or_else(|| {
-            self.class.method(name).map(|it| {
-                if let Object::NativeFn(clos) = &it {
-                    Object::NativeFn(clos.clone().bind(self.clone()))
-                } else {
-                    unreachable!()
-                }
-            })
-        })
+        (self.fields.borrow().get(name).cloned())
+            .or_else(|| self.class.method(name).map(|it| it.bind(self.clone())))
     }
 
     #[allow(clippy::must_use_candidate)]
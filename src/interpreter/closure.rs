@@ -9,46 +9,58 @@ use itertools::izip;
 use tap::prelude::*;
 use uuid::Uuid;
 
-use super::{Env, Instance, Interpreter, MutCell, Object, ReturnMarker};
-use crate::{lexer::Token, parser::Stmt};
+use super::{ControlFlow, Env, Instance, Interpreter, MutCell, Object};
+use crate::{lexer::Token, parser::Stmt, source::Span};
 
 #[derive(Debug, Clone, Trace, Finalize)]
-pub(crate) struct Closure {
+pub struct Closure {
     #[unsafe_ignore_trace]
     pub(crate) uid: Uuid,
     pub(crate) name: Option<String>,
+    /// The span of the name of this closure, or of the `fun` keyword for
+    /// lambdas, memorized for error reports.
+    #[unsafe_ignore_trace]
+    pub(crate) defn: Span,
     #[unsafe_ignore_trace]
     pub(crate) params: Vec<Token>,
     #[unsafe_ignore_trace]
     pub(crate) body: Vec<Stmt>,
     pub(crate) env: MutCell<Env>,
+    /// The global environment of the module where this closure is defined.
+    pub(crate) globals: MutCell<Env>,
     is_init: bool,
 }
 
 impl Closure {
     pub(crate) fn new<'n>(
         name: impl Into<Option<&'n str>>,
+        defn: Span,
         params: impl IntoIterator<Item = Token>,
         body: impl IntoIterator<Item = Stmt>,
         env: &MutCell<Env>,
+        globals: &MutCell<Env>,
     ) -> Self {
         Self {
             uid: Uuid::new_v4(),
             name: name.into().map(str::to_owned),
+            defn,
             params: params.into_iter().collect(),
             body: body.into_iter().collect(),
             env: Gc::clone(env),
+            globals: Gc::clone(globals),
             is_init: false,
         }
     }
 
     pub(crate) fn new_init<'n>(
         name: impl Into<Option<&'n str>>,
+        defn: Span,
         params: impl IntoIterator<Item = Token>,
         body: impl IntoIterator<Item = Stmt>,
         env: &MutCell<Env>,
+        globals: &MutCell<Env>,
     ) -> Self {
-        Self::new(name, params, body, env).tap_mut(|it| it.is_init = true)
+        Self::new(name, defn, params, body, env, globals).tap_mut(|it| it.is_init = true)
     }
 
     #[must_use]
@@ -66,28 +78,17 @@ impl Closure {
         // Temporarily switch into the scope environment...
         let old_env = Gc::clone(&interpreter.env);
         interpreter.env = Env::from_outer(&self.env).shared();
-        let (expected_len, got_len) = (self.params.len(), args.len());
-        if expected_len != got_len {
-            anyhow::bail!(
-                "[..] unexpected number of parameters (expected {}, got {})",
-                expected_len,
-                got_len
-            )
-        }
+        let old_globals = mem::replace(&mut interpreter.globals, Gc::clone(&self.globals));
         izip!(self.params.iter(), args).for_each(|(ident, defn)| {
             interpreter.env.borrow_mut().insert_val(&ident.lexeme, defn);
         });
-        let res = self
-            .body
-            .pipe_ref_mut(mem::take)
-            .into_iter()
-            .try_for_each(|it| interpreter.exec(it));
+        let res = interpreter.exec_stmts(self.body.pipe_ref_mut(mem::take));
         // Switch back...
         interpreter.env = old_env;
-        match res {
-            Err(e) if e.is::<ReturnMarker>() => Ok(e.downcast::<ReturnMarker>().unwrap().0),
-            e => {
-                e?;
+        interpreter.globals = old_globals;
+        match res? {
+            ControlFlow::Return(obj) => Ok(obj),
+            _ => {
                 if self.is_init {
                     // Special case: for initializers, we implicitly return `this`.
                     // This is actually not quite elegant as it adds a branch to all closure
@@ -0,0 +1,134 @@
+//! The coverage recorder tracking which statements have been run.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use itertools::Itertools;
+
+use crate::{parser::Stmt, source::SourceMap};
+
+/// The lines of a source file containing statements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileCoverage {
+    pub(crate) name: String,
+    /// The number of times each line has been run, where a line is run
+    /// whenever one of its statements is.
+    pub(crate) lines: BTreeMap<usize, u64>,
+}
+
+impl FileCoverage {
+    /// Returns the number of lines that have been run.
+    pub(crate) fn hit(&self) -> usize {
+        self.lines.values().filter(|&&it| it > 0).count()
+    }
+}
+
+/// A coverage recorder checked before executing each statement.
+///
+/// The statements are added as they are resolved, so that the ones never run
+/// are known as well.
+#[derive(Debug, Default)]
+pub(crate) struct Coverage {
+    /// The number of times each statement has been run, by the start of its
+    /// span.
+    stmts: RefCell<HashMap<usize, u64>>,
+}
+
+impl Coverage {
+    /// Adds `stmt` to the statements to be run.
+    pub(crate) fn add(&self, stmt: &Stmt) {
+        if let Some(start) = Self::key(stmt) {
+            self.stmts.borrow_mut().entry(start).or_default();
+        }
+    }
+
+    /// Records a run of `stmt`.
+    pub(crate) fn hit(&self, stmt: &Stmt) {
+        if let Some(start) = Self::key(stmt) {
+            *self.stmts.borrow_mut().entry(start).or_default() += 1;
+        }
+    }
+
+    fn key(stmt: &Stmt) -> Option<usize> {
+        // A block is located at its first statement, which is counted on its
+        // own.
+        if matches!(stmt, Stmt::Block(_)) {
+            return None;
+        }
+        stmt.span().map(|it| it.start)
+    }
+
+    /// Returns the coverage of each file in `sources` containing statements,
+    /// in the order they have been added.
+    pub(crate) fn files(&self, sources: &SourceMap) -> Vec<FileCoverage> {
+        let mut files: Vec<FileCoverage> = vec![];
+        let stmts = self.stmts.borrow();
+        for (&start, &count) in stmts.iter().sorted() {
+            let Some(file) = sources.file(start) else {
+                continue;
+            };
+            if files.last().map(|it| &it.name) != Some(&file.name) {
+                files.push(FileCoverage {
+                    name: file.name.clone(),
+                    lines: BTreeMap::new(),
+                });
+            }
+            let (line, _) = file.pos(start);
+            // This is a safe unwrap since a file has just been pushed if
+            // `files` was empty.
+            let hits = files.last_mut().unwrap().lines.entry(line).or_default();
+            *hits = count.max(*hits);
+        }
+        files
+    }
+
+    /// Renders a summary of each file, listing the lines never run.
+    pub(crate) fn text(&self, sources: &SourceMap) -> String {
+        let mut res = String::new();
+        for file in self.files(sources) {
+            let (hit, total) = (file.hit(), file.lines.len());
+            #[allow(clippy::cast_precision_loss)]
+            let pct = hit as f64 / total as f64 * 100.;
+            let _ = writeln!(res, "{}: {hit}/{total} lines ({pct:.1}%)", file.name);
+            let missed = (file.lines.iter())
+                .filter(|(_, &count)| count == 0)
+                .map(|(&line, _)| line)
+                .collect_vec();
+            if missed.is_empty() {
+                continue;
+            }
+            // Consecutive lines are merged into ranges.
+            let ranges = (missed.iter())
+                .enumerate()
+                .group_by(|(i, &line)| line - i)
+                .into_iter()
+                .map(|(_, group)| {
+                    let group = group.map(|(_, &line)| line).collect_vec();
+                    match (group[0], group[group.len() - 1]) {
+                        (first, last) if first == last => first.to_string(),
+                        (first, last) => format!("{first}-{last}"),
+                    }
+                })
+                .join(", ");
+            let _ = writeln!(res, "  not run: {ranges}");
+        }
+        res
+    }
+
+    /// Renders the coverage in the LCOV tracefile format.
+    pub(crate) fn lcov(&self, sources: &SourceMap) -> String {
+        let mut res = String::new();
+        for file in self.files(sources) {
+            let _ = writeln!(res, "TN:\nSF:{}", file.name);
+            for (line, count) in &file.lines {
+                let _ = writeln!(res, "DA:{line},{count}");
+            }
+            let _ = writeln!(res, "LF:{}\nLH:{}", file.lines.len(), file.hit());
+            res.push_str("end_of_record\n");
+        }
+        res
+    }
+}
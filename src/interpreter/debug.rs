@@ -0,0 +1,434 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    fmt::{self, Display},
+    fs,
+    path::PathBuf,
+    rc::Rc,
+};
+
+use anyhow::Result;
+use gc::Gc;
+use itertools::Itertools;
+
+use super::{Env, Interpreter, MutCell, Object};
+use crate::{
+    error::{render_error, Diagnostic},
+    lexer::Lexer,
+    parser::{Parser, Stmt},
+    resolver::Resolver,
+    source::{SourceMap, Span},
+};
+
+/// A position in a source file, in the text editor standard (index starting
+/// from 1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Location {
+    pub(crate) file: String,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StopReason {
+    /// The program is about to run its first statement.
+    Entry,
+    /// A step has been completed.
+    Step,
+    Breakpoint,
+    /// A runtime error has been raised with the given message.
+    Error(String),
+}
+
+/// Where and why the program has stopped.
+#[derive(Debug, Clone)]
+pub(crate) struct Stop {
+    pub(crate) reason: StopReason,
+    pub(crate) loc: Location,
+    /// The source line of `loc` without its indentation.
+    pub(crate) text: String,
+}
+
+/// How to resume a stopped program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Resume {
+    /// Run until the next breakpoint or runtime error.
+    Continue,
+    /// Stop at the next statement, which might be in a called function.
+    StepIn,
+    /// Stop at the next statement of the current call or of its callers.
+    StepOver,
+    /// Stop at the next statement of the callers of the current call.
+    StepOut,
+    /// Abort the program with a [`Terminated`] error.
+    Terminate,
+}
+
+/// The user interface of a [`Debugger`].
+pub(crate) trait Frontend: fmt::Debug {
+    /// Lets the user inspect the program stopped at `stop` through `paused`,
+    /// returning how to resume it.
+    fn stopped(&self, stop: &Stop, paused: &mut Paused<'_>) -> Result<Resume>;
+
+    /// Shows the text printed by the program.
+    fn output(&self, text: &str);
+}
+
+/// The error aborting a program terminated from the debugger.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Terminated;
+
+impl Display for Terminated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the program has been terminated by the debugger")
+    }
+}
+
+impl std::error::Error for Terminated {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Entry,
+    Continue,
+    StepIn,
+    /// Stepping over the calls deeper than the given call depth.
+    StepOver(usize),
+    /// Stepping out of the calls of the given call depth or deeper.
+    StepOut(usize),
+    Terminated,
+}
+
+/// A source file seen by the debugger.
+#[derive(Debug)]
+struct File {
+    /// The canonical path of the file, to which the breakpoints are attached.
+    key: PathBuf,
+    /// The offsets of the beginnings of the lines of the file in its
+    /// `SourceMap`.
+    line_starts: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct State {
+    mode: Mode,
+    /// The lines with a breakpoint in each file.
+    breakpoints: HashMap<PathBuf, BTreeSet<usize>>,
+    break_on_error: bool,
+    /// The call depth, the file and the line of the last statement, so that a
+    /// breakpoint is hit only once when several statements share a line.
+    last: Option<(usize, usize, usize)>,
+    /// If a runtime error is being propagated after stopping on it.
+    unwinding: bool,
+    /// If the program is stopped, in which case the code run by the front end,
+    /// e.g. to evaluate an expression, is not debugged.
+    stopped: bool,
+    /// The files seen so far, by their offsets in the `SourceMap`.
+    files: HashMap<usize, File>,
+}
+
+/// Returns the key of the breakpoints in the file `name`.
+fn file_key(name: &str) -> PathBuf {
+    fs::canonicalize(name).unwrap_or_else(|_| name.into())
+}
+
+impl State {
+    /// Returns the start of the file containing the byte at `offset`, together
+    /// with the line number of that byte.
+    fn line_of(&mut self, sources: &SourceMap, offset: usize) -> Option<(usize, usize)> {
+        let src = sources.file(offset)?;
+        let file = self.files.entry(src.start).or_insert_with(|| File {
+            key: file_key(&src.name),
+            line_starts: std::iter::once(src.start)
+                .chain(src.src.match_indices('\n').map(|(i, _)| src.start + i + 1))
+                .collect(),
+        });
+        let line = file.line_starts.partition_point(|&it| it <= offset);
+        Some((src.start, line))
+    }
+}
+
+/// A debugger attached to an [`Interpreter`], which is checked before
+/// executing each statement.
+#[derive(Debug)]
+pub(crate) struct Debugger {
+    pub(crate) frontend: Rc<dyn Frontend>,
+    state: RefCell<State>,
+}
+
+impl Debugger {
+    /// Creates a debugger which stops only at breakpoints or on runtime
+    /// errors.
+    pub(crate) fn new(frontend: Rc<dyn Frontend>) -> Self {
+        let state = State {
+            mode: Mode::Continue,
+            breakpoints: HashMap::new(),
+            break_on_error: true,
+            last: None,
+            unwinding: false,
+            stopped: false,
+            files: HashMap::new(),
+        };
+        Self {
+            frontend,
+            state: RefCell::new(state),
+        }
+    }
+
+    /// Makes the program stop before its first statement.
+    pub(crate) fn stop_on_entry(&self) {
+        self.state.borrow_mut().mode = Mode::Entry;
+    }
+
+    /// Sets whether the program stops on runtime errors, including the
+    /// exceptions thrown, even if they are caught later.
+    pub(crate) fn break_on_error(&self, on: bool) {
+        self.state.borrow_mut().break_on_error = on;
+    }
+
+    /// Replaces the breakpoints in the file `name` with the given lines.
+    pub(crate) fn set_breakpoints(&self, name: &str, lines: impl IntoIterator<Item = usize>) {
+        let mut state = self.state.borrow_mut();
+        state
+            .breakpoints
+            .insert(file_key(name), lines.into_iter().collect());
+    }
+
+    /// Adds or removes the breakpoint at `line` of the file `name`, returning
+    /// if it has been changed.
+    pub(crate) fn toggle_breakpoint(&self, name: &str, line: usize, on: bool) -> bool {
+        let mut state = self.state.borrow_mut();
+        let lines = state.breakpoints.entry(file_key(name)).or_default();
+        if on {
+            lines.insert(line)
+        } else {
+            lines.remove(&line)
+        }
+    }
+
+    /// Returns the breakpoints in order as `(file, line)` pairs.
+    pub(crate) fn breakpoints(&self) -> Vec<(PathBuf, usize)> {
+        let state = self.state.borrow();
+        (state.breakpoints.iter())
+            .flat_map(|(file, lines)| lines.iter().map(|&it| (file.clone(), it)))
+            .sorted()
+            .collect()
+    }
+}
+
+/// A stopped program, which can be inspected by a [`Frontend`].
+#[derive(Debug)]
+pub(crate) struct Paused<'i> {
+    interpreter: &'i mut Interpreter,
+    debugger: &'i Debugger,
+    loc: Location,
+}
+
+/// An environment of a stopped program.
+#[derive(Debug, Clone)]
+pub(crate) struct Scope {
+    pub(crate) name: String,
+    /// The variables defined in this environment, ordered by name.
+    pub(crate) vars: Vec<(String, Object)>,
+}
+
+/// A call in progress in a stopped program.
+#[derive(Debug, Clone)]
+pub(crate) struct Frame {
+    /// The name of the callee, or `<script>` for the top level.
+    pub(crate) name: String,
+    /// Where the statement in progress in this call is.
+    pub(crate) loc: Option<Location>,
+}
+
+impl Paused<'_> {
+    pub(crate) fn debugger(&self) -> &Debugger {
+        self.debugger
+    }
+
+    /// Returns where the program is stopped.
+    pub(crate) fn location(&self) -> &Location {
+        &self.loc
+    }
+
+    /// Returns the local environments from the innermost one, followed by the
+    /// global one.
+    pub(crate) fn scopes(&self) -> Vec<Scope> {
+        let scope = |name: String, env: &MutCell<Env>| {
+            let env: &Env = &env.borrow();
+            let vars = (env.dict.iter())
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .sorted_by(|(k1, _), (k2, _)| k1.cmp(k2))
+                .collect();
+            Scope { name, vars }
+        };
+        let locals = self.interpreter.local_envs().into_iter().enumerate();
+        (locals.map(|(i, env)| match i {
+            0 => scope("Locals".into(), &env),
+            _ => scope(format!("Enclosing #{i}"), &env),
+        }))
+        .chain(std::iter::once(scope(
+            "Globals".into(),
+            &self.interpreter.globals,
+        )))
+        .collect()
+    }
+
+    /// Returns the calls in progress from the innermost one.
+    pub(crate) fn backtrace(&self) -> Vec<Frame> {
+        let frames = &self.interpreter.frames;
+        let names = std::iter::once("<script>").chain(frames.iter().map(|it| it.name.as_str()));
+        let locs = (frames.iter())
+            .map(|it| self.interpreter.locate(it.call_site))
+            .chain(std::iter::once(Some(self.loc.clone())));
+        let mut res = (names.zip(locs))
+            .map(|(name, loc)| Frame {
+                name: name.into(),
+                loc,
+            })
+            .collect_vec();
+        res.reverse();
+        res
+    }
+
+    /// Evaluates the expression `src` as if it were in the statement where the
+    /// program is stopped.
+    pub(crate) fn eval(&mut self, src: &str) -> Result<Object> {
+        let interpreter = &mut *self.interpreter;
+        let offset = interpreter.sources.add("<debugger>", src);
+        let expr = Parser::new(Lexer::with_offset(src, offset)).parse_expr()?;
+        let scopes = (interpreter.local_envs().iter().rev())
+            .map(|it| it.borrow().dict.keys().cloned().collect())
+            .collect_vec();
+        let mut resolver = Resolver::new(interpreter.take());
+        resolver.enter_scopes(scopes);
+        resolver.resolve_expr(expr.clone());
+        let (this, res) = resolver.finish();
+        *interpreter = this;
+        res?;
+        interpreter.eval(expr)
+    }
+
+    /// Renders the error `e` raised while inspecting the program.
+    pub(crate) fn render_error(&self, e: &anyhow::Error) -> String {
+        render_error(e, &self.interpreter.sources)
+    }
+}
+
+impl Interpreter {
+    /// Returns the environments of the current call from the innermost one,
+    /// excluding the global one.
+    fn local_envs(&self) -> Vec<MutCell<Env>> {
+        std::iter::successors(Some(Gc::clone(&self.env)), |it| it.borrow().outer.clone())
+            .take_while(|it| !Gc::ptr_eq(it, &self.globals))
+            .collect()
+    }
+
+    fn locate(&self, span: Span) -> Option<Location> {
+        let file = self.sources.file(span.start)?;
+        let (line, column) = file.pos(span.start);
+        Some(Location {
+            file: file.name.clone(),
+            line,
+            column,
+        })
+    }
+
+    /// Stops before executing `stmt` if the debugger asks to.
+    pub(crate) fn debug_stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        let Some(debugger) = self.debugger.clone() else {
+            return Ok(());
+        };
+        let Some(span) = stmt.span().filter(|_| !matches!(stmt, Stmt::Block(_))) else {
+            return Ok(());
+        };
+        let reason = {
+            let mut state = debugger.state.borrow_mut();
+            if state.stopped {
+                return Ok(());
+            }
+            if state.mode == Mode::Terminated {
+                return Err(Terminated.into());
+            }
+            state.unwinding = false;
+            if state.mode == Mode::Continue && state.breakpoints.values().all(BTreeSet::is_empty) {
+                return Ok(());
+            }
+            let Some((file, line)) = state.line_of(&self.sources, span.start) else {
+                return Ok(());
+            };
+            let depth = self.frames.len();
+            let new_line = state.last != Some((depth, file, line));
+            state.last = Some((depth, file, line));
+            let is_breakpoint = || {
+                let key = &state.files[&file].key;
+                (state.breakpoints.get(key)).is_some_and(|it| it.contains(&line))
+            };
+            match state.mode {
+                Mode::Entry => StopReason::Entry,
+                Mode::StepIn => StopReason::Step,
+                Mode::StepOver(it) if depth <= it => StopReason::Step,
+                Mode::StepOut(it) if depth < it => StopReason::Step,
+                _ if new_line && is_breakpoint() => StopReason::Breakpoint,
+                _ => return Ok(()),
+            }
+        };
+        self.debug_stop(&debugger, reason, span)
+    }
+
+    /// Stops on the error `e` raised by a statement if it's a runtime error,
+    /// unless the program has already stopped on it in a deeper statement.
+    pub(crate) fn debug_error(&mut self, e: &anyhow::Error) -> Result<()> {
+        let Some(debugger) = self.debugger.clone() else {
+            return Ok(());
+        };
+        let Some(diag) = (e.downcast_ref::<Diagnostic>()).filter(|it| it.code.is_runtime()) else {
+            return Ok(());
+        };
+        {
+            let mut state = debugger.state.borrow_mut();
+            if state.stopped || state.unwinding || !state.break_on_error {
+                return Ok(());
+            }
+            state.unwinding = true;
+        }
+        let reason = StopReason::Error(diag.msg.clone());
+        self.debug_stop(&debugger, reason, diag.primary)
+    }
+
+    fn debug_stop(&mut self, debugger: &Debugger, reason: StopReason, span: Span) -> Result<()> {
+        let Some(loc) = self.locate(span) else {
+            return Ok(());
+        };
+        let text = (self.sources.file(span.start))
+            .and_then(|it| Some(it.line(loc.line)?.0.trim().to_owned()))
+            .unwrap_or_default();
+        let stop = Stop { reason, loc, text };
+        debugger.state.borrow_mut().stopped = true;
+        let mut paused = Paused {
+            interpreter: self,
+            debugger,
+            loc: stop.loc.clone(),
+        };
+        let resume = debugger.frontend.stopped(&stop, &mut paused);
+        let depth = self.frames.len();
+        let mut state = debugger.state.borrow_mut();
+        state.stopped = false;
+        state.mode = match resume {
+            Ok(Resume::Continue) => Mode::Continue,
+            Ok(Resume::StepIn) => Mode::StepIn,
+            Ok(Resume::StepOver) => Mode::StepOver(depth),
+            Ok(Resume::StepOut) => Mode::StepOut(depth),
+            Ok(Resume::Terminate) | Err(_) => Mode::Terminated,
+        };
+        match resume {
+            Ok(Resume::Terminate) => Err(Terminated.into()),
+            res => res.map(|_| ()),
+        }
+    }
+}
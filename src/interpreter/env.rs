@@ -35,22 +35,13 @@ impl Env {
         rc_cell_of(self)
     }
 
-    /*
-    fn lookup_with_env(this: &RcCell<Env>, ident: &str) -> Option<(RcCell<Env>, Object)> {
-        if let Some(obj) = Gc::clone(this).borrow().dict.get(ident) {
-            return Some((Gc::clone(this), obj.clone()));
-        }
-        this.borrow()
-            .outer
-            .as_ref()
-            .and_then(|o| Self::lookup_with_env(o, ident))
-    }
-
+    /// Looks up `ident` in this environment and then in the outer ones, as is
+    /// done for a global variable, which might be a builtin.
     #[must_use]
-    pub(crate)fn lookup(this: &RcCell<Env>, ident: &str) -> Option<Object> {
-        Self::lookup_with_env(this, ident).map(|(_, obj)| obj)
+    pub(crate) fn lookup(this: &MutCell<Env>, ident: &str) -> Option<Object> {
+        std::iter::successors(Some(Gc::clone(this)), |env| env.borrow().outer.clone())
+            .find_map(|env| Self::lookup_dict(&env, ident))
     }
-    */
 
     #[must_use]
     pub(crate) fn lookup_dict(this: &MutCell<Env>, ident: &str) -> Option<Object> {
@@ -1,14 +1,18 @@
-use anyhow::{anyhow, Context, Result};
+use std::cmp::Ordering;
+
+use anyhow::{anyhow, Result};
 use gc::Gc;
 use itertools::Itertools;
 use tap::prelude::*;
 
-use super::{Closure, Env, Instance, Interpreter, Object};
+use super::{map::Entries, Closure, Env, Instance, Interpreter, MapKey, Object};
 use crate::{
-    error::runtime_report,
+    bail,
+    error::{with_traceback, CallFrame, Code, Diagnostic},
     lexer::{Token, TokenType as Tk},
     parser::Expr,
-    runtime_bail,
+    report,
+    source::Span,
 };
 
 impl Interpreter {
@@ -20,12 +24,14 @@ impl Interpreter {
                 let dist = self.locals.get(&name).copied();
                 let ident = &name.lexeme;
                 self.lookup(&name)
-                    .with_context(|| {
-                        runtime_report(
-                            name.pos,
+                    .ok_or_else(|| {
+                        report!(
+                            Code::UndefinedVariable,
+                            name.span,
                             "while evaluating an Assignment expression",
-                            format!("identifier `{}` is undefined", name),
+                            "identifier `{name}` is undefined",
                         )
+                        .into()
                     })
                     .and_then(|_| {
                         let val = self.eval(*val)?;
@@ -38,110 +44,48 @@ impl Interpreter {
                     })
             }
             Expr::Binary { lhs, op, rhs } => {
-                #[allow(clippy::enum_glob_use)]
-                use Object::*;
-                Ok(match (op.ty, &self.eval(*lhs)?, &self.eval(*rhs)?) {
-                    (Tk::Plus, Str(lhs), Str(rhs)) => Str(format!("{lhs}{rhs}")),
-                    (Tk::Plus, Str(lhs), rhs) => Str(format!("{lhs}{rhs}")),
-                    (Tk::Plus, lhs, Str(rhs)) => Str(format!("{lhs}{rhs}")),
-                    (Tk::Plus, lhs @ (Bool(_) | Number(_)), rhs @ (Bool(_) | Number(_))) => {
-                        Number(lhs.try_conv::<f64>()? + rhs.try_conv::<f64>()?)
-                    }
-                    (Tk::Minus, lhs @ (Bool(_) | Number(_)), rhs @ (Bool(_) | Number(_))) => {
-                        Number(lhs.try_conv::<f64>()? - rhs.try_conv::<f64>()?)
-                    }
-                    (Tk::Star, lhs @ (Bool(_) | Number(_)), rhs @ (Bool(_) | Number(_))) => {
-                        Number(lhs.try_conv::<f64>()? * rhs.try_conv::<f64>()?)
-                    }
-                    (Tk::Slash, lhs @ (Bool(_) | Number(_)), rhs @ (Bool(_) | Number(_))) => {
-                        Number(lhs.try_conv::<f64>()? / rhs.try_conv::<f64>()?)
-                    }
-                    (Tk::Greater, lhs @ (Bool(_) | Number(_)), rhs @ (Bool(_) | Number(_))) => {
-                        Bool(lhs.try_conv::<f64>()? > rhs.try_conv::<f64>()?)
-                    }
-                    (
-                        Tk::GreaterEqual,
-                        lhs @ (Bool(_) | Number(_)),
-                        rhs @ (Bool(_) | Number(_)),
-                    ) => Bool(lhs.try_conv::<f64>()? >= rhs.try_conv::<f64>()?),
-                    (Tk::Less, lhs @ (Bool(_) | Number(_)), rhs @ (Bool(_) | Number(_))) => {
-                        Bool(lhs.try_conv::<f64>()? < rhs.try_conv::<f64>()?)
-                    }
-                    (Tk::LessEqual, lhs @ (Bool(_) | Number(_)), rhs @ (Bool(_) | Number(_))) => {
-                        Bool(lhs.try_conv::<f64>()? <= rhs.try_conv::<f64>()?)
-                    }
-                    (Tk::EqualEqual, lhs, rhs) => Bool(lhs == rhs),
-                    (Tk::BangEqual, lhs, rhs) => Bool(lhs != rhs),
-                    (ty, lhs, rhs) => runtime_bail!(
-                        op.pos,
-                        "while evaluating a Binary expression",
-                        "binary operator `{:?}` undefined for ({:?}, {:?})",
-                        ty,
-                        lhs,
-                        rhs,
-                    ),
-                })
+                let (lhs, rhs) = (self.eval(*lhs)?, self.eval(*rhs)?);
+                binary_op(op.ty, op.span, &lhs, &rhs)
             }
-            Expr::Call { callee, args, end } => {
-                let callee = self.eval(*callee)?;
-                let args: Vec<Object> = args.into_iter().map(|i| self.eval(i)).try_collect()?;
-                let res = match &callee {
-                    Object::NativeFn(clos) => {
-                        clos.clone().apply(self, args).with_context(|| {
-                            runtime_report(
-                                end.pos,
-                                "while evaluating a function Call expression",
-                                "",
-                            )
-                        })?
-                    }
-                    Object::ForeignFn(f) => f(args)?,
-                    Object::Class(c) => {
-                        let instance = Instance::from(c.clone());
-                        if let Some(it) = instance.class.method("init") {
-                            if let Object::NativeFn(clos) = &it {
-                                clos.clone().bind(instance.clone()).apply(self, args)?;
-                            } else {
-                                unreachable!();
-                            }
-                        } else if !args.is_empty() {
-                            runtime_bail!(
-                                end.pos,
-                                "while evaluating a new Class expression",
-                                "unexpected number of parameters (expected 0, got {})",
-                                args.len(),
-                            );
-                        }
-                        Object::Instance(instance)
-                    }
-                    obj => runtime_bail!(
-                        end.pos,
-                        "while evaluating a function Call expression",
-                        "the object `{}` is not callable",
-                        obj,
-                    ),
-                };
-                Ok(res)
+            expr @ (Expr::Call { .. } | Expr::Get { .. } | Expr::Index { .. }) => {
+                Ok(self.eval_chain(expr)?.unwrap_or_default())
             }
-            Expr::Get { obj, name } => {
-                let ctx = "while evaluating a Get expression";
-                let obj = self.eval(*obj)?;
-                if let Object::Instance(ref i) = obj {
-                    let lexeme = &name.lexeme;
-                    i.get(lexeme).with_context(|| {
-                        let err_msg =
-                            format!("property `{}` undefined for the given object", lexeme);
-                        runtime_report(name.pos, ctx, err_msg)
-                    })
-                } else {
-                    runtime_bail!(name.pos, ctx, "the object `{}` cannot have properties", obj);
+            Expr::Grouping(expr) => self.eval(*expr),
+            Expr::Interpolation(parts) => {
+                let parts: Vec<Object> = parts.into_iter().map(|i| self.eval(i)).try_collect()?;
+                Ok(Object::Str(parts.iter().map(Object::stringify).collect()))
+            }
+            Expr::IndexSet {
+                obj,
+                idx,
+                end,
+                op,
+                to,
+            } => {
+                let (obj, idx) = (self.eval(*obj)?, self.eval(*idx)?);
+                let mut to = self.eval(*to)?;
+                if let Some(op) = op {
+                    let old = get_index(end.span, &obj, &idx)?;
+                    to = binary_op(op.ty, op.span, &old, &to)?;
                 }
+                set_index(end.span, &obj, &idx, to.clone())?;
+                Ok(to)
             }
-            Expr::Grouping(expr) => self.eval(*expr),
-            Expr::Lambda { params, body } => {
-                Ok(Object::NativeFn(Closure::new(None, params, body, env)))
+            Expr::Lambda { kw, params, body } => {
+                let closure = Closure::new(None, kw.span, params, body, env, &self.globals);
+                Ok(Object::NativeFn(closure))
+            }
+            Expr::List(elems) => {
+                let elems: Vec<Object> = elems.into_iter().map(|i| self.eval(i)).try_collect()?;
+                Ok(Object::List(elems.into()))
             }
             Expr::Literal(lit) => Ok(lit.into()),
+            Expr::Map { entries, keys, .. } => {
+                let entries: Vec<(Span, Object, Object)> = (keys.into_iter().zip(entries))
+                    .map(|(span, (k, v))| anyhow::Ok((span, self.eval(k)?, self.eval(v)?)))
+                    .try_collect()?;
+                new_map(entries)
+            }
             Expr::Logical { lhs, op, rhs } => match op.ty {
                 Tk::And => {
                     let lhs = self.eval(*lhs)?;
@@ -159,25 +103,41 @@ impl Interpreter {
                         self.eval(*rhs)
                     }
                 }
+                Tk::QuestionQuestion => match self.eval(*lhs)? {
+                    Object::Nil => self.eval(*rhs),
+                    lhs => Ok(lhs),
+                },
                 _ => unreachable!(),
             },
-            Expr::Set { obj, name, to } => {
+            Expr::Set { obj, name, op, to } => {
                 let ctx = "while evaluating a Set expression";
                 let obj = self.eval(*obj)?;
                 if let Object::Instance(ref i) = obj {
                     let lexeme = &name.lexeme;
-                    let to = self.eval(*to)?;
+                    let mut to = self.eval(*to)?;
+                    if let Some(op) = op {
+                        let old = get_property(name.span, &obj, lexeme)?;
+                        to = binary_op(op.ty, op.span, &old, &to)?;
+                    }
                     i.set(lexeme, to.clone());
                     Ok(to)
                 } else {
-                    runtime_bail!(name.pos, ctx, "the object `{}` cannot have properties", obj)
+                    bail!(
+                        Code::TypeMismatch,
+                        name.span,
+                        ctx,
+                        "the object `{}` cannot have properties",
+                        obj,
+                    )
                 }
             }
             Expr::Super { kw, method } => {
                 let ctx = "while evaluating a superclass method";
-                let distance = *self.locals.get(&kw).with_context(|| {
-                    runtime_report(kw.pos, ctx, "identifier `super` is undefined")
-                })?;
+                let undefined = |ident| {
+                    let msg = format!("identifier `{ident}` is undefined");
+                    anyhow!(report!(Code::UndefinedVariable, kw.span, ctx, msg))
+                };
+                let distance = *self.locals.get(&kw).ok_or_else(|| undefined("super"))?;
                 let outer_err = |dist| {
                     anyhow!(
                         "Internal Error while looking up `super`: distance ({}) out of range",
@@ -188,67 +148,181 @@ impl Interpreter {
                 // where `super` appears, and `super` to its direct superclass.
                 let this_env =
                     &Env::outer_nth(env, distance - 1).ok_or_else(|| outer_err(distance - 1))?;
-                let this = Env::lookup_dict(this_env, "this").with_context(|| {
-                    runtime_report(kw.pos, ctx, "identifier `this` is undefined")
-                })?;
+                let this = Env::lookup_dict(this_env, "this").ok_or_else(|| undefined("this"))?;
                 let sup_env = &Env::outer_nth(this_env, 1).ok_or_else(|| outer_err(distance))?;
-                let sup = Env::lookup_dict(sup_env, "super").with_context(|| {
-                    runtime_report(kw.pos, ctx, "identifier `super` is undefined")
-                })?;
+                let sup = Env::lookup_dict(sup_env, "super").ok_or_else(|| undefined("super"))?;
                 match (&this, &sup) {
                     (Object::Instance(this), Object::Class(sup)) => {
                         let lexeme = &method.lexeme;
-                        let method = sup.method(lexeme).with_context(|| {
-                            let err_msg =
-                                format!("property `{}` undefined for the given object", lexeme);
-                            runtime_report(method.pos, ctx, err_msg)
+                        let method = sup.method(lexeme).ok_or_else(|| {
+                            anyhow!(report!(
+                                Code::UndefinedProperty,
+                                method.span,
+                                ctx,
+                                "property `{lexeme}` undefined for the given object",
+                            ))
                         })?;
-                        if let Object::NativeFn(clos) = &method {
-                            Ok(Object::NativeFn(clos.clone().bind(this.clone())))
-                        } else {
-                            unreachable!()
-                        }
+                        Ok(method.bind(this.clone()))
                     }
                     _ => unreachable!(),
                 }
             }
-            Expr::This(kw) => self.lookup(&kw).with_context(|| {
-                runtime_report(
-                    kw.pos,
+            Expr::Ternary {
+                cond,
+                then_expr,
+                else_expr,
+            } => {
+                if self.eval(*cond)?.to_bool() {
+                    self.eval(*then_expr)
+                } else {
+                    self.eval(*else_expr)
+                }
+            }
+            Expr::This(kw) => self.lookup(&kw).ok_or_else(|| {
+                anyhow!(report!(
+                    Code::UndefinedVariable,
+                    kw.span,
                     "while evaluating a This expression",
                     "identifier `this` is undefined",
-                )
+                ))
             }),
-            Expr::Unary { op, rhs } => match op.ty {
-                Tk::Bang => Ok(Object::Bool(!self.eval(*rhs)?.to_bool())),
-                Tk::Minus => {
-                    let rhs = &self.eval(*rhs)?;
-                    let rhs = -rhs.try_conv::<f64>().with_context(|| {
-                        let err_msg = format!(
-                            "unary operator `{:?}` undefined for the given object",
-                            op.ty
-                        );
-                        runtime_report(op.pos, "while evaluating an Unary expression", err_msg)
-                    })?;
-                    Ok(Object::Number(rhs))
-                }
-                _ => unreachable!(),
-            },
-            Expr::Variable(name) => self.lookup(&name).with_context(|| {
-                runtime_report(
-                    name.pos,
+            Expr::Unary { op, rhs } => {
+                let rhs = self.eval(*rhs)?;
+                unary_op(op.ty, op.span, &rhs)
+            }
+            Expr::Variable(name) => self.lookup(&name).ok_or_else(|| {
+                anyhow!(report!(
+                    Code::UndefinedVariable,
+                    name.span,
                     "while evaluating a Variable expression",
-                    format!("identifier `{}` is undefined", name),
-                )
+                    "identifier `{name}` is undefined",
+                ))
             }),
         }
     }
 
+    /// Evaluates a link in a chain of calls, properties and indices, e.g.
+    /// `a?.b.c()[d]`, returning `None` if the chain has been short-circuited
+    /// by an optional chaining on `nil`.
+    fn eval_chain(&mut self, expr: Expr) -> Result<Option<Object>> {
+        let res = match expr {
+            Expr::Call { callee, args, end } => {
+                let Some(callee) = self.eval_chain(*callee)? else {
+                    return Ok(None);
+                };
+                let args: Vec<Object> = args.into_iter().map(|i| self.eval(i)).try_collect()?;
+                self.call(&callee, args, end.span)?
+            }
+            Expr::Get {
+                obj,
+                name,
+                optional,
+            } => match self.eval_chain(*obj)? {
+                None => return Ok(None),
+                Some(Object::Nil) if optional => return Ok(None),
+                Some(obj) => get_property(name.span, &obj, &name.lexeme)?,
+            },
+            Expr::Index { obj, idx, end } => {
+                let Some(obj) = self.eval_chain(*obj)? else {
+                    return Ok(None);
+                };
+                let idx = self.eval(*idx)?;
+                get_index(end.span, &obj, &idx)?
+            }
+            expr => self.eval(expr)?,
+        };
+        Ok(Some(res))
+    }
+
+    /// Applies the `callee` to the given arguments, where `span` is the span of
+    /// the call site memorized for error reports.
+    pub(crate) fn call(
+        &mut self,
+        callee: &Object,
+        args: Vec<Object>,
+        span: Span,
+    ) -> Result<Object> {
+        let res = match callee {
+            Object::NativeFn(clos) => {
+                check_arity(
+                    span,
+                    "while evaluating a function Call expression",
+                    clos.params.len(),
+                    args.len(),
+                )?;
+                self.apply_in_frame(clos.clone(), args, span)?
+            }
+            Object::ForeignFn(foreign) => {
+                let ctx = "while evaluating a foreign function Call expression";
+                if !foreign.arity.accepts(args.len()) {
+                    bail!(
+                        Code::ArityMismatch,
+                        span,
+                        ctx,
+                        "unexpected number of parameters (expected {}, got {})",
+                        foreign.arity,
+                        args.len(),
+                    );
+                }
+                foreign
+                    .apply(args)
+                    .map_err(|e| foreign_error(e, span, ctx))?
+            }
+            Object::Class(c) => {
+                let ctx = "while evaluating a new Class expression";
+                let instance = Instance::from(c.clone());
+                if let Some(it) = instance.class.method("init") {
+                    if let Object::NativeFn(clos) = &it {
+                        check_arity(span, ctx, clos.params.len(), args.len())?;
+                        let init = clos.clone().bind(instance.clone());
+                        self.apply_in_frame(init, args, span)?;
+                    } else {
+                        unreachable!();
+                    }
+                } else {
+                    check_arity(span, ctx, 0, args.len())?;
+                }
+                Object::Instance(instance)
+            }
+            obj => bail!(
+                Code::TypeMismatch,
+                span,
+                "while evaluating a function Call expression",
+                "the object `{}` is not callable",
+                obj,
+            ),
+        };
+        Ok(res)
+    }
+
+    /// Applies the closure `clos` in a new call frame, where `span` is the span
+    /// of the call site memorized for error reports.
+    fn apply_in_frame(&mut self, clos: Closure, args: Vec<Object>, span: Span) -> Result<Object> {
+        self.frames.push(CallFrame {
+            name: clos.name.clone().unwrap_or_else(|| "<lambda>".into()),
+            call_site: span,
+            defn: clos.defn,
+        });
+        let profiler = self.profiler.clone();
+        if let Some(profiler) = &profiler {
+            // This is a safe unwrap since a frame has just been pushed.
+            profiler.enter(&self.frames.last().unwrap().name, clos.defn);
+        }
+        let res = clos
+            .apply(self, args)
+            .map_err(|e| with_traceback(e, || self.frames.clone()));
+        if let Some(profiler) = &profiler {
+            profiler.exit();
+        }
+        self.frames.pop();
+        res
+    }
+
     /// Look up a variable definition in the current evaluation context.
     fn lookup(&self, name: &Token) -> Option<Object> {
         let ident = &name.lexeme;
         self.locals.get(name).map_or_else(
-            || Env::lookup_dict(&self.globals, ident),
+            || Env::lookup(&self.globals, ident),
             |&dist| Env::outer_nth(&self.env, dist).and_then(|it| Env::lookup_dict(&it, ident)),
         )
     }
@@ -265,3 +339,298 @@ impl Interpreter {
         Ok(())
     }
 }
+
+/// Checks that a function expecting `expected` arguments is called with `got`
+/// ones.
+pub(crate) fn check_arity(span: Span, ctx: &str, expected: usize, got: usize) -> Result<()> {
+    if expected != got {
+        bail!(
+            Code::ArityMismatch,
+            span,
+            ctx,
+            "unexpected number of parameters (expected {}, got {})",
+            expected,
+            got,
+        );
+    }
+    Ok(())
+}
+
+/// Turns an error raised by a foreign function called at `span` into a
+/// [`Diagnostic`], unless it is one already.
+pub(crate) fn foreign_error(e: anyhow::Error, span: Span, ctx: &str) -> anyhow::Error {
+    if e.is::<Diagnostic>() {
+        return e;
+    }
+    report!(Code::Foreign, span, ctx, e).into()
+}
+
+/// Gets the property `name` of `obj`, where `span` is the span of the
+/// property name memorized for error reports.
+pub(crate) fn get_property(span: Span, obj: &Object, name: &str) -> Result<Object> {
+    let ctx = "while evaluating a Get expression";
+    let prop = match obj {
+        Object::Instance(i) => i.get(name),
+        Object::List(list) => list.method(name),
+        Object::Map(map) => map.method(name),
+        Object::Module(module) => module
+            .get(name)
+            .ok_or_else(|| {
+                anyhow!(report!(
+                    Code::UndefinedProperty,
+                    span,
+                    ctx,
+                    "global `{name}` undefined in module `{}`",
+                    module.name,
+                ))
+            })
+            .map(Some)?,
+        obj => bail!(
+            Code::TypeMismatch,
+            span,
+            ctx,
+            "the object `{}` cannot have properties",
+            obj,
+        ),
+    };
+    prop.ok_or_else(|| {
+        anyhow!(report!(
+            Code::UndefinedProperty,
+            span,
+            ctx,
+            "property `{name}` undefined for the given object",
+        ))
+    })
+}
+
+/// Evaluates `obj[idx]`, where `span` is the span of the subscript memorized
+/// for error reports.
+pub(crate) fn get_index(span: Span, obj: &Object, idx: &Object) -> Result<Object> {
+    let ctx = "while evaluating an Index expression";
+    match obj {
+        Object::List(list) => list.get(idx),
+        Object::Map(map) => map.get(idx),
+        obj => bail!(
+            Code::TypeMismatch,
+            span,
+            ctx,
+            "the object `{}` cannot be indexed",
+            obj,
+        ),
+    }
+    .map_err(|e| bad_index(e, span, ctx))
+}
+
+/// Evaluates `obj[idx] = to`, where `span` is the span of the subscript
+/// memorized for error reports.
+pub(crate) fn set_index(span: Span, obj: &Object, idx: &Object, to: Object) -> Result<()> {
+    let ctx = "while evaluating an IndexSet expression";
+    match obj {
+        Object::List(list) => list.set(idx, to),
+        Object::Map(map) => map.set(idx, to),
+        obj => bail!(
+            Code::TypeMismatch,
+            span,
+            ctx,
+            "the object `{}` cannot be indexed",
+            obj,
+        ),
+    }
+    .map_err(|e| bad_index(e, span, ctx))
+}
+
+fn bad_index(e: anyhow::Error, span: Span, ctx: &str) -> anyhow::Error {
+    if e.is::<Diagnostic>() {
+        return e;
+    }
+    report!(Code::BadIndex, span, ctx, e).into()
+}
+
+/// Creates a new Map with the given `(span, key, value)` entries, where
+/// `span` is the span of the key memorized for error reports.
+pub(crate) fn new_map(entries: impl IntoIterator<Item = (Span, Object, Object)>) -> Result<Object> {
+    let entries: Entries = (entries.into_iter())
+        .map(|(span, k, v)| {
+            let k = MapKey::try_from(&k)
+                .map_err(|e| report!(Code::BadIndex, span, "while evaluating a Map literal", e))?;
+            anyhow::Ok((k, v))
+        })
+        .try_collect()?;
+    Ok(Object::Map(entries.into()))
+}
+
+/// The value of a numeric operand, where `Bool`s count as `Int`s.
+#[derive(Debug, Clone, Copy)]
+enum Num {
+    Int(i64),
+    Float(f64),
+}
+
+impl Num {
+    fn of(obj: &Object) -> Option<Self> {
+        match obj {
+            Object::Bool(b) => Some(Self::Int(i64::from(*b))),
+            Object::Int(i) => Some(Self::Int(*i)),
+            Object::Number(n) => Some(Self::Float(*n)),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn to_f64(self) -> f64 {
+        match self {
+            Self::Int(i) => i as f64,
+            Self::Float(n) => n,
+        }
+    }
+
+    /// Applies an integer operation if both operands are `Int`s, or a float
+    /// one otherwise, where `None` from the former means an overflow.
+    fn apply(
+        self,
+        rhs: Self,
+        int_op: impl FnOnce(i64, i64) -> Option<i64>,
+        float_op: impl FnOnce(f64, f64) -> f64,
+    ) -> Option<Object> {
+        match (self, rhs) {
+            (Self::Int(lhs), Self::Int(rhs)) => int_op(lhs, rhs).map(Object::Int),
+            (lhs, rhs) => Some(Object::Number(float_op(lhs.to_f64(), rhs.to_f64()))),
+        }
+    }
+}
+
+impl PartialEq for Num {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Num {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Int(lhs), Self::Int(rhs)) => lhs.partial_cmp(rhs),
+            (lhs, rhs) => lhs.to_f64().partial_cmp(&rhs.to_f64()),
+        }
+    }
+}
+
+/// Evaluates the binary operator `ty` at `span` on the given operands.
+///
+/// Arithmetic on `Int`s is exact and checked for overflows, except for `/`
+/// which always results in a `Number`, and for `**` with a negative exponent.
+/// Mixing `Int`s with `Number`s promotes the former to the latter, while
+/// bitwise operators are defined for `Int`s only.
+pub(crate) fn binary_op(ty: Tk, span: Span, lhs: &Object, rhs: &Object) -> Result<Object> {
+    #[allow(clippy::enum_glob_use)]
+    use Object::*;
+    let ctx = "while evaluating a Binary expression";
+    macro_rules! bail_mismatch {
+        () => {
+            bail!(
+                Code::TypeMismatch,
+                span,
+                ctx,
+                "binary operator `{:?}` undefined for ({:?}, {:?})",
+                ty,
+                lhs,
+                rhs,
+            )
+        };
+    }
+    let res = match (ty, lhs, rhs) {
+        (Tk::Plus, Str(lhs), Str(rhs)) => Some(Str(format!("{lhs}{rhs}"))),
+        (Tk::Plus, Str(lhs), rhs) => Some(Str(format!("{lhs}{rhs}"))),
+        (Tk::Plus, lhs, Str(rhs)) => Some(Str(format!("{lhs}{rhs}"))),
+        (Tk::EqualEqual, lhs, rhs) => Some(Bool(lhs == rhs)),
+        (Tk::BangEqual, lhs, rhs) => Some(Bool(lhs != rhs)),
+        _ => {
+            let (Some(l), Some(r)) = (Num::of(lhs), Num::of(rhs)) else {
+                bail_mismatch!();
+            };
+            match (ty, l, r) {
+                (Tk::Percent, Num::Int(_), Num::Int(0)) => bail!(
+                    Code::DivisionByZero,
+                    span,
+                    ctx,
+                    "binary operator `{:?}` divides {} by zero",
+                    ty,
+                    lhs,
+                ),
+                (Tk::StarStar, Num::Int(l), Num::Int(r)) if r >= 0 => u32::try_from(r)
+                    .ok()
+                    .and_then(|r| l.checked_pow(r))
+                    .map(Int),
+                (Tk::Amp, Num::Int(l), Num::Int(r)) => Some(Int(l & r)),
+                (Tk::Pipe, Num::Int(l), Num::Int(r)) => Some(Int(l | r)),
+                (Tk::Caret, Num::Int(l), Num::Int(r)) => Some(Int(l ^ r)),
+                (Tk::LessLess, Num::Int(l), Num::Int(r)) => u32::try_from(r)
+                    .ok()
+                    .and_then(|r| l.checked_shl(r))
+                    .map(Int),
+                (Tk::GreaterGreater, Num::Int(l), Num::Int(r)) => u32::try_from(r)
+                    .ok()
+                    .and_then(|r| l.checked_shr(r))
+                    .map(Int),
+                (Tk::Amp | Tk::Pipe | Tk::Caret | Tk::LessLess | Tk::GreaterGreater, ..) => {
+                    bail_mismatch!()
+                }
+                (Tk::Plus, ..) => l.apply(r, i64::checked_add, |l, r| l + r),
+                (Tk::Minus, ..) => l.apply(r, i64::checked_sub, |l, r| l - r),
+                (Tk::Star, ..) => l.apply(r, i64::checked_mul, |l, r| l * r),
+                (Tk::Slash, ..) => Some(Number(l.to_f64() / r.to_f64())),
+                (Tk::Percent, ..) => l.apply(r, i64::checked_rem, |l, r| l % r),
+                (Tk::StarStar, ..) => Some(Number(l.to_f64().powf(r.to_f64()))),
+                (Tk::Greater, ..) => Some(Bool(l > r)),
+                (Tk::GreaterEqual, ..) => Some(Bool(l >= r)),
+                (Tk::Less, ..) => Some(Bool(l < r)),
+                (Tk::LessEqual, ..) => Some(Bool(l <= r)),
+                _ => unreachable!(),
+            }
+        }
+    };
+    res.ok_or_else(|| {
+        report!(
+            Code::IntOverflow,
+            span,
+            ctx,
+            "binary operator `{:?}` overflows for ({}, {})",
+            ty,
+            lhs,
+            rhs,
+        )
+        .into()
+    })
+}
+
+/// Evaluates the unary operator `ty` at `span` on the given operand.
+pub(crate) fn unary_op(ty: Tk, span: Span, rhs: &Object) -> Result<Object> {
+    match (ty, rhs) {
+        (Tk::Bang, rhs) => Ok(Object::Bool(!rhs.to_bool())),
+        (Tk::Minus, Object::Number(n)) => Ok(Object::Number(-n)),
+        (Tk::Minus, rhs @ (Object::Bool(_) | Object::Int(_))) => {
+            let neg = rhs.try_conv::<i64>()?.checked_neg();
+            neg.map(Object::Int).ok_or_else(|| {
+                report!(
+                    Code::IntOverflow,
+                    span,
+                    "while evaluating an Unary expression",
+                    "unary operator `{:?}` overflows for {}",
+                    ty,
+                    rhs,
+                )
+                .into()
+            })
+        }
+        (Tk::Tilde, rhs @ (Object::Bool(_) | Object::Int(_))) => {
+            Ok(Object::Int(!rhs.try_conv::<i64>()?))
+        }
+        (Tk::Minus | Tk::Tilde, _) => bail!(
+            Code::TypeMismatch,
+            span,
+            "while evaluating an Unary expression",
+            "unary operator `{:?}` undefined for the given object",
+            ty,
+        ),
+        _ => unreachable!(),
+    }
+}
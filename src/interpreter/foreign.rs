@@ -0,0 +1,120 @@
+use std::{
+    fmt::{Debug, Display},
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use anyhow::Result;
+use gc::{Finalize, Trace};
+use uuid::Uuid;
+
+use super::Object;
+
+/// The number of arguments accepted by a [`Foreign`] function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    /// Exactly `n` arguments.
+    Fixed(usize),
+    /// At least `n` arguments.
+    Variadic(usize),
+    /// `min` required arguments, followed by optional ones up to `max`
+    /// arguments in total.
+    Optional { min: usize, max: usize },
+}
+
+impl Arity {
+    #[must_use]
+    pub fn accepts(self, len: usize) -> bool {
+        match self {
+            Arity::Fixed(n) => len == n,
+            Arity::Variadic(min) => len >= min,
+            Arity::Optional { min, max } => (min..=max).contains(&len),
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Fixed(n) => write!(f, "{n}"),
+            Arity::Variadic(min) => write!(f, "at least {min}"),
+            Arity::Optional { min, max } => write!(f, "{min} to {max}"),
+        }
+    }
+}
+
+/// The signature of a host function callable from Lox.
+pub type ForeignFnBody = dyn Fn(Vec<Object>) -> Result<Object>;
+
+/// A host (Rust) function registered in the interpreter.
+#[derive(Clone, Trace, Finalize)]
+pub struct Foreign {
+    #[unsafe_ignore_trace]
+    pub(crate) uid: Uuid,
+    pub(crate) name: String,
+    #[unsafe_ignore_trace]
+    pub(crate) arity: Arity,
+    /// The receiver prepended to the arguments, if this is a bound method.
+    pub(crate) this: Option<Box<Object>>,
+    // The captured host state is opaque to the garbage collector, so it should never contain an
+    // `Object`: dropping it during a collection would panic. Use `this` to carry one instead.
+    #[unsafe_ignore_trace]
+    fun: Rc<ForeignFnBody>,
+}
+
+impl Foreign {
+    pub(crate) fn new(
+        name: &str,
+        arity: Arity,
+        fun: impl Fn(Vec<Object>) -> Result<Object> + 'static,
+    ) -> Self {
+        Self {
+            uid: Uuid::new_v4(),
+            name: name.into(),
+            arity,
+            this: None,
+            fun: Rc::new(fun),
+        }
+    }
+
+    /// Binds `this` as the receiver of this method, which is then passed to
+    /// the host function as its first argument.
+    #[must_use]
+    pub(crate) fn bind(mut self, this: Object) -> Self {
+        self.this = Some(Box::new(this));
+        self
+    }
+
+    /// Applies this function to the given arguments, whose number is supposed
+    /// to be checked against `self.arity` beforehand.
+    pub(crate) fn apply(&self, mut args: Vec<Object>) -> Result<Object> {
+        if let Some(this) = &self.this {
+            args.insert(0, (**this).clone());
+        }
+        (self.fun)(args)
+    }
+}
+
+impl Debug for Foreign {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Foreign")
+            .field("uid", &self.uid)
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Hash for Foreign {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.uid.hash(state);
+    }
+}
+
+impl PartialEq for Foreign {
+    fn eq(&self, other: &Self) -> bool {
+        self.uid == other.uid
+    }
+}
+
+impl Eq for Foreign {}
@@ -1,26 +1,91 @@
-use derive_more::{Display, Error, From};
+use std::collections::HashMap;
 
-use super::Object;
+use super::{Class, Instance, Object};
+use crate::{
+    error::{Code, Diagnostic},
+    report,
+    source::{SourceMap, Span},
+};
 
-#[derive(Debug, Error, Display, Clone, Copy)]
-#[display(fmt = "Internal Error: found `break` out of loop context")]
-pub(crate) struct BreakMarker;
+/// How the execution of a statement completes, which is `Normal` unless it
+/// jumps.
+///
+/// `Break` and `Continue` carry the label of the loop they target, if any.
+///
+/// Errors, including the exceptions thrown, are not jumps, and are propagated
+/// as the `Err` of a `Result<ControlFlow>` instead.
+#[derive(Debug, Clone, Default)]
+#[must_use]
+pub(crate) enum ControlFlow {
+    #[default]
+    Normal,
+    Break(Option<String>),
+    Continue(Option<String>),
+    Return(Object),
+}
 
-#[derive(Debug, Error, Display, Clone, Copy)]
-#[display(fmt = "Internal Error: found `continue` out of loop context")]
-pub(crate) struct ContinueMarker;
+/// The values thrown by `throw` and not caught yet.
+///
+/// The values cannot be put in the errors themselves, which must be `Send`,
+/// so each error reporting a thrown value carries its key instead.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Thrown {
+    next: u64,
+    vals: HashMap<u64, Object>,
+}
 
-#[derive(Debug, Error, Display, From)]
-#[display(fmt = "Internal Error: found `return` out of function context")]
-pub(crate) struct ReturnMarker(#[error(not(source))] pub(crate) Object);
+impl Thrown {
+    /// Returns the error reporting the value `obj` thrown at `span`, keeping
+    /// the value until it is caught.
+    pub(crate) fn throw(&mut self, obj: Object, span: Span) -> anyhow::Error {
+        let mut diag = report!(
+            Code::UncaughtException,
+            span,
+            "while executing a Throw statement",
+            "uncaught exception `{obj}`",
+        );
+        diag.exception = Some(self.next);
+        self.vals.insert(self.next, obj);
+        self.next += 1;
+        diag.into()
+    }
 
-// ! This is a HIGHLY DANGEROUS hack and non-idiomatic Rust.
-// SAFETY: Those `unsafe impl`s are required because `ReturnMarker` abuses
-// `anyhow::Error` to return a value in our interpreter, however `anyhow::Error`
-// requires `Send + Sync` to work.
-// It's safe to do so here because:
-// - Those types are `pub(crate)` only;
-// - Our interpreter won't be sending `ReturnMarker`s across thread boundaries.
-// See: <https://github.com/dtolnay/anyhow/issues/81>
-unsafe impl Send for ReturnMarker {}
-unsafe impl Sync for ReturnMarker {}
+    /// Forgets the values never caught, once their errors have reached the
+    /// top level.
+    pub(crate) fn clear(&mut self) {
+        self.vals.clear();
+    }
+}
+
+/// Turns the error `e` raised in a `try` block into the exception to be bound
+/// in its `catch` clause, or gives it back if it cannot be caught, e.g. when it
+/// is a syntax error.
+///
+/// The value of a `throw` is taken from `thrown` and caught as is, while any
+/// other runtime error is caught as an `Error` instance with its `message`,
+/// `kind`, `line` and `column`.
+pub(crate) fn catch(
+    e: anyhow::Error,
+    thrown: &mut Thrown,
+    sources: &SourceMap,
+) -> Result<Object, anyhow::Error> {
+    let Some(diag) = e
+        .downcast_ref::<Diagnostic>()
+        .filter(|it| it.code.is_runtime())
+    else {
+        return Err(e);
+    };
+    if let Some(obj) = diag.exception.and_then(|it| thrown.vals.remove(&it)) {
+        return Ok(obj);
+    }
+    let error = Instance::from(Class::new("Error", None, HashMap::new()));
+    error.set("message", Object::Str(diag.msg.clone()));
+    error.set("kind", Object::Str(format!("{:?}", diag.code)));
+    let pos = (sources.file(diag.primary.start)).map(|it| it.pos(diag.primary.start));
+    let (line, column) = pos.map_or((Object::Nil, Object::Nil), |(line, col)| {
+        (Object::from(line), Object::from(col))
+    });
+    error.set("line", line);
+    error.set("column", column);
+    Ok(Object::Instance(error))
+}
@@ -0,0 +1,126 @@
+use std::hash::{Hash, Hasher};
+
+use anyhow::{anyhow, bail, Result};
+use gc::{Finalize, Trace};
+use uuid::Uuid;
+
+use super::{Arity, Foreign, MutCell, Object};
+use crate::util::rc_cell_of;
+
+/// A growable, mutable sequence of objects shared by reference.
+#[derive(Debug, Clone, Trace, Finalize)]
+pub struct List {
+    #[unsafe_ignore_trace]
+    pub(crate) uid: Uuid,
+    pub(crate) elems: MutCell<Vec<Object>>,
+}
+
+impl From<Vec<Object>> for List {
+    fn from(elems: Vec<Object>) -> Self {
+        Self {
+            uid: Uuid::new_v4(),
+            elems: rc_cell_of(elems),
+        }
+    }
+}
+
+impl List {
+    #[must_use]
+    pub(crate) fn len(&self) -> usize {
+        self.elems.borrow().len()
+    }
+
+    /// Gets the element at `idx`, which should be an integer within bounds.
+    pub(crate) fn get(&self, idx: &Object) -> Result<Object> {
+        let idx = self.index(idx)?;
+        Ok(self.elems.borrow()[idx].clone())
+    }
+
+    /// Sets the element at `idx`, which should be an integer within bounds.
+    pub(crate) fn set(&self, idx: &Object, to: Object) -> Result<()> {
+        let idx = self.index(idx)?;
+        self.elems.borrow_mut()[idx] = to;
+        Ok(())
+    }
+
+    fn index(&self, idx: &Object) -> Result<usize> {
+        let len = self.len();
+        let Some(i) = as_index(idx) else {
+            bail!("List index must be an integer, got `{idx}`");
+        };
+        match usize::try_from(i) {
+            Ok(i) if i < len => Ok(i),
+            _ => bail!("index `{idx}` out of bounds for List of length {len}"),
+        }
+    }
+
+    /// Returns the built-in method `name` with this list bound as the receiver.
+    #[must_use]
+    pub(crate) fn method(&self, name: &str) -> Option<Object> {
+        let foreign = match name {
+            "push" => Foreign::new(name, Arity::Fixed(1), |mut args| {
+                let to = args.pop().unwrap();
+                this(&args).elems.borrow_mut().push(to);
+                Ok(Object::Nil)
+            }),
+            "pop" => Foreign::new(name, Arity::Fixed(0), |args| {
+                let popped = this(&args).elems.borrow_mut().pop();
+                popped.ok_or_else(|| anyhow!("cannot `pop` from an empty List"))
+            }),
+            "len" => Foreign::new(name, Arity::Fixed(0), |args| {
+                Ok(Object::from(this(&args).len()))
+            }),
+            "slice" => Foreign::new(name, Arity::Optional { min: 1, max: 2 }, |args| {
+                let this = this(&args);
+                let len = this.len();
+                let bound = |arg: &Object| match as_index(arg).map(usize::try_from) {
+                    Some(Ok(i)) => Ok(i),
+                    _ => bail!("`slice` expected a non-negative integer, got `{arg}`"),
+                };
+                let start = bound(&args[1])?;
+                let end = args.get(2).map_or(Ok(len), bound)?;
+                if start > end || end > len {
+                    bail!("`slice` range {start}..{end} out of bounds for List of length {len}");
+                }
+                let elems = this.elems.borrow()[start..end].to_vec();
+                Ok(Object::List(elems.into()))
+            }),
+            _ => return None,
+        };
+        Some(Object::ForeignFn(foreign.bind(Object::List(self.clone()))))
+    }
+}
+
+/// Gets the integral value of `idx` to be used as an index, which might be an
+/// `Int` or an integral `Number`.
+pub(crate) fn as_index(idx: &Object) -> Option<i64> {
+    match idx {
+        Object::Int(i) => Some(*i),
+        #[allow(clippy::cast_possible_truncation)]
+        // The cast saturates, so huge indices are still out of bounds.
+        Object::Number(n) if n.fract() == 0. => Some(*n as i64),
+        _ => None,
+    }
+}
+
+/// Gets the receiver of a bound List method.
+fn this(args: &[Object]) -> &List {
+    match &args[0] {
+        Object::List(list) => list,
+        _ => unreachable!(),
+    }
+}
+
+impl Hash for List {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.uid.hash(state);
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        self.uid == other.uid
+    }
+}
+
+impl Eq for List {}
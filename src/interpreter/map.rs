@@ -0,0 +1,178 @@
+use std::hash::{Hash, Hasher};
+
+use anyhow::{bail, Result};
+use derive_more::{Deref, DerefMut};
+use gc::{custom_trace, Finalize, Trace};
+use indexmap::IndexMap;
+use itertools::Itertools;
+use uuid::Uuid;
+
+use super::{object::int_eq_number, Arity, Class, Foreign, Instance, MutCell, Object};
+use crate::util::rc_cell_of;
+
+/// A mutable mapping from hashable objects to objects shared by reference,
+/// remembering the insertion order of its keys.
+#[derive(Debug, Clone, Trace, Finalize)]
+pub struct Map {
+    #[unsafe_ignore_trace]
+    pub(crate) uid: Uuid,
+    pub(crate) entries: MutCell<Entries>,
+}
+
+#[derive(Debug, Default, Clone, Deref, DerefMut, Finalize)]
+pub(crate) struct Entries(IndexMap<MapKey, Object>);
+
+unsafe impl Trace for Entries {
+    custom_trace!(this, {
+        for (k, v) in &this.0 {
+            mark(k);
+            mark(v);
+        }
+    });
+}
+
+/// An object used as a key in a [`Map`].
+///
+/// Primitives are hashed by value, while classes and instances are hashed by
+/// identity.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Trace, Finalize)]
+pub(crate) enum MapKey {
+    Nil,
+    Bool(bool),
+    /// An `Int`, or an integral `Number` in the range of `Int`, so that keys
+    /// equal to each other are the same.
+    Int(i64),
+    /// The bits of a non-integral, non-NaN `Number`.
+    Number(u64),
+    Str(String),
+    Class(Class),
+    Instance(Instance),
+}
+
+impl TryFrom<&Object> for MapKey {
+    type Error = anyhow::Error;
+
+    fn try_from(obj: &Object) -> Result<Self, Self::Error> {
+        Ok(match obj {
+            Object::Nil => Self::Nil,
+            Object::Bool(b) => Self::Bool(*b),
+            Object::Int(i) => Self::Int(*i),
+            #[allow(clippy::cast_possible_truncation)]
+            Object::Number(n) if int_eq_number(*n as i64, *n) => Self::Int(*n as i64),
+            Object::Number(n) if !n.is_nan() => Self::Number(n.to_bits()),
+            Object::Str(s) => Self::Str(s.clone()),
+            Object::Class(c) => Self::Class(c.clone()),
+            Object::Instance(i) => Self::Instance(i.clone()),
+            obj => bail!("the object `{obj}` cannot be used as a Map key"),
+        })
+    }
+}
+
+impl From<&MapKey> for Object {
+    fn from(key: &MapKey) -> Self {
+        match key {
+            MapKey::Nil => Self::Nil,
+            MapKey::Bool(b) => Self::Bool(*b),
+            MapKey::Int(i) => Self::Int(*i),
+            MapKey::Number(bits) => Self::Number(f64::from_bits(*bits)),
+            MapKey::Str(s) => Self::Str(s.clone()),
+            MapKey::Class(c) => Self::Class(c.clone()),
+            MapKey::Instance(i) => Self::Instance(i.clone()),
+        }
+    }
+}
+
+impl From<Entries> for Map {
+    fn from(entries: Entries) -> Self {
+        Self {
+            uid: Uuid::new_v4(),
+            entries: rc_cell_of(entries),
+        }
+    }
+}
+
+impl FromIterator<(MapKey, Object)> for Entries {
+    fn from_iter<T: IntoIterator<Item = (MapKey, Object)>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Map {
+    #[must_use]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    /// Gets the value associated with `key`, which should be present.
+    pub(crate) fn get(&self, key: &Object) -> Result<Object> {
+        let found = self.entries.borrow().get(&MapKey::try_from(key)?).cloned();
+        match found {
+            Some(val) => Ok(val),
+            None => bail!("key `{key}` not found in Map"),
+        }
+    }
+
+    /// Associates `to` with `key`, overwriting the previous value if any.
+    pub(crate) fn set(&self, key: &Object, to: Object) -> Result<()> {
+        let key = MapKey::try_from(key)?;
+        self.entries.borrow_mut().insert(key, to);
+        Ok(())
+    }
+
+    /// Returns the built-in method `name` with this map bound as the receiver.
+    #[must_use]
+    pub(crate) fn method(&self, name: &str) -> Option<Object> {
+        let foreign = match name {
+            "keys" => Foreign::new(name, Arity::Fixed(0), |args| {
+                let keys = this(&args).entries.borrow().keys().map_into().collect_vec();
+                Ok(Object::List(keys.into()))
+            }),
+            "values" => Foreign::new(name, Arity::Fixed(0), |args| {
+                let vals = this(&args).entries.borrow().values().cloned().collect_vec();
+                Ok(Object::List(vals.into()))
+            }),
+            "has" => Foreign::new(name, Arity::Fixed(1), |args| {
+                let key = key_arg(&args)?;
+                let found = this(&args).entries.borrow().contains_key(&key);
+                Ok(Object::Bool(found))
+            }),
+            "remove" => Foreign::new(name, Arity::Fixed(1), |args| {
+                let key = key_arg(&args)?;
+                let removed = this(&args).entries.borrow_mut().shift_remove(&key);
+                Ok(removed.unwrap_or_default())
+            }),
+            "len" => Foreign::new(name, Arity::Fixed(0), |args| {
+                Ok(Object::from(this(&args).len()))
+            }),
+            _ => return None,
+        };
+        Some(Object::ForeignFn(foreign.bind(Object::Map(self.clone()))))
+    }
+}
+
+/// Gets the receiver of a bound Map method.
+fn this(args: &[Object]) -> &Map {
+    match &args[0] {
+        Object::Map(map) => map,
+        _ => unreachable!(),
+    }
+}
+
+/// Gets the key argument of a bound Map method.
+fn key_arg(args: &[Object]) -> Result<MapKey> {
+    MapKey::try_from(&args[1])
+}
+
+impl Hash for Map {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.uid.hash(state);
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.uid == other.uid
+    }
+}
+
+impl Eq for Map {}
@@ -0,0 +1,49 @@
+use std::hash::{Hash, Hasher};
+
+use gc::{Finalize, Trace};
+use uuid::Uuid;
+
+use super::{Env, MutCell, Object};
+
+/// A module loaded by an `import`, exposing its top-level globals as its
+/// properties.
+#[derive(Debug, Clone, Trace, Finalize)]
+pub struct Module {
+    #[unsafe_ignore_trace]
+    pub(crate) uid: Uuid,
+    /// The canonical path of the module file.
+    pub(crate) name: String,
+    pub(crate) globals: MutCell<Env>,
+}
+
+impl Module {
+    #[must_use]
+    pub(crate) fn new(name: &str, globals: MutCell<Env>) -> Self {
+        Self {
+            uid: Uuid::new_v4(),
+            name: name.into(),
+            globals,
+        }
+    }
+
+    /// Gets the top-level global `name` defined in this module, excluding
+    /// the builtins.
+    #[must_use]
+    pub(crate) fn get(&self, name: &str) -> Option<Object> {
+        Env::lookup_dict(&self.globals, name)
+    }
+}
+
+impl Hash for Module {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.uid.hash(state);
+    }
+}
+
+impl PartialEq for Module {
+    fn eq(&self, other: &Self) -> bool {
+        self.uid == other.uid
+    }
+}
+
+impl Eq for Module {}
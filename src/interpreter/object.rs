@@ -2,20 +2,61 @@ use std::fmt::Display;
 
 use anyhow::{bail, Result};
 use gc::{Finalize, Trace};
+use uuid::Uuid;
 
-use super::{Class, Closure, Instance};
-use crate::parser::Lit;
+use super::{Class, Closure, Foreign, Instance, List, Map, Module};
+use crate::{parser::Lit, vm};
 
-#[derive(Debug, Clone, PartialEq, Trace, Finalize)]
-pub(crate) enum Object {
+/// A Lox value.
+///
+/// `Int`s and `Number`s are compared by their numeric values, so `1 == 1.0`.
+#[derive(Debug, Clone, Trace, Finalize)]
+pub enum Object {
     Nil,
     Bool(bool),
+    Int(i64),
     Number(f64),
     Str(String),
     NativeFn(Closure),
-    ForeignFn(fn(Vec<Object>) -> Result<Object>),
+    BytecodeFn(vm::Closure),
+    ForeignFn(Foreign),
     Class(Class),
     Instance(Instance),
+    List(List),
+    Map(Map),
+    Module(Module),
+}
+
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        #[allow(clippy::enum_glob_use)]
+        use Object::*;
+        match (self, other) {
+            (Nil, Nil) => true,
+            (Bool(lhs), Bool(rhs)) => lhs == rhs,
+            (Int(lhs), Int(rhs)) => lhs == rhs,
+            #[allow(clippy::float_cmp)]
+            (Number(lhs), Number(rhs)) => lhs == rhs,
+            (Int(i), Number(n)) | (Number(n), Int(i)) => int_eq_number(*i, *n),
+            (Str(lhs), Str(rhs)) => lhs == rhs,
+            (NativeFn(lhs), NativeFn(rhs)) => lhs == rhs,
+            (BytecodeFn(lhs), BytecodeFn(rhs)) => lhs == rhs,
+            (ForeignFn(lhs), ForeignFn(rhs)) => lhs == rhs,
+            (Class(lhs), Class(rhs)) => lhs == rhs,
+            (Instance(lhs), Instance(rhs)) => lhs == rhs,
+            (List(lhs), List(rhs)) => lhs == rhs,
+            (Map(lhs), Map(rhs)) => lhs == rhs,
+            (Module(lhs), Module(rhs)) => lhs == rhs,
+            _ => false,
+        }
+    }
+}
+
+/// Checks if the `Int` `i` and the `Number` `n` are exactly the same number.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn int_eq_number(i: i64, n: f64) -> bool {
+    // The cast saturates, so a huge `n` can never equal `i`.
+    n.fract() == 0. && n as i128 == i128::from(i)
 }
 
 impl Default for Object {
@@ -26,9 +67,23 @@ impl Default for Object {
 
 impl Display for Object {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_nested(f, &mut vec![])
+    }
+}
+
+impl Object {
+    /// Writes this object as its `Display` form, where `shown` holds the uids
+    /// of the containers being written, so that a container reached again
+    /// from itself is abbreviated instead of written forever.
+    fn fmt_nested(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        shown: &mut Vec<Uuid>,
+    ) -> std::fmt::Result {
         match self {
             Object::Nil => write!(f, "nil"),
             Object::Bool(b) => write!(f, "{}", b),
+            Object::Int(i) => write!(f, "{i}"),
             Object::Number(n) => write!(f, "{}", n.to_string().trim_end_matches(".0")),
             Object::Str(s) => write!(f, r#""{}""#, s),
             Object::NativeFn(clos) => write!(
@@ -36,9 +91,45 @@ impl Display for Object {
                 "<fun: {}@native>",
                 clos.name.clone().unwrap_or_else(|| clos.uid.to_string()),
             ),
-            Object::ForeignFn(_) => write!(f, "<fun: _@foreign>"),
+            Object::BytecodeFn(clos) => write!(
+                f,
+                "<fun: {}@native>",
+                clos.fun
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| clos.uid.to_string()),
+            ),
+            Object::ForeignFn(foreign) => write!(f, "<fun: {}@foreign>", foreign.name),
             Object::Class(c) => write!(f, "<class: {}>", c.name),
             Object::Instance(i) => write!(f, "<instance: {}@{}>", i.uid, i.class.name),
+            Object::List(list) if shown.contains(&list.uid) => write!(f, "[...]"),
+            Object::List(list) => {
+                shown.push(list.uid);
+                write!(f, "[")?;
+                for (i, elem) in list.elems.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    elem.fmt_nested(f, shown)?;
+                }
+                shown.pop();
+                write!(f, "]")
+            }
+            Object::Map(map) if shown.contains(&map.uid) => write!(f, "{{...}}"),
+            Object::Map(map) => {
+                shown.push(map.uid);
+                write!(f, "{{")?;
+                for (i, (k, v)) in map.entries.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", Object::from(k))?;
+                    v.fmt_nested(f, shown)?;
+                }
+                shown.pop();
+                write!(f, "}}")
+            }
+            Object::Module(module) => write!(f, "<module: {}>", module.name),
         }
     }
 }
@@ -48,6 +139,7 @@ impl From<Lit> for Object {
         match lit {
             Lit::Nil => Self::Nil,
             Lit::Bool(b) => Self::Bool(b),
+            Lit::Int(i) => Self::Int(i),
             Lit::Number(n) => Self::Number(n),
             Lit::Str(s) => Self::Str(s),
         }
@@ -61,23 +153,146 @@ impl From<&Object> for bool {
 }
 
 impl Object {
+    /// Returns the truthiness of this object, where only `nil` and `false` are
+    /// falsey.
     #[must_use]
-    pub(crate) fn to_bool(&self) -> bool {
+    pub fn to_bool(&self) -> bool {
         self.into()
     }
+
+    /// Binds `this` to the given instance in this method.
+    ///
+    /// # Panics
+    /// Panics if this object is not a Lox function.
+    #[must_use]
+    pub(crate) fn bind(&self, instance: Instance) -> Self {
+        match self {
+            Object::NativeFn(clos) => Object::NativeFn(clos.clone().bind(instance)),
+            Object::BytecodeFn(clos) => Object::BytecodeFn(clos.clone().bind(instance)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the string representation of this object, where `Str`s are not
+    /// quoted.
+    #[must_use]
+    pub fn stringify(&self) -> String {
+        match self {
+            Object::Str(s) => s.clone(),
+            obj => obj.to_string(),
+        }
+    }
 }
 
 impl TryFrom<&Object> for f64 {
     type Error = anyhow::Error;
 
+    #[allow(clippy::cast_precision_loss)]
     fn try_from(obj: &Object) -> Result<Self, Self::Error> {
         match obj {
             Object::Number(n) => Ok(*n),
+            Object::Int(i) => Ok(*i as f64),
             Object::Bool(b) => Ok(f64::from(u8::from(*b))),
-            obj => bail!(
-                "Runtime Error: object `{:?}` cannot be converted to Number",
-                obj,
-            ),
+            obj => bail!("object `{obj:?}` cannot be converted to Number"),
+        }
+    }
+}
+
+impl TryFrom<Object> for f64 {
+    type Error = anyhow::Error;
+
+    fn try_from(obj: Object) -> Result<Self, Self::Error> {
+        (&obj).try_into()
+    }
+}
+
+impl TryFrom<&Object> for i64 {
+    type Error = anyhow::Error;
+
+    fn try_from(obj: &Object) -> Result<Self, Self::Error> {
+        match obj {
+            Object::Int(i) => Ok(*i),
+            Object::Bool(b) => Ok(i64::from(*b)),
+            obj => bail!("object `{obj:?}` cannot be converted to Int"),
         }
     }
 }
+
+impl TryFrom<Object> for i64 {
+    type Error = anyhow::Error;
+
+    fn try_from(obj: Object) -> Result<Self, Self::Error> {
+        (&obj).try_into()
+    }
+}
+
+impl TryFrom<Object> for bool {
+    type Error = anyhow::Error;
+
+    fn try_from(obj: Object) -> Result<Self, Self::Error> {
+        match obj {
+            Object::Bool(b) => Ok(b),
+            obj => bail!("object `{obj:?}` cannot be converted to Bool"),
+        }
+    }
+}
+
+impl TryFrom<Object> for String {
+    type Error = anyhow::Error;
+
+    fn try_from(obj: Object) -> Result<Self, Self::Error> {
+        match obj {
+            Object::Str(ref s) => Ok(s.clone()),
+            obj => bail!("object `{obj:?}` cannot be converted to Str"),
+        }
+    }
+}
+
+impl From<()> for Object {
+    fn from((): ()) -> Self {
+        Self::Nil
+    }
+}
+
+impl From<bool> for Object {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+
+impl From<i64> for Object {
+    fn from(i: i64) -> Self {
+        Self::Int(i)
+    }
+}
+
+impl From<usize> for Object {
+    /// Converts a length or an index to an `Int`, saturating on overflow.
+    fn from(n: usize) -> Self {
+        Self::Int(n.try_into().unwrap_or(i64::MAX))
+    }
+}
+
+impl From<f64> for Object {
+    fn from(n: f64) -> Self {
+        Self::Number(n)
+    }
+}
+
+impl From<String> for Object {
+    fn from(s: String) -> Self {
+        Self::Str(s)
+    }
+}
+
+impl From<&str> for Object {
+    fn from(s: &str) -> Self {
+        Self::Str(s.into())
+    }
+}
+
+impl<T: Into<Object>> From<Option<T>> for Object {
+    fn from(opt: Option<T>) -> Self {
+        opt.map_or(Self::Nil, Into::into)
+    }
+}
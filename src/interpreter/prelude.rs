@@ -0,0 +1,132 @@
+//! The native functions available in the global environment of every Lox
+//! program.
+
+use std::{
+    io::BufRead,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use super::{list::as_index, Arity, Interpreter, Object};
+
+/// Defines all native functions in the global environment of `interpreter`.
+#[allow(clippy::cast_precision_loss)]
+pub(crate) fn install(interpreter: &mut Interpreter) {
+    macro_rules! define {
+        ( $( $name:literal ($arity:expr) => $fun:expr ),+ $(,)? ) => {
+            $( interpreter.define_foreign($name, $arity, $fun); )+
+        };
+    }
+
+    define! {
+        // Time.
+        "clock" (Arity::Fixed(0)) => |_| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            Ok(Object::Number(now.as_secs_f64()))
+        },
+        // Console input.
+        "readLine" (Arity::Fixed(0)) => |_| {
+            let mut ln = String::new();
+            if std::io::stdin().lock().read_line(&mut ln)? == 0 {
+                return Ok(Object::Nil);
+            }
+            Ok(Object::Str(ln.trim_end_matches(['\r', '\n']).into()))
+        },
+        // Strings and collections.
+        "len" (Arity::Fixed(1)) => |args| {
+            let len = match &args[0] {
+                Object::Str(s) => s.chars().count(),
+                Object::List(list) => list.len(),
+                Object::Map(map) => map.len(),
+                obj => return Err(type_err("len", 0, "Str, List or Map", obj)),
+            };
+            Ok(Object::from(len))
+        },
+        "substr" (Arity::Optional { min: 2, max: 3 }) => |args| {
+            let s = str_arg("substr", &args, 0)?;
+            let len = s.chars().count();
+            let start = index_arg("substr", &args, 1)?;
+            let end = match args.get(2) {
+                Some(_) => start + index_arg("substr", &args, 2)?,
+                None => len,
+            };
+            if start > len || end > len {
+                bail!("`substr` range {start}..{end} out of bounds for Str of length {len}");
+            }
+            Ok(Object::Str(s.chars().skip(start).take(end - start).collect()))
+        },
+        "indexOf" (Arity::Fixed(2)) => |args| {
+            let (s, pat) = (str_arg("indexOf", &args, 0)?, str_arg("indexOf", &args, 1)?);
+            Ok(s.find(pat).map_or(Object::Int(-1), |i| s[..i].chars().count().into()))
+        },
+        "split" (Arity::Fixed(2)) => |args| {
+            let (s, sep) = (str_arg("split", &args, 0)?, str_arg("split", &args, 1)?);
+            let parts = if sep.is_empty() {
+                s.chars().map(|c| Object::Str(c.into())).collect()
+            } else {
+                s.split(sep).map(Object::from).collect::<Vec<_>>()
+            };
+            Ok(Object::List(parts.into()))
+        },
+        "toUpper" (Arity::Fixed(1)) => |args| {
+            Ok(Object::Str(str_arg("toUpper", &args, 0)?.to_uppercase()))
+        },
+        // Conversions.
+        "num" (Arity::Fixed(1)) => |args| {
+            Ok(match &args[0] {
+                Object::Str(s) => {
+                    let s = s.trim();
+                    s.parse().map(Object::Int)
+                        .or_else(|_| s.parse().map(Object::Number))
+                        .unwrap_or_default()
+                }
+                obj @ (Object::Int(_) | Object::Number(_)) => obj.clone(),
+                obj => Object::Int(obj.try_into()?),
+            })
+        },
+        "str" (Arity::Fixed(1)) => |args| Ok(Object::Str(args[0].stringify())),
+        // Math.
+        "sqrt" (Arity::Fixed(1)) => |args| Ok(Object::Number(num_arg("sqrt", &args, 0)?.sqrt())),
+        "floor" (Arity::Fixed(1)) => |args| match &args[0] {
+            Object::Int(i) => Ok(Object::Int(*i)),
+            _ => Ok(Object::Number(num_arg("floor", &args, 0)?.floor())),
+        },
+        "pow" (Arity::Fixed(2)) => |args| {
+            let (x, y) = (num_arg("pow", &args, 0)?, num_arg("pow", &args, 1)?);
+            Ok(Object::Number(x.powf(y)))
+        },
+        "random" (Arity::Fixed(0)) => |_| Ok(Object::Number(rand::random())),
+    }
+}
+
+fn str_arg<'a>(fun: &str, args: &'a [Object], idx: usize) -> Result<&'a str> {
+    match &args[idx] {
+        Object::Str(s) => Ok(s),
+        obj => Err(type_err(fun, idx, "Str", obj)),
+    }
+}
+
+fn num_arg(fun: &str, args: &[Object], idx: usize) -> Result<f64> {
+    match &args[idx] {
+        Object::Number(n) => Ok(*n),
+        #[allow(clippy::cast_precision_loss)]
+        Object::Int(i) => Ok(*i as f64),
+        obj => Err(type_err(fun, idx, "Number", obj)),
+    }
+}
+
+/// Gets a non-negative integer argument to be used as an index.
+fn index_arg(fun: &str, args: &[Object], idx: usize) -> Result<usize> {
+    match as_index(&args[idx]).map(usize::try_from) {
+        Some(Ok(i)) => Ok(i),
+        _ => Err(type_err(fun, idx, "non-negative integer", &args[idx])),
+    }
+}
+
+fn type_err(fun: &str, idx: usize, expected: &str, got: &Object) -> anyhow::Error {
+    anyhow!(
+        "`{fun}` expected argument #{} to be {expected}, got `{got}`",
+        idx + 1,
+    )
+}
@@ -0,0 +1,131 @@
+//! The profiler recording the calls of each Lox function.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use itertools::Itertools;
+
+use crate::source::{SourceMap, Span};
+
+/// The calls recorded for a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FunProfile {
+    pub(crate) name: String,
+    /// The span of the name of the function, or of the `fun` keyword for
+    /// lambdas.
+    pub(crate) defn: Span,
+    pub(crate) calls: u64,
+    /// The time spent in the function, including its callees.
+    pub(crate) total: Duration,
+    /// The time spent in the function, excluding its callees.
+    pub(crate) own: Duration,
+    /// The number of calls in progress, so that recursive calls are not
+    /// counted twice in `total`.
+    active: usize,
+}
+
+/// A call in progress.
+#[derive(Debug)]
+struct Call {
+    defn: Span,
+    start: Instant,
+    /// The time spent in the callees so far.
+    callees: Duration,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The functions called so far, by the span of their definitions.
+    funs: HashMap<Span, FunProfile>,
+    /// The calls in progress, innermost call last.
+    calls: Vec<Call>,
+}
+
+/// A profiler checked before and after each closure application.
+#[derive(Debug, Default)]
+pub(crate) struct Profiler {
+    state: RefCell<State>,
+}
+
+impl Profiler {
+    /// Records the start of a call to the function `name` defined at `defn`.
+    pub(crate) fn enter(&self, name: &str, defn: Span) {
+        let mut state = self.state.borrow_mut();
+        let fun = state.funs.entry(defn).or_insert_with(|| FunProfile {
+            name: name.into(),
+            defn,
+            calls: 0,
+            total: Duration::ZERO,
+            own: Duration::ZERO,
+            active: 0,
+        });
+        fun.calls += 1;
+        fun.active += 1;
+        state.calls.push(Call {
+            defn,
+            start: Instant::now(),
+            callees: Duration::ZERO,
+        });
+    }
+
+    /// Records the end of the innermost call in progress.
+    pub(crate) fn exit(&self) {
+        let state = &mut *self.state.borrow_mut();
+        let Some(call) = state.calls.pop() else {
+            return;
+        };
+        let elapsed = call.start.elapsed();
+        if let Some(caller) = state.calls.last_mut() {
+            caller.callees += elapsed;
+        }
+        if let Some(fun) = state.funs.get_mut(&call.defn) {
+            fun.active -= 1;
+            if fun.active == 0 {
+                fun.total += elapsed;
+            }
+            fun.own += elapsed.saturating_sub(call.callees);
+        }
+    }
+
+    /// Returns the functions called so far, the most expensive one first.
+    pub(crate) fn profiles(&self) -> Vec<FunProfile> {
+        (self.state.borrow().funs.values())
+            .cloned()
+            .sorted_by(|a, b| {
+                (b.own.cmp(&a.own))
+                    .then_with(|| b.calls.cmp(&a.calls))
+                    .then_with(|| a.defn.start.cmp(&b.defn.start))
+            })
+            .collect()
+    }
+
+    /// Renders the profiles as a table, where the functions are located in
+    /// `sources`.
+    pub(crate) fn report(&self, sources: &SourceMap) -> String {
+        let mut res = format!(
+            "{:>10} {:>12} {:>12}  function\n",
+            "calls", "total (ms)", "self (ms)"
+        );
+        for fun in self.profiles() {
+            let ms = |it: Duration| it.as_secs_f64() * 1e3;
+            let _ = write!(
+                res,
+                "{:>10} {:>12.3} {:>12.3}  {}",
+                fun.calls,
+                ms(fun.total),
+                ms(fun.own),
+                fun.name
+            );
+            if let Some(file) = sources.file(fun.defn.start) {
+                let (line, col) = file.pos(fun.defn.start);
+                let _ = write!(res, " ({}:{line}:{col})", file.name);
+            }
+            res.push('\n');
+        }
+        res
+    }
+}
@@ -1,21 +1,32 @@
+use std::mem;
+
 use anyhow::Result;
 use gc::Gc;
 
-use super::{BreakMarker, Class, Closure, ContinueMarker, Env, Interpreter, Object, ReturnMarker};
-use crate::{lexer::TokenType as Tk, parser::Stmt, runtime_bail};
+use super::{catch, get_property, Class, Closure, ControlFlow, Env, Interpreter, Object};
+use crate::{bail, error::Code, lexer::TokenType as Tk, module, parser::Stmt, util::MutCell};
 
 impl Interpreter {
-    pub(crate) fn exec(&mut self, stmt: Stmt) -> Result<()> {
+    pub(crate) fn exec(&mut self, stmt: Stmt) -> Result<ControlFlow> {
+        if let Some(coverage) = &self.coverage {
+            coverage.hit(&stmt);
+        }
+        if self.debugger.is_none() {
+            return self.exec_inner(stmt);
+        }
+        self.debug_stmt(&stmt)?;
+        self.exec_inner(stmt).or_else(|e| {
+            self.debug_error(&e)?;
+            Err(e)
+        })
+    }
+
+    /// Executes `stmt` without checking the debugger.
+    #[allow(clippy::too_many_lines)]
+    fn exec_inner(&mut self, stmt: Stmt) -> Result<ControlFlow> {
         let env = &Gc::clone(&self.env);
         match stmt {
-            Stmt::Block(stmts) => {
-                let old_env = Gc::clone(env);
-                // Temporarily switch into the scope environment...
-                self.env = Env::from_outer(env).shared();
-                stmts.into_iter().try_for_each(|it| self.exec(it))?;
-                // Switch back...
-                self.env = old_env;
-            }
+            Stmt::Block(stmts) => return self.exec_block(stmts, Env::from_outer(env).shared()),
             Stmt::Class {
                 name,
                 superclass,
@@ -28,8 +39,9 @@ impl Interpreter {
                         super_env.insert_val("super", Object::Class(sup.clone()));
                         (super_env.shared(), Some(sup.clone()))
                     } else {
-                        runtime_bail!(
-                            name.pos,
+                        bail!(
+                            Code::TypeMismatch,
+                            name.span,
                             "while evaluating a Class declaration",
                             "class `{}` cannot inherit from non-class value `{}`",
                             name.lexeme,
@@ -39,15 +51,16 @@ impl Interpreter {
                 } else {
                     (Gc::clone(env), None)
                 };
+                let globals = &self.globals;
                 let methods = methods
                     .into_iter()
                     .map(|it| {
                         if let Stmt::Fun { name, params, body } = it {
-                            let name: &str = &name.lexeme;
+                            let (defn, name) = (name.span, name.lexeme.as_str());
                             let closure = if name == "init" {
-                                Closure::new_init(name, params, body, env)
+                                Closure::new_init(name, defn, params, body, env, globals)
                             } else {
-                                Closure::new(name, params, body, env)
+                                Closure::new(name, defn, params, body, env, globals)
                             };
                             (name.to_owned(), Object::NativeFn(closure))
                         } else {
@@ -62,49 +75,132 @@ impl Interpreter {
                 self.eval(expr)?;
             }
             Stmt::Fun { name, params, body } => {
-                let name: &str = &name.lexeme;
-                let closure = Object::NativeFn(Closure::new(name, params, body, env));
+                let (defn, name) = (name.span, name.lexeme.as_str());
+                let closure = Closure::new(name, defn, params, body, env, &self.globals);
+                let closure = Object::NativeFn(closure);
                 env.borrow_mut().insert_val(name, closure);
             }
             Stmt::If {
                 cond,
                 then_stmt,
                 else_stmt,
+                ..
             } => {
                 if self.eval(cond)?.to_bool() {
-                    self.exec(*then_stmt)?;
+                    return self.exec(*then_stmt);
                 } else if let Some(else_stmt) = else_stmt {
-                    self.exec(*else_stmt)?;
+                    return self.exec(*else_stmt);
+                }
+            }
+            Stmt::Import { path, alias, names } => {
+                let module = Object::Module(module::import(self, &path.lexeme, path.span)?);
+                for name in &names {
+                    let val = get_property(name.span, &module, &name.lexeme)?;
+                    env.borrow_mut().insert_val(&name.lexeme, val);
+                }
+                if let Some(alias) = alias {
+                    env.borrow_mut().insert_val(&alias.lexeme, module);
+                }
+            }
+            Stmt::Jump { kw, label } => {
+                let label = label.map(|it| it.lexeme);
+                return Ok(match kw.ty {
+                    Tk::Break => ControlFlow::Break(label),
+                    Tk::Continue => ControlFlow::Continue(label),
+                    _ => unreachable!(),
+                });
+            }
+            Stmt::Print { val, .. } => {
+                let val = self.eval(val)?;
+                match &self.debugger {
+                    Some(it) => it.frontend.output(&format!("{val}\n")),
+                    None => println!("{val}"),
                 }
             }
-            Stmt::Jump(t) => match t.ty {
-                Tk::Break => return Err(anyhow::Error::new(BreakMarker)),
-                Tk::Continue => return Err(anyhow::Error::new(ContinueMarker)),
-                _ => unreachable!(),
-            },
-            Stmt::Print(expr) => println!("{}", self.eval(expr)?),
             Stmt::Return { kw: _, val } => {
                 let obj = self.eval(val.unwrap_or_default())?;
-                return Err(anyhow::Error::new(ReturnMarker(obj)));
+                return Ok(ControlFlow::Return(obj));
+            }
+            Stmt::Throw { kw, val } => {
+                let obj = self.eval(val)?;
+                return Err(self.thrown.throw(obj, kw.span));
+            }
+            Stmt::Try {
+                body,
+                catch: handler,
+                finally,
+                ..
+            } => {
+                let res = self.exec_block(body, Env::from_outer(env).shared());
+                let res = match (res, handler) {
+                    (Err(e), Some((name, body))) => catch(e, &mut self.thrown, &self.sources)
+                        .and_then(|exception| {
+                            let mut catch_env = Env::from_outer(env);
+                            catch_env.insert_val(&name.lexeme, exception);
+                            self.exec_block(body, catch_env.shared())
+                        }),
+                    (res, _) => res,
+                };
+                if let Some(body) = finally {
+                    // An error or a jump in the `finally` clause takes over the
+                    // previous one.
+                    let flow = self.exec_block(body, Env::from_outer(env).shared())?;
+                    if !matches!(flow, ControlFlow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+                return res;
             }
             Stmt::Var { name, init } => {
                 let init = self.eval(init.unwrap_or_default())?;
                 self.env.borrow_mut().insert_val(&name.lexeme, init);
             }
-            Stmt::While { cond, body } => {
+            Stmt::While {
+                label,
+                cond,
+                body,
+                incr,
+                ..
+            } => {
+                let label = label.map(|it| it.lexeme);
+                // Whether a jump to `to` is a jump to this loop.
+                let targets = |to: &Option<String>| to.is_none() || to == &label;
                 while self.eval(cond.clone())?.to_bool() {
-                    match self.exec(*body.clone()) {
-                        Err(e) if e.is::<BreakMarker>() => break,
-                        Err(e) if e.is::<ContinueMarker>() => continue,
-                        res => res?,
+                    match self.exec(*body.clone())? {
+                        ControlFlow::Break(to) if targets(&to) => break,
+                        ControlFlow::Continue(to) if targets(&to) => (),
+                        ControlFlow::Normal => (),
+                        flow => return Ok(flow),
+                    }
+                    if let Some(incr) = &incr {
+                        self.eval(incr.clone())?;
                     }
                 }
             }
         }
-        Ok(())
+        Ok(ControlFlow::Normal)
+    }
+
+    /// Runs the given statements till one of them jumps.
+    pub(crate) fn exec_stmts(
+        &mut self,
+        stmts: impl IntoIterator<Item = Stmt>,
+    ) -> Result<ControlFlow> {
+        for stmt in stmts {
+            let flow = self.exec(stmt)?;
+            if !matches!(flow, ControlFlow::Normal) {
+                return Ok(flow);
+            }
+        }
+        Ok(ControlFlow::Normal)
     }
 
-    pub(crate) fn exec_stmts(&mut self, stmts: impl IntoIterator<Item = Stmt>) -> Result<()> {
-        stmts.into_iter().try_for_each(|it| self.exec(it))
+    /// Runs the given statements in the scope environment `env`, switching
+    /// back to the current one afterwards, even if an error has been raised.
+    fn exec_block(&mut self, stmts: Vec<Stmt>, env: MutCell<Env>) -> Result<ControlFlow> {
+        let old_env = mem::replace(&mut self.env, env);
+        let res = self.exec_stmts(stmts);
+        self.env = old_env;
+        res
     }
 }
@@ -1,22 +1,79 @@
 #![cfg(test)]
 #![allow(clippy::enum_glob_use)]
 
-use indoc::indoc;
+use std::{collections::BTreeMap, ffi::OsStr, path::Path, time::Instant};
+
+use indoc::{formatdoc, indoc};
+use itertools::Itertools;
 use pretty_assertions::assert_eq;
 
 use super::*;
-use crate::run::run_str;
+use crate::{
+    error::{render_error, Code, Diagnostic, Errors},
+    module::run_entry,
+    run::{run_str, Backend},
+    vm::Vm,
+};
 
+/// Runs the given `(src, expected)` pairs with both backends, checking that
+/// they agree with each other on every output before the first error.
 fn assert_eval(pairs: &[(&str, &str)]) {
-    let interpreter = &mut Interpreter::default();
-    pairs
-        .iter()
-        .try_for_each(|(src, expected)| {
-            let got = run_str(src, interpreter, true)?;
-            assert_eq!(expected, &got, "unexpected output for `{}`", src);
-            anyhow::Ok(())
+    assert_eval_in("<stdin>", pairs);
+}
+
+/// Same as [`assert_eval`], but with every source named `name`.
+fn assert_eval_in(name: &str, pairs: &[(&str, &str)]) {
+    fn outputs(
+        backend: &mut impl Backend,
+        name: &str,
+        pairs: &[(&str, &str)],
+    ) -> Vec<anyhow::Result<String>> {
+        let mut res = vec![];
+        for (src, _) in pairs {
+            res.push(run_str(name, src, backend, true, |_, _| ()));
+            if res.last().unwrap().is_err() {
+                break;
+            }
+        }
+        res
+    }
+
+    let tree = outputs(&mut Interpreter::default(), name, pairs);
+    let bytecode = outputs(&mut Vm::default(), name, pairs);
+    for (i, (src, expected)) in pairs.iter().enumerate() {
+        match (&tree[i], bytecode.get(i)) {
+            (Ok(got), Some(Ok(got1))) => {
+                assert_eq!(expected, got, "unexpected output for `{}`", src);
+                assert_eq!(got, got1, "unexpected bytecode output for `{}`", src);
+            }
+            (Err(e), Some(Err(_))) => panic!("{:?}", e),
+            (Ok(_), e) | (_, e @ Some(Ok(_))) => {
+                panic!("backends disagree on `{}` (bytecode: {:?})", src, e)
+            }
+            (_, None) => unreachable!(),
+        }
+    }
+}
+
+/// Runs the entry file `name` of source `src` with both backends, checking that
+/// they agree with each other on the rendered error it fails with, which is
+/// returned.
+fn render_run(name: &str, src: &str) -> String {
+    fn render(backend: &mut impl Backend, name: &str, src: &str) -> String {
+        let err = run_entry(backend, Path::new(name), |it| {
+            run_str(name, src, it, false, |_, _| ())
         })
-        .unwrap();
+        .unwrap_err();
+        render_error(&err, backend.sources())
+    }
+
+    let got = render(&mut Interpreter::default(), name, src);
+    assert_eq!(
+        got,
+        render(&mut Vm::default(), name, src),
+        "backends disagree"
+    );
+    got
 }
 
 #[test]
@@ -190,6 +247,70 @@ fn for_stmt_jump() {
     ]);
 }
 
+#[test]
+fn for_stmt_continue() {
+    assert_eval(&[
+        ("var product = 1;", ""),
+        (
+            "for (var i = 1; i <= 5; i = i + 1) { if (i == 3) continue; product = product * i; }",
+            "",
+        ),
+        ("product", "40"),
+    ]);
+}
+
+#[test]
+fn labelled_jump() {
+    assert_eval(&[
+        ("var log = [];", ""),
+        (
+            indoc! {"
+                outer: for (var i = 0; i < 3; i = i + 1) {
+                    for (var j = 0; j < 3; j = j + 1) {
+                        if (j == 1) continue outer;
+                        if (i == 2) break outer;
+                        log.push([i, j]);
+                    }
+                }
+            "},
+            "",
+        ),
+        ("log", "[[0, 0], [1, 0]]"),
+        ("var n = 0;", ""),
+        (
+            indoc! {"
+                outer: while (true) {
+                    inner: while (true) {
+                        n = n + 1;
+                        if (n < 3) continue inner;
+                        try { break outer; } finally { n = n * 10; }
+                    }
+                    n = -1;
+                }
+            "},
+            "",
+        ),
+        ("n", "30"),
+    ]);
+}
+
+#[test]
+fn labelled_jump_shadowed() {
+    assert_eval(&[
+        ("var n = 0;", ""),
+        (
+            indoc! {"
+                a: while (n < 10) {
+                    n = n + 1;
+                    a: while (true) { break a; }
+                }
+            "},
+            "",
+        ),
+        ("n", "10"),
+    ]);
+}
+
 #[test]
 fn for_stmt_return_in_dup_fun() {
     assert_eval(&[(
@@ -661,3 +782,1057 @@ fn bare_super() {
 fn class_super_method_not_sub() {
     assert_eval(&[("class A { method() { return super.method(); } }", "")]);
 }
+
+#[test]
+fn prelude_clock() {
+    assert_eval(&[
+        ("var t0 = clock();", ""),
+        ("var t1 = clock();", ""),
+        ("t0 > 0 and t1 >= t0", "true"),
+        ("clock", "<fun: clock@foreign>"),
+    ]);
+}
+
+#[test]
+fn prelude_str() {
+    assert_eval(&[
+        (r#"var s = "Hello, world";"#, ""),
+        ("len(s)", "12"),
+        ("substr(s, 7)", r#""world""#),
+        ("substr(s, 0, 5)", r#""Hello""#),
+        ("substr(s, 12, 0)", r#""""#),
+        (r#"indexOf(s, "o")"#, "4"),
+        (r#"indexOf(s, "lox")"#, "-1"),
+        ("toUpper(s)", r#""HELLO, WORLD""#),
+        (r#"len("åäö")"#, "3"),
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "`substr` range 3..8 out of bounds for Str of length 5")]
+fn prelude_substr_out_of_bounds() {
+    assert_eval(&[(r#"substr("Hello", 3, 5)"#, "")]);
+}
+
+#[test]
+#[should_panic(expected = "`len` expected argument #1 to be Str, List or Map, got `42`")]
+fn prelude_len_type() {
+    assert_eval(&[("len(42)", "")]);
+}
+
+#[test]
+fn prelude_conv() {
+    assert_eval(&[
+        (r#"num("  42.5 ")"#, "42.5"),
+        (r#"num("lox")"#, "nil"),
+        ("num(true)", "1"),
+        (r#"str(42) + str("!")"#, r#""42!""#),
+        ("str(nil)", r#""nil""#),
+    ]);
+}
+
+#[test]
+fn prelude_math() {
+    assert_eval(&[
+        ("sqrt(16)", "4"),
+        ("floor(-2.5)", "-3"),
+        ("floor(9007199254740993)", "9007199254740993"),
+        ("pow(2, 10)", "1024"),
+        ("var r = random();", ""),
+        ("0 <= r and r < 1", "true"),
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "unexpected number of parameters (expected 1, got 2)")]
+fn prelude_arity() {
+    assert_eval(&[("sqrt(1, 2)", "")]);
+}
+
+#[test]
+fn prelude_split() {
+    assert_eval(&[
+        (r#"split("a,b,,c", ",")"#, r#"["a", "b", "", "c"]"#),
+        (r#"split("lox", "")"#, r#"["l", "o", "x"]"#),
+        (r#"len(split("lox", ""))"#, "3"),
+    ]);
+}
+
+#[test]
+fn list_basic() {
+    assert_eval(&[
+        ("[]", "[]"),
+        (r#"var xs = [1, "two", [3], nil,];"#, ""),
+        ("xs", r#"[1, "two", [3], nil]"#),
+        ("xs[1]", r#""two""#),
+        ("xs[2][0]", "3"),
+        ("xs[3] = 4", "4"),
+        ("xs[0] = xs[0] + xs[3]", "5"),
+        ("xs", r#"[5, "two", [3], 4]"#),
+        ("len(xs)", "4"),
+    ]);
+}
+
+#[test]
+fn list_methods() {
+    assert_eval(&[
+        ("var xs = [];", ""),
+        ("for (var i = 0; i < 5; i = i + 1) xs.push(i * i);", ""),
+        ("xs", "[0, 1, 4, 9, 16]"),
+        ("xs.len()", "5"),
+        ("xs.pop()", "16"),
+        ("xs.slice(1)", "[1, 4, 9]"),
+        ("xs.slice(1, 3)", "[1, 4]"),
+        ("xs.slice(4)", "[]"),
+        ("var push = xs.push; push(42);", ""),
+        ("xs", "[0, 1, 4, 9, 42]"),
+    ]);
+}
+
+#[test]
+fn list_reference_semantics() {
+    assert_eval(&[
+        ("var xs = [1, 2];", ""),
+        ("var ys = xs;", ""),
+        ("ys.push(3);", ""),
+        ("xs", "[1, 2, 3]"),
+        ("xs == ys", "true"),
+        ("xs == [1, 2, 3]", "false"),
+        ("fun fill(zs) { zs[0] = 0; }", ""),
+        ("fill(xs);", ""),
+        ("xs", "[0, 2, 3]"),
+    ]);
+}
+
+#[test]
+fn list_cyclic_display() {
+    assert_eval(&[
+        ("var xs = [1];", ""),
+        ("xs.push(xs);", ""),
+        ("xs", "[1, [...]]"),
+        (r#""${xs}""#, r#""[1, [...]]""#),
+        ("var ys = [xs, xs];", ""),
+        ("ys", "[[1, [...]], [1, [...]]]"),
+    ]);
+}
+
+#[test]
+fn list_in_closure() {
+    assert_eval(&[
+        (
+            indoc! {"
+                fun counter() {
+                    var log = [];
+                    fun tick(x) {
+                        log.push(x);
+                        return log.len();
+                    }
+                    return tick;
+                }
+                var tick = counter();
+            "},
+            "",
+        ),
+        ("tick(1); tick(2);", ""),
+        ("tick(3)", "3"),
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "index `3` out of bounds for List of length 3")]
+fn list_index_out_of_bounds() {
+    assert_eval(&[("var xs = [1, 2, 3];", ""), ("xs[3]", "")]);
+}
+
+#[test]
+#[should_panic(expected = "index `-1` out of bounds for List of length 1")]
+fn list_index_negative() {
+    assert_eval(&[("[1][-1] = 2", "")]);
+}
+
+#[test]
+#[should_panic(expected = "List index must be an integer, got `0.5`")]
+fn list_index_fract() {
+    assert_eval(&[("[1][0.5]", "")]);
+}
+
+#[test]
+#[should_panic(expected = "the object `\"lox\"` cannot be indexed")]
+fn index_non_list() {
+    assert_eval(&[(r#""lox"[0]"#, "")]);
+}
+
+#[test]
+#[should_panic(expected = "cannot `pop` from an empty List")]
+fn list_pop_empty() {
+    assert_eval(&[("[].pop()", "")]);
+}
+
+#[test]
+#[should_panic(expected = "`slice` range 2..1 out of bounds for List of length 3")]
+fn list_slice_out_of_bounds() {
+    assert_eval(&[("[1, 2, 3].slice(2, 1)", "")]);
+}
+
+#[test]
+#[should_panic(expected = "property `foo` undefined for the given object")]
+fn list_method_undefined() {
+    assert_eval(&[("[].foo", "")]);
+}
+
+#[test]
+fn map_basic() {
+    assert_eval(&[
+        // An empty Block, rather than an empty Map.
+        ("{}", ""),
+        ("({})", "{}"),
+        (r#"var m = {"one": 1, 2: "two", true: [3], nil: nil,};"#, ""),
+        ("m", r#"{"one": 1, 2: "two", true: [3], nil: nil}"#),
+        (r#"m["one"]"#, "1"),
+        ("m[1 + 1]", r#""two""#),
+        ("m[true][0]", "3"),
+        (r#"m["one"] = "uno""#, r#""uno""#),
+        ("m[-0] = 0", "0"),
+        ("m[0]", "0"),
+        (
+            "m",
+            r#"{"one": "uno", 2: "two", true: [3], nil: nil, 0: 0}"#,
+        ),
+        ("len(m)", "5"),
+    ]);
+}
+
+#[test]
+fn map_methods() {
+    assert_eval(&[
+        (r#"var m = {"b": 1, "a": 2};"#, ""),
+        (r#"m["c"] = 3;"#, ""),
+        ("m.keys()", r#"["b", "a", "c"]"#),
+        ("m.values()", "[1, 2, 3]"),
+        (r#"m.has("a")"#, "true"),
+        (r#"m.remove("a")"#, "2"),
+        (r#"m.has("a")"#, "false"),
+        (r#"m.remove("a")"#, "nil"),
+        ("m.keys()", r#"["b", "c"]"#),
+        ("m.len()", "2"),
+    ]);
+}
+
+#[test]
+fn map_identity_keys() {
+    assert_eval(&[
+        ("class Point {}", ""),
+        ("var p = Point(); var q = Point();", ""),
+        (r#"var m = {p: "p", Point: "class"};"#, ""),
+        ("m[p]", r#""p""#),
+        ("m[Point]", r#""class""#),
+        ("m.has(q)", "false"),
+        ("q.x = 1; m[q] = 1;", ""),
+        ("len(m)", "3"),
+    ]);
+}
+
+#[test]
+fn map_cyclic_display() {
+    assert_eval(&[
+        (r#"var m = {"a": 1};"#, ""),
+        (r#"m["self"] = m;"#, ""),
+        ("m", r#"{"a": 1, "self": {...}}"#),
+        (r#""${m}""#, r#""{"a": 1, "self": {...}}""#),
+        (r#"var xs = [m]; m["xs"] = xs;"#, ""),
+        ("xs", r#"[{"a": 1, "self": {...}, "xs": [...]}]"#),
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "key `\"nope\"` not found in Map")]
+fn map_missing_key() {
+    assert_eval(&[(r#"{"yes": 1}["nope"]"#, "")]);
+}
+
+#[test]
+#[should_panic(expected = "the object `[]` cannot be used as a Map key")]
+fn map_unhashable_key() {
+    assert_eval(&[("var m = {};", ""), ("m[[]] = 1;", "")]);
+}
+
+#[test]
+fn map_unhashable_key_span() {
+    let src = "var m = {\n  \"a\": 1,\n  [1, 2]: 2,\n};";
+    assert_eq!(
+        indoc! {"
+            Runtime Error[E0305]: the object `[1, 2]` cannot be used as a Map key
+             --> <stdin>:3:3
+              |
+            3 |   [1, 2]: 2,
+              |   ^^^^^^ while evaluating a Map literal
+        "},
+        render_run("<stdin>", src),
+    );
+}
+
+#[test]
+#[should_panic(expected = "the object `NaN` cannot be used as a Map key")]
+fn map_nan_key() {
+    assert_eval(&[("{0 / 0: 1}", "")]);
+}
+
+#[test]
+fn str_escapes() {
+    assert_eval(&[
+        (r#"len("a\tb\n")"#, "4"),
+        (r"'it\'s' + '\u{1F600}'", r#""it's😀""#),
+    ]);
+}
+
+#[test]
+fn str_interpolation() {
+    assert_eval(&[
+        (r#"var name = "Lox"; var age = 27;"#, ""),
+        (
+            r#""Hello ${name}, you are ${age + 1}""#,
+            r#""Hello Lox, you are 28""#,
+        ),
+        (
+            r#""${nil}, ${true}, ${[1, "a"]}, ${ {"k": "}"}["k"] }""#,
+            r#""nil, true, [1, "a"], }""#,
+        ),
+        (r#""${"${name}" + '!'}""#, r#""Lox!""#),
+        (
+            indoc! {r#"
+                fun counter(prefix) {
+                    var n = 0;
+                    return fun () {
+                        n = n + 1;
+                        return "${prefix}#${n}";
+                    };
+                }
+                var c = counter("n");
+                c();
+            "#},
+            "",
+        ),
+        ("c()", r#""n#2""#),
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "identifier `nope` is undefined")]
+fn str_interpolation_undefined() {
+    assert_eval(&[(r#""${nope}""#, "")]);
+}
+
+#[test]
+fn int_arithmetic() {
+    assert_eval(&[
+        ("9007199254740993 + 1", "9007199254740994"),
+        ("0xFF * 2 - 0b1", "509"),
+        ("7 / 2", "3.5"),
+        ("1 + 0.5", "1.5"),
+        ("-0x10", "-16"),
+        ("1 == 1.0", "true"),
+        ("2 < 2.5", "true"),
+        (r#"num("42") + num(" 1.5 ")"#, "43.5"),
+        (r#"indexOf("hello", "z")"#, "-1"),
+    ]);
+}
+
+#[test]
+fn int_keys_and_indices() {
+    assert_eval(&[
+        ("var l = [1, 2, 3];", ""),
+        ("l[0b10] + l[1.0]", "5"),
+        ("l.slice(len(l) - 2)", "[2, 3]"),
+        (r#"var m = {1: "one"};"#, ""),
+        ("m[1.0]", r#""one""#),
+        (r#"m[2.0] = "two";"#, ""),
+        ("m.keys()", "[1, 2]"),
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "index `-1` out of bounds for List of length 1")]
+fn int_negative_index() {
+    assert_eval(&[("[1][-1]", "")]);
+}
+
+#[test]
+#[should_panic(expected = "binary operator `Star` overflows for (9223372036854775807, 2)")]
+fn int_overflow() {
+    assert_eval(&[("0x7FFF_FFFF_FFFF_FFFF * 2", "")]);
+}
+
+#[test]
+fn arith_and_bitwise_ops() {
+    assert_eval(&[
+        ("7 % 3", "1"),
+        ("-7 % 3", "-1"),
+        ("7.5 % 2", "1.5"),
+        ("2 ** 10", "1024"),
+        ("2 ** -1", "0.5"),
+        ("2 ** 0.5 == sqrt(2)", "true"),
+        ("0b1100 & 0b1010 | 0b1", "9"),
+        ("0b1100 ^ 0b1010", "6"),
+        ("~0", "-1"),
+        ("1 << 4 >> 2", "4"),
+        ("-16 >> 2", "-4"),
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "binary operator `Amp` undefined for (Number(1.5), Int(1))")]
+fn bitwise_on_float() {
+    assert_eval(&[("1.5 & 1", "")]);
+}
+
+#[test]
+#[should_panic(expected = "binary operator `LessLess` overflows for (1, 64)")]
+fn shift_overflow() {
+    assert_eval(&[("1 << 64", "")]);
+}
+
+#[test]
+fn compound_assignment() {
+    assert_eval(&[
+        ("var a = 1;", ""),
+        ("a += 2", "3"),
+        ("a", "3"),
+        ("class C {}", ""),
+        ("var c = C(); c.n = 10;", ""),
+        ("c.n -= 4", "6"),
+        ("c.n /= 4", "1.5"),
+        ("var xs = [1, 2, 3]; var i = 0;", ""),
+        (
+            indoc! {"
+                fun next() {
+                    i += 1;
+                    return i;
+                }
+            "},
+            "",
+        ),
+        // The target is evaluated only once.
+        ("xs[next()] *= 10", "20"),
+        ("xs[next()] %= 2", "1"),
+        ("[xs, i]", "[[1, 20, 1], 2]"),
+        (r#"var s = "a";"#, ""),
+        ("s += 1", r#""a1""#),
+    ]);
+}
+
+#[test]
+fn ternary_and_coalesce() {
+    assert_eval(&[
+        ("var n = 0;", ""),
+        (r#"n > 0 ? "pos" : n < 0 ? "neg" : "zero""#, r#""zero""#),
+        ("nil ?? 1", "1"),
+        ("false ?? 1", "false"),
+        // The RHS is evaluated only if needed.
+        ("0 ?? (n = 1)", "0"),
+        ("n", "0"),
+    ]);
+}
+
+#[test]
+fn optional_chaining() {
+    assert_eval(&[
+        (
+            indoc! {"
+                class Node {
+                    init(val, next) {
+                        this.val = val;
+                        this.next = next;
+                    }
+                    get() { return this.val; }
+                }
+                var list = Node(1, Node(2, nil));
+                var calls = 0;
+                fun f() {
+                    calls += 1;
+                    return 0;
+                }
+            "},
+            "",
+        ),
+        ("list?.next?.val", "2"),
+        ("list.next.next?.val", "nil"),
+        // The rest of the chain is short-circuited, including the arguments.
+        ("list.next.next?.next.get(f())[f()]", "nil"),
+        ("calls", "0"),
+        ("list?.get()", "1"),
+        ("(nil?.a) ?? [1][f()]", "1"),
+        ("calls", "1"),
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "the object `nil` cannot have properties")]
+fn optional_chaining_grouped() {
+    assert_eval(&[("(nil?.a).b", "")]);
+}
+
+/// Returns the code of the error raised by the last source in `srcs`, checking
+/// that both backends agree on it.
+fn error_code(srcs: &[&str]) -> Code {
+    error_code_in("<stdin>", srcs)
+}
+
+/// Same as [`error_code`], but with every source named `name`.
+fn error_code_in(name: &str, srcs: &[&str]) -> Code {
+    fn code(backend: &mut impl Backend, name: &str, srcs: &[&str]) -> Code {
+        let (last, init) = srcs.split_last().unwrap();
+        for src in init {
+            run_str(name, src, backend, true, |_, _| ()).unwrap();
+        }
+        let err = run_str(name, last, backend, true, |_, _| ()).unwrap_err();
+        err.downcast_ref::<Diagnostic>().unwrap().code
+    }
+
+    let got = code(&mut Interpreter::default(), name, srcs);
+    assert_eq!(
+        got,
+        code(&mut Vm::default(), name, srcs),
+        "backends disagree"
+    );
+    got
+}
+
+#[test]
+fn try_catch() {
+    assert_eval(&[
+        ("var log = [];", ""),
+        (
+            r#"fun f(x) { if (x > 1) throw "too big: ${x}"; return x; }"#,
+            "",
+        ),
+        (
+            indoc! {r#"
+                try { log.push(f(1)); log.push(f(2)); log.push(3); }
+                catch (e) { log.push(e); }
+                finally { log.push("done"); }
+            "#},
+            "",
+        ),
+        ("log", r#"[1, "too big: 2", "done"]"#),
+    ]);
+}
+
+#[test]
+fn try_catch_runtime_error() {
+    assert_eval(&[
+        ("var e;", ""),
+        ("try {\n  nil + 1;\n} catch (err) { e = err; }", ""),
+        ("e.kind", r#""TypeMismatch""#),
+        ("[e.line, e.column]", "[2, 7]"),
+        ("try { [].pop(); } catch (err) { e = err; }", ""),
+        ("e.kind", r#""Foreign""#),
+        ("e.message", r#""cannot `pop` from an empty List""#),
+    ]);
+}
+
+#[test]
+fn try_catch_unwind() {
+    assert_eval(&[
+        (
+            r#"fun deep(n) { if (n == 0) throw "bottom"; return deep(n - 1) + 1; }"#,
+            "",
+        ),
+        (
+            indoc! {r#"
+                fun f() {
+                    var a = "a";
+                    try { var b = "b"; deep(5); } catch (e) { return a + e; }
+                }
+            "#},
+            "",
+        ),
+        ("f()", r#""abottom""#),
+        ("f() + f()", r#""abottomabottom""#),
+        ("var getX;", ""),
+        (
+            r#"try { var x = "x"; getX = fun () { return x; }; throw 1; } catch (_e) {}"#,
+            "",
+        ),
+        ("getX()", r#""x""#),
+    ]);
+}
+
+#[test]
+fn try_finally_jump() {
+    assert_eval(&[
+        ("var log = [];", ""),
+        (
+            r#"fun f() { try { return 1; } finally { log.push("f"); } }"#,
+            "",
+        ),
+        ("f()", "1"),
+        ("fun g() { try { throw 1; } finally { return 2; } }", ""),
+        ("g()", "2"),
+        (
+            indoc! {"
+                var i = -1;
+                while (i < 5) {
+                    i += 1;
+                    try {
+                        if (i == 1) continue;
+                        if (i == 3) break;
+                        try { log.push(i); } finally { var j = i * 10; log.push(j); }
+                    } finally { log.push(-i); }
+                }
+            "},
+            "",
+        ),
+        ("log", r#"["f", 0, 0, 0, -1, 2, 20, -2, -3]"#),
+    ]);
+}
+
+#[test]
+fn try_finally_rethrow() {
+    assert_eval(&[
+        ("var log = [];", ""),
+        (
+            indoc! {r#"
+                fun f() {
+                    try {
+                        try { throw "inner"; } finally { log.push("in"); }
+                    } catch (e) { log.push(e); } finally { log.push("out"); }
+                }
+            "#},
+            "",
+        ),
+        ("f();", ""),
+        ("log", r#"["in", "inner", "out"]"#),
+        ("var got;", ""),
+        (
+            indoc! {r#"
+                try {
+                    try { throw "A"; } finally { try { throw "B"; } catch (e) {} }
+                } catch (e) { got = e; }
+            "#},
+            "",
+        ),
+        ("got", r#""A""#),
+    ]);
+    assert_eq!(
+        Code::UncaughtException,
+        error_code(&["try { throw 1; } finally {}"])
+    );
+    assert_eq!(
+        Code::TypeMismatch,
+        error_code(&["try { nil + 1; } finally { var a = 1; }"])
+    );
+    assert_eq!(
+        Code::UndefinedVariable,
+        error_code(&["try { throw 1; } catch (e) { nope; }"])
+    );
+}
+
+#[test]
+fn diagnostic_codes() {
+    assert_eq!(Code::UnexpectedToken, error_code(&["print 1"]));
+    assert_eq!(Code::InvalidAssignTarget, error_code(&["1 = 2;"]));
+    assert_eq!(Code::JumpOutOfLoop, error_code(&["break;"]));
+    assert_eq!(
+        Code::UndefinedLabel,
+        error_code(&["a: while (true) { while (true) { break b; } }"])
+    );
+    assert_eq!(
+        Code::UndefinedLabel,
+        error_code(&["a: while (true) { fun f() { while (true) continue a; } }"])
+    );
+    assert_eq!(Code::SelfInit, error_code(&["{ var a = a; }"]));
+    assert_eq!(Code::UndefinedVariable, error_code(&["nope"]));
+    assert_eq!(Code::TypeMismatch, error_code(&["1 + nil"]));
+    assert_eq!(Code::ArityMismatch, error_code(&["fun f() {}", "f(1)"]));
+    assert_eq!(Code::BadIndex, error_code(&["[1][1]"]));
+    assert_eq!(Code::Foreign, error_code(&[r#"[].pop()"#]));
+    assert_eq!(
+        Code::IntOverflow,
+        error_code(&["-0x7FFF_FFFF_FFFF_FFFF - 2"])
+    );
+    assert_eq!(Code::UncaughtException, error_code(&[r#"throw "boom";"#]));
+}
+
+#[test]
+fn diagnostic_spans() {
+    let backend = &mut Interpreter::default();
+    run_str("<stdin>", "var a = 1;", backend, true, |_, _| ()).unwrap();
+    let err = run_str("<stdin>", "a +\n  nil", backend, true, |_, _| ()).unwrap_err();
+    assert_eq!(
+        indoc! {"
+            Runtime Error[E0303]: binary operator `Plus` undefined for (Int(1), Nil)
+             --> <stdin>:1:3
+              |
+            1 | a +
+              |   ^ while evaluating a Binary expression
+        "},
+        render_error(&err, &backend.sources),
+    );
+}
+
+#[test]
+fn semantic_errors_accumulate() {
+    let src = indoc! {"
+        break;
+        class A < A { init() { return 1; } }
+        print this;
+    "};
+    for err in [
+        run_str(
+            "<stdin>",
+            src,
+            &mut Interpreter::default(),
+            false,
+            |_, _| (),
+        )
+        .unwrap_err(),
+        run_str("<stdin>", src, &mut Vm::default(), false, |_, _| ()).unwrap_err(),
+    ] {
+        let Errors(errs) = err.downcast_ref().unwrap();
+        let codes = errs
+            .iter()
+            .map(|it| it.downcast_ref::<Diagnostic>().unwrap().code)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Code::JumpOutOfLoop,
+                Code::SelfInherit,
+                Code::ReturnInInit,
+                Code::ThisOutOfClass,
+            ],
+            codes,
+        );
+    }
+}
+
+/// Returns the warnings found in `src` as `(code, line, col)` triples.
+fn warnings(src: &str) -> Vec<(Code, usize, usize)> {
+    let backend = &mut Interpreter::default();
+    let mut res = vec![];
+    run_str("<stdin>", src, backend, false, |it, sources| {
+        let (line, col) = sources
+            .file(it.primary.start)
+            .unwrap()
+            .pos(it.primary.start);
+        res.push((it.code, line, col));
+    })
+    .unwrap();
+    res
+}
+
+#[test]
+fn resolver_warnings() {
+    let src = indoc! {r#"
+        fun f(a, _b) {
+            var x = 1;
+            var y = 2;
+            {
+                var x = 3;
+                print x;
+            }
+            return y;
+            print "never";
+        }
+        var g = fun (c) { while (true) { break; continue; } };
+    "#};
+    assert_eq!(
+        vec![
+            (Code::ShadowedLocal, 5, 13),
+            (Code::UnreachableCode, 8, 5),
+            (Code::UnusedParam, 1, 7),
+            (Code::UnusedVariable, 2, 9),
+            (Code::UnreachableCode, 11, 34),
+            (Code::UnusedParam, 11, 14),
+        ],
+        warnings(src),
+    );
+    assert_eq!(
+        Vec::<(Code, usize, usize)>::new(),
+        warnings("var a; { var b = a; print b; }")
+    );
+    assert_eq!(
+        vec![(Code::RedeclaredLocal, 1, 18), (Code::ShadowedLocal, 1, 31)],
+        warnings("{ var a = 1; var a = 2; { var a = 3; print a; } print a; }")
+    );
+    assert_eq!(
+        vec![(Code::UnusedVariable, 1, 7)],
+        warnings("{ var x; x = 1; var y = 0; y += 1; }")
+    );
+}
+
+#[test]
+fn tracebacks() {
+    let src = indoc! {"
+        class A {
+            init(n) { this.n = n; }
+            go() { return countdown(this.n); }
+        }
+        fun countdown(n) {
+            if (n == 0) return fun () { return nil + 1; }();
+            return countdown(n - 1);
+        }
+        A(5).go();
+    "};
+    assert_eq!(
+        indoc! {"
+            Traceback (most recent call last):
+              main.lox:9:9, in <script>
+                A(5).go();
+              main.lox:3:35, in go (defined at main.lox:3:5)
+                go() { return countdown(this.n); }
+              main.lox:7:27, in countdown (defined at main.lox:5:5)
+                return countdown(n - 1);
+              main.lox:7:27, in countdown (defined at main.lox:5:5)
+                return countdown(n - 1);
+              main.lox:7:27, in countdown (defined at main.lox:5:5)
+                return countdown(n - 1);
+              [previous entry repeated 2 more times]
+              main.lox:6:51, in countdown (defined at main.lox:5:5)
+                if (n == 0) return fun () { return nil + 1; }();
+              main.lox:6:44, in <lambda> (defined at main.lox:6:24)
+                if (n == 0) return fun () { return nil + 1; }();
+            Runtime Error[E0303]: binary operator `Plus` undefined for (Nil, Int(1))
+             --> main.lox:6:44
+              |
+            6 |     if (n == 0) return fun () { return nil + 1; }();
+              |                                            ^ while evaluating a Binary expression
+        "},
+        render_run("main.lox", src),
+    );
+}
+
+/// Writes the given `(path, src)` files into a new temporary directory,
+/// returning the path of its `main.lox`, which is always created.
+fn module_dir(files: &[(&str, &str)]) -> String {
+    let dir = std::env::temp_dir().join(format!("dolores-{}", uuid::Uuid::new_v4()));
+    for (path, src) in [("main.lox", "")].iter().chain(files) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, src).unwrap();
+    }
+    dir.join("main.lox").display().to_string()
+}
+
+#[test]
+fn module_import() {
+    let main = module_dir(&[(
+        "m.lox",
+        indoc! {"
+            var x = 1;
+            fun getX() { return x; }
+            fun setX(v) { x = v; }
+            class Point {
+                init(x, y) { this.x = x; this.y = y; }
+                sum() { return this.x + this.y + getX(); }
+            }
+        "},
+    )]);
+    assert_eval_in(
+        &main,
+        &[
+            ("var x = 42;", ""),
+            (r#"import "m.lox" as m;"#, ""),
+            ("m.x", "1"),
+            ("m.getX()", "1"),
+            ("m.setX(3);", ""),
+            ("m.x", "3"),
+            ("x", "42"),
+            ("m.Point(1, 2).sum()", "6"),
+            (r#"from "m.lox" import getX, setX;"#, ""),
+            ("getX()", "3"),
+        ],
+    );
+}
+
+#[test]
+fn module_run_once() {
+    let main = module_dir(&[
+        ("log.lox", "var xs = [];"),
+        ("b.lox", r#"from "log.lox" import xs; xs.push("b");"#),
+    ]);
+    assert_eval_in(
+        &main,
+        &[
+            (r#"import "b.lox" as b1;"#, ""),
+            (r#"import "b.lox" as b2;"#, ""),
+            ("b1 == b2", "true"),
+            (r#"from "log.lox" import xs;"#, ""),
+            ("xs", r#"["b"]"#),
+        ],
+    );
+}
+
+#[test]
+fn module_relative_path() {
+    let main = module_dir(&[
+        ("lib/a.lox", r#"from "b.lox" import b; var a = b + 1;"#),
+        ("lib/b.lox", "var b = 1;"),
+    ]);
+    assert_eval_in(&main, &[(r#"from "lib/a.lox" import a;"#, ""), ("a", "2")]);
+}
+
+#[test]
+fn module_errors() {
+    let main = module_dir(&[
+        ("a.lox", r#"import "b.lox" as b;"#),
+        ("b.lox", r#"import "a.lox" as a;"#),
+        ("m.lox", "var x = 1;"),
+    ]);
+    assert_eq!(
+        Code::CyclicImport,
+        error_code_in(&main, &[r#"import "a.lox" as a;"#])
+    );
+    assert_eq!(
+        Code::ImportFailed,
+        error_code_in(&main, &[r#"import "nope.lox" as n;"#])
+    );
+    assert_eq!(
+        Code::UndefinedProperty,
+        error_code_in(&main, &[r#"from "m.lox" import y;"#])
+    );
+}
+
+#[test]
+fn module_entry_cycle() {
+    let main = module_dir(&[("lib.lox", r#"from "main.lox" import a;"#)]);
+    let src = indoc! {r#"
+        var a = 1;
+        import "lib.lox" as lib;
+    "#};
+    std::fs::write(&main, src).unwrap();
+
+    // The entry file is not run again, so the cycle starts from it.
+    let dir = Path::new(&main).parent().unwrap().canonicalize().unwrap();
+    let (main_path, lib_path) = (dir.join("main.lox"), dir.join("lib.lox"));
+    let (main_path, lib_path) = (main_path.display(), lib_path.display());
+    assert_eq!(
+        formatdoc! {r#"
+            Runtime Error[E0311]: cyclic import: `{main_path}` -> `{lib_path}` -> `{main_path}`
+             --> {lib_path}:1:6
+              |
+            1 | from "main.lox" import a;
+              |      ^^^^^^^^^^ while importing a module
+        "#},
+        render_run(&main, src),
+    );
+}
+
+const PROFILED: &str = indoc! {"
+    fun fib(n) {
+      if (n < 2) return n;
+      return fib(n - 1) + fib(n - 2);
+    }
+    fun unused() {
+      print \"never\";
+      print \"run\";
+    }
+    class A {
+      init() { this.sq = fun (x) { return x * x; }; }
+    }
+    var a = A();
+    fib(10);
+    a.sq(3);
+    if (false) {
+      print \"no\";
+    }
+"};
+
+#[test]
+fn profile() {
+    let mut interpreter = Interpreter {
+        profiler: Some(Rc::default()),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    run_str("main.lox", PROFILED, &mut interpreter, false, |_, _| ()).unwrap();
+    let elapsed = start.elapsed();
+    let profiler = interpreter.profiler.as_ref().unwrap();
+    let profiles = profiler.profiles();
+    let got = (profiles.iter())
+        .map(|it| (it.name.as_str(), it.calls))
+        .sorted()
+        .collect_vec();
+    assert_eq!(vec![("<lambda>", 1), ("fib", 177), ("init", 1)], got);
+
+    // Recursive calls are counted once in the total time.
+    let fib = profiles.iter().find(|it| it.name == "fib").unwrap();
+    assert!(fib.total <= elapsed);
+    assert!(profiles.iter().all(|it| it.own <= it.total));
+
+    let report = profiler.report(&interpreter.sources);
+    let mut lines = report.lines();
+    assert_eq!(
+        "calls   total (ms)    self (ms)  function",
+        lines.next().unwrap().trim_start()
+    );
+    let fib = lines
+        .find(|it| it.ends_with(" fib (main.lox:1:5)"))
+        .unwrap();
+    assert_eq!("177", fib.split_whitespace().next().unwrap());
+}
+
+#[test]
+fn coverage() {
+    let mut interpreter = Interpreter {
+        coverage: Some(Rc::default()),
+        ..Default::default()
+    };
+    run_str("main.lox", PROFILED, &mut interpreter, false, |_, _| ()).unwrap();
+    let coverage = interpreter.coverage.as_ref().unwrap();
+    let files = coverage.files(&interpreter.sources);
+    assert_eq!(1, files.len());
+    let expected = [
+        (1, 1),
+        (2, 177),
+        (3, 88),
+        (5, 1),
+        (6, 0),
+        (7, 0),
+        (9, 1),
+        (10, 1),
+        (12, 1),
+        (13, 1),
+        (14, 1),
+        (15, 1),
+        (16, 0),
+    ];
+    assert_eq!(
+        expected.into_iter().collect::<BTreeMap<_, _>>(),
+        files[0].lines
+    );
+
+    let text = coverage.text(&interpreter.sources);
+    assert_eq!("main.lox: 10/13 lines (76.9%)\n  not run: 6-7, 16\n", text);
+    let lcov = coverage.lcov(&interpreter.sources);
+    assert!(lcov.starts_with("TN:\nSF:main.lox\nDA:1,1\nDA:2,177\n"));
+    assert!(lcov.ends_with("DA:16,0\nLF:13\nLH:10\nend_of_record\n"));
+}
+
+#[test]
+fn coverage_modules() {
+    let main = module_dir(&[("m.lox", "fun f() {\n  return 1;\n}\nvar x = 2;")]);
+    let mut interpreter = Interpreter {
+        coverage: Some(Rc::default()),
+        ..Default::default()
+    };
+    let src = r#"from "m.lox" import x; print x;"#;
+    run_str(&main, src, &mut interpreter, false, |_, _| ()).unwrap();
+    let files = interpreter
+        .coverage
+        .as_ref()
+        .unwrap()
+        .files(&interpreter.sources);
+    let got = (files.iter())
+        .map(|it| {
+            (
+                Path::new(&it.name).file_name().unwrap(),
+                it.hit(),
+                it.lines.len(),
+            )
+        })
+        .collect_vec();
+    assert_eq!(
+        vec![(OsStr::new("main.lox"), 1, 1), (OsStr::new("m.lox"), 2, 3)],
+        got
+    );
+}
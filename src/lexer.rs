@@ -1,17 +1,33 @@
 use std::fmt::Display;
 
+use anyhow::Result;
 use logos::Logos;
 
-use crate::util::index_to_pos;
+use crate::{
+    bail,
+    error::{Code, Diagnostic},
+    report,
+    source::Span,
+};
 
 pub(crate) struct Lexer<'s> {
     inner: logos::Lexer<'s, TokenType>,
+    /// The offset of `src` in its `SourceMap`.
+    offset: usize,
 }
 
 impl<'s> Lexer<'s> {
+    /// Creates a lexer for a standalone `src`, whose spans are never rendered.
+    #[cfg(test)]
     pub(crate) fn new(src: &'s str) -> Self {
+        Self::with_offset(src, 0)
+    }
+
+    /// Creates a lexer for `src`, which starts at `offset` in its `SourceMap`.
+    pub(crate) fn with_offset(src: &'s str, offset: usize) -> Self {
         Self {
             inner: TokenType::lexer(src),
+            offset,
         }
     }
 }
@@ -20,11 +36,27 @@ impl<'s> Iterator for Lexer<'s> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let src = self.inner.source();
-        self.inner.next().map(|ty| Token {
+        let ty = self.inner.next()?;
+        let span = self.inner.span();
+        // `?.` before a digit is a `?` before a Number instead, as in `a?.5:1`,
+        // so the lexing restarts from the `.`.
+        if ty == TokenType::QuestionDot
+            && (self.inner.remainder()).starts_with(|c: char| c.is_ascii_digit())
+        {
+            let src = self.inner.source();
+            let start = self.offset + span.start;
+            self.inner = TokenType::lexer(&src[span.start + 1..]);
+            self.offset = start + 1;
+            return Some(Token {
+                ty: TokenType::Question,
+                lexeme: "?".into(),
+                span: Span::new(start, start + 1),
+            });
+        }
+        Some(Token {
             ty,
-            pos: index_to_pos(src, self.inner.span().start),
             lexeme: self.inner.slice().into(),
+            span: Span::new(self.offset + span.start, self.offset + span.end),
         })
     }
 }
@@ -33,9 +65,8 @@ impl<'s> Iterator for Lexer<'s> {
 pub(crate) struct Token {
     pub(crate) ty: TokenType,
     pub(crate) lexeme: String,
-    /// The `(line_num, column_num)` pair of the starting position of this
-    /// token, in the text editor standard (index starting from 1).
-    pub(crate) pos: (usize, usize),
+    /// The byte range of this token in its `SourceMap`.
+    pub(crate) span: Span,
 }
 
 impl Display for Token {
@@ -44,6 +75,254 @@ impl Display for Token {
     }
 }
 
+/// A piece of a `Str` token.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum StrPart<'s> {
+    /// A literal piece, whose escape sequences have been decoded.
+    Lit(String),
+    /// The source of an interpolated expression, together with its offset in
+    /// the `SourceMap`.
+    Interp(&'s str, usize),
+}
+
+/// Splits the lexeme of a `Str` token into literal pieces and interpolated
+/// expressions, where `span` is the span of the lexeme memorized for error
+/// reports.
+pub(crate) fn str_parts(lexeme: &str, span: Span) -> Result<Vec<StrPart<'_>>> {
+    // The lexeme is quoted with ASCII quotes, as guaranteed by `lex_str`.
+    let body = &lexeme[1..lexeme.len() - 1];
+    let offset = span.start + 1;
+    let mut res = vec![];
+    let (mut i, mut lit_start) = (0, 0);
+    while let Some(&b) = body.as_bytes().get(i) {
+        match b {
+            b'\\' => i += 1 + body[i + 1..].chars().next().map_or(0, char::len_utf8),
+            b'$' if body[i + 1..].starts_with('{') => {
+                if lit_start < i {
+                    let lit = unescape(&body[lit_start..i], offset + lit_start)?;
+                    res.push(StrPart::Lit(lit));
+                }
+                // `lex_str` guarantees that the interpolation is closed.
+                let len = interp_len(&body[i + 2..]).unwrap();
+                res.push(StrPart::Interp(&body[i + 2..i + 1 + len], offset + i + 2));
+                i += 2 + len;
+                lit_start = i;
+            }
+            _ => i += 1,
+        }
+    }
+    if lit_start < body.len() || res.is_empty() {
+        res.push(StrPart::Lit(unescape(
+            &body[lit_start..],
+            offset + lit_start,
+        )?));
+    }
+    Ok(res)
+}
+
+/// Decodes a literal piece of a `Str` token, which starts at `offset` in its
+/// `SourceMap`.
+///
+/// The supported escape sequences are `\n`, `\t`, `\r`, `\\`, `\"`, `\'`, `\0`,
+/// `\$`, and `\u{...}` with 1 to 6 hex digits.
+fn unescape(lit: &str, offset: usize) -> Result<String> {
+    let ctx = "while lexing a String literal";
+    let mut res = String::with_capacity(lit.len());
+    let mut chars = lit.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        // `lex_str` also guarantees that a backslash is followed by a char.
+        let (_, esc) = chars.next().unwrap();
+        // The span of the escape sequence consumed so far.
+        let esc_span = |end: usize| Span::new(offset + start, offset + end);
+        res.push(match esc {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '\\' => '\\',
+            '"' => '"',
+            '\'' => '\'',
+            '0' => '\0',
+            '$' => '$',
+            'u' => {
+                if chars.next_if(|&(_, c)| c == '{').is_none() {
+                    bail!(
+                        Code::InvalidEscape,
+                        esc_span(start + 2),
+                        ctx,
+                        "expected `{{` after `\\u`",
+                    );
+                }
+                let digits_start = start + 3;
+                let mut end = digits_start;
+                while let Some((i, c)) = chars.next_if(|&(_, c)| c != '}' && c != '\\') {
+                    end = i + c.len_utf8();
+                }
+                if chars.next_if(|&(_, c)| c == '}').is_none() {
+                    bail!(
+                        Code::InvalidEscape,
+                        esc_span(end),
+                        ctx,
+                        "expected `}}` to end the Unicode escape",
+                    );
+                }
+                let digits = &lit[digits_start..end];
+                let code = (1..=6)
+                    .contains(&digits.len())
+                    .then(|| u32::from_str_radix(digits, 16).ok())
+                    .flatten();
+                let Some(decoded) = code.and_then(char::from_u32) else {
+                    bail!(
+                        Code::InvalidEscape,
+                        esc_span(end + 1),
+                        ctx,
+                        "invalid Unicode escape `\\u{{{}}}`",
+                        digits,
+                    );
+                };
+                decoded
+            }
+            esc => bail!(
+                Code::InvalidEscape,
+                esc_span(start + 1 + esc.len_utf8()),
+                ctx,
+                "unknown escape sequence `\\{}`",
+                esc,
+            ),
+        });
+    }
+    Ok(res)
+}
+
+/// Lexes the rest of a String literal quoted by `quote`, marking the rest of
+/// the line as erroneous if the literal is unterminated.
+fn lex_str(lex: &mut logos::Lexer<TokenType>, quote: u8) -> bool {
+    let rest = lex.remainder();
+    if let Some(len) = str_len(rest, quote) {
+        lex.bump(len);
+        return true;
+    }
+    lex.bump(rest.find(['\r', '\n']).unwrap_or(rest.len()));
+    false
+}
+
+/// Returns the length of the rest of a String literal quoted by `quote`
+/// including the closing quote, or `None` if it is unterminated.
+///
+/// Interpolated expressions are skipped as a whole, so that they can contain
+/// other String literals.
+fn str_len(src: &str, quote: u8) -> Option<usize> {
+    // Only ASCII bytes are matched, so slicing at them is always safe.
+    let bytes = src.as_bytes();
+    let mut i = 0;
+    while let Some(&b) = bytes.get(i) {
+        match b {
+            b'\r' | b'\n' => return None,
+            b'\\' if matches!(bytes.get(i + 1), None | Some(b'\r' | b'\n')) => return None,
+            b'\\' => i += 2,
+            b'$' if bytes.get(i + 1) == Some(&b'{') => i += 2 + interp_len(&src[i + 2..])?,
+            _ if b == quote => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Returns the length of the rest of an interpolated expression including the
+/// closing `}`, or `None` if it is unterminated.
+///
+/// Comments are skipped as a whole, so that they can contain `}`.
+fn interp_len(src: &str) -> Option<usize> {
+    let bytes = src.as_bytes();
+    let (mut i, mut depth) = (0, 0_usize);
+    while let Some(&b) = bytes.get(i) {
+        match b {
+            b'\r' | b'\n' => return None,
+            b'"' | b'\'' => i += 1 + str_len(&src[i + 1..], b)?,
+            // A line comment runs past the end of the String literal.
+            b'/' if bytes.get(i + 1) == Some(&b'/') => return None,
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                // The comment cannot span lines, like the String literal.
+                let len = 2 + block_comment_len(&src[i + 2..])?;
+                if src[i..i + len].contains(['\r', '\n']) {
+                    return None;
+                }
+                i += len;
+            }
+            b'{' => {
+                depth += 1;
+                i += 1;
+            }
+            b'}' if depth == 0 => return Some(i + 1),
+            b'}' => {
+                depth -= 1;
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Reports an `Error` token, which is either an unterminated literal or
+/// comment, or an unrecognized token.
+pub(crate) fn lex_error(token: &Token) -> Diagnostic {
+    let (lexeme, span) = (&token.lexeme, token.span);
+    let ctx = "while lexing";
+    if lexeme.starts_with("/*") {
+        // Pointing to the opening `/*` is enough, as the comment spans the
+        // rest of the source.
+        let span = Span::new(span.start, span.start + 2);
+        return report!(Code::InvalidToken, span, ctx, "unterminated block comment");
+    }
+    if lexeme.starts_with(['"', '\'']) {
+        return report!(Code::InvalidToken, span, ctx, "unterminated String literal");
+    }
+    report!(
+        Code::InvalidToken,
+        span,
+        ctx,
+        "unrecognized token `{}`",
+        lexeme
+    )
+}
+
+/// Lexes the rest of a block comment, which can be nested, marking the rest of
+/// the source as erroneous if the comment is unterminated.
+fn lex_block_comment(lex: &mut logos::Lexer<TokenType>) -> bool {
+    let rest = lex.remainder();
+    let len = block_comment_len(rest);
+    lex.bump(len.unwrap_or(rest.len()));
+    len.is_some()
+}
+
+/// Returns the length of the rest of a block comment including the closing
+/// `*/`, or `None` if it is unterminated.
+fn block_comment_len(src: &str) -> Option<usize> {
+    let bytes = src.as_bytes();
+    let (mut i, mut depth) = (0, 1_usize);
+    while i < bytes.len() {
+        match &bytes[i..] {
+            [b'/', b'*', ..] => {
+                depth += 1;
+                i += 2;
+            }
+            [b'*', b'/', ..] => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
 #[derive(Logos, Debug, PartialEq, Eq, Clone, Copy, Hash)]
 #[repr(u16)]
 pub(crate) enum TokenType {
@@ -60,9 +339,18 @@ pub(crate) enum TokenType {
     #[token("}")]
     RightBrace,
 
+    #[token("[")]
+    LeftBracket,
+
+    #[token("]")]
+    RightBracket,
+
     #[token(",")]
     Comma,
 
+    #[token(":")]
+    Colon,
+
     #[token(".")]
     Dot,
 
@@ -81,6 +369,24 @@ pub(crate) enum TokenType {
     #[token("*")]
     Star,
 
+    #[token("%")]
+    Percent,
+
+    #[token("&")]
+    Amp,
+
+    #[token("|")]
+    Pipe,
+
+    #[token("^")]
+    Caret,
+
+    #[token("~")]
+    Tilde,
+
+    #[token("?")]
+    Question,
+
     // One or two character tokens.
     #[token("!")]
     Bang,
@@ -106,25 +412,65 @@ pub(crate) enum TokenType {
     #[token("<=")]
     LessEqual,
 
+    #[token("**")]
+    StarStar,
+
+    #[token("<<")]
+    LessLess,
+
+    #[token(">>")]
+    GreaterGreater,
+
+    #[token("??")]
+    QuestionQuestion,
+
+    #[token("?.")]
+    QuestionDot,
+
+    // Compound assignment operators.
+    #[token("+=")]
+    PlusEqual,
+
+    #[token("-=")]
+    MinusEqual,
+
+    #[token("*=")]
+    StarEqual,
+
+    #[token("/=")]
+    SlashEqual,
+
+    #[token("%=")]
+    PercentEqual,
+
     // Literals.
-    #[regex(r"[a-zA-Z_][0-9a-zA-Z_?!]*")]
+    #[regex(r"[a-zA-Z_][0-9a-zA-Z_!]*")]
     Identifier,
 
-    #[regex(r#""(([^\r\n\\"]|\\.)*)"|'(([^\r\n\\']|\\.)*)'"#)]
+    #[token("\"", |lex| lex_str(lex, b'"'))]
+    #[token("'", |lex| lex_str(lex, b'\''))]
     Str,
 
-    // Numerical conversions are painful! It's better to use floats only here...
-    #[regex(r"0|0x[0-9a-fA-F_]+|[0-9]+")]
-    #[regex(r"[0-9]*\.[0-9]+([eE][+-]?[0-9]+)?")]
+    // Integers, where the digits after a radix prefix are checked when parsing.
+    #[regex(r"[0-9][0-9_]*|0[xbo][0-9a-zA-Z_]+")]
+    // Floats.
+    #[regex(r"([0-9][0-9_]*)?\.[0-9][0-9_]*([eE][+-]?[0-9_]+)?")]
+    #[regex(r"[0-9][0-9_]*[eE][+-]?[0-9_]+")]
     Number,
 
     // Keywords.
     #[token("and")]
     And,
 
+    #[token("as")]
+    As,
+
     #[token("break")]
     Break,
 
+    #[token("catch")]
+    Catch,
+
     #[token("class")]
     Class,
 
@@ -137,15 +483,24 @@ pub(crate) enum TokenType {
     #[token("false")]
     False,
 
+    #[token("finally")]
+    Finally,
+
     #[token("fun")]
     Fun,
 
     #[token("for")]
     For,
 
+    #[token("from")]
+    From,
+
     #[token("if")]
     If,
 
+    #[token("import")]
+    Import,
+
     #[token("nil")]
     Nil,
 
@@ -164,9 +519,15 @@ pub(crate) enum TokenType {
     #[token("this")]
     This,
 
+    #[token("throw")]
+    Throw,
+
     #[token("true")]
     True,
 
+    #[token("try")]
+    Try,
+
     #[token("var")]
     Var,
 
@@ -175,14 +536,39 @@ pub(crate) enum TokenType {
 
     // Misc.
     #[regex(r"//[^\r\n]*(\r\n|\n)?")]
-    // TODO: Add MultiLineComment maybe?
     SingleLineComment,
 
+    #[token("/*", lex_block_comment)]
+    MultiLineComment,
+
     #[error]
     #[regex(r"[ \t\n\f]+", logos::skip)]
     Error,
 }
 
+impl TokenType {
+    /// Returns if this token type is for trivia, i.e. comments, which are
+    /// skipped by the parser but still kept for tooling.
+    #[must_use]
+    pub(crate) fn is_trivia(self) -> bool {
+        matches!(self, Self::SingleLineComment | Self::MultiLineComment)
+    }
+
+    /// Returns the binary operator applied by this compound assignment
+    /// operator, e.g. `Plus` for `PlusEqual`.
+    #[must_use]
+    pub(crate) fn compound_op(self) -> Option<Self> {
+        Some(match self {
+            Self::PlusEqual => Self::Plus,
+            Self::MinusEqual => Self::Minus,
+            Self::StarEqual => Self::Star,
+            Self::SlashEqual => Self::Slash,
+            Self::PercentEqual => Self::Percent,
+            _ => return None,
+        })
+    }
+}
+
 #[allow(clippy::enum_glob_use)]
 #[cfg(test)]
 mod tests {
@@ -190,6 +576,7 @@ mod tests {
     use pretty_assertions::assert_eq;
 
     use super::*;
+    use crate::error::Diagnostic;
 
     fn lex(src: &str) -> Vec<(TokenType, String)> {
         Lexer::new(src).map(|t| (t.ty, t.lexeme)).collect()
@@ -221,6 +608,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn question_marks() {
+        assert_lex!(
+            "a?b:c??d?.e!",
+            r#"[(Identifier, "a"), (Question, "?"), (Identifier, "b"), (Colon, ":"), (Identifier, "c"), (QuestionQuestion, "??"), (Identifier, "d"), (QuestionDot, "?."), (Identifier, "e!")]"#,
+        );
+        assert_lex!(
+            "true?.5:1",
+            r#"[(True, "true"), (Question, "?"), (Number, ".5"), (Colon, ":"), (Number, "1")]"#,
+        );
+    }
+
     #[test]
     fn comments() {
         assert_lex!(
@@ -232,4 +631,106 @@ mod tests {
             r#"[(Var, "var"), (Identifier, "a"), (Equal, "="), (Number, "1"), (Semicolon, ";"), (SingleLineComment, "// Wow\n"), (SingleLineComment, "// This is a comment\n"), (Identifier, "a"), (Equal, "="), (False, "false"), (Semicolon, ";")]"#,
         );
     }
+
+    #[test]
+    fn block_comments() {
+        assert_lex!(
+            "a /* b /* c */ d */ e /**/ f */",
+            r#"[(Identifier, "a"), (MultiLineComment, "/* b /* c */ d */"), (Identifier, "e"), (MultiLineComment, "/**/"), (Identifier, "f"), (Star, "*"), (Slash, "/")]"#,
+        );
+        assert_lex!(
+            "a /* b /* c */ d\n e",
+            r#"[(Identifier, "a"), (Error, "/* b /* c */ d\n e")]"#,
+        );
+        let err = lex_error(&Lexer::with_offset("/* a", 10).next().unwrap());
+        assert_eq!(
+            ("unterminated block comment", "10..12".into()),
+            (err.msg.as_str(), err.primary.to_string()),
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_lex!(
+            r#"'a"b' "c'd" "e\"f" '\''"#,
+            r#"[(Str, "'a\"b'"), (Str, "\"c'd\""), (Str, "\"e\\\"f\""), (Str, "'\\''")]"#,
+        );
+        let unescaped = |lexeme| match &str_parts(lexeme, Span::default()).unwrap()[..] {
+            [StrPart::Lit(s)] => s.clone(),
+            parts => panic!("unexpected parts {parts:?}"),
+        };
+        assert_eq!("a\"b", unescaped(r#"'a"b'"#));
+        assert_eq!("\n\t\r\\\"'\0", unescaped(r#""\n\t\r\\\"\'\0""#));
+        assert_eq!("é😀!", unescaped(r"'\u{E9}\u{1f600}\u{21}'"));
+        assert_eq!("${a}", unescaped(r#""\${a}""#));
+    }
+
+    #[test]
+    fn interpolations() {
+        assert_lex!(
+            r#""a ${f("}", '${1}')} b" 'c${{}}'"#,
+            r#"[(Str, "\"a ${f(\"}\", '${1}')} b\""), (Str, "'c${{}}'")]"#,
+        );
+        assert_lex!(r#""a ${"b"; 1"#, r#"[(Error, "\"a ${\"b\"; 1")]"#,);
+        assert_eq!(
+            vec![
+                StrPart::Lit("\t".into()),
+                StrPart::Interp("x", 15),
+                StrPart::Interp(r#" {"k": "}"}["k"] "#, 19),
+                StrPart::Lit("!".into()),
+            ],
+            str_parts(r#""\t${x}${ {"k": "}"}["k"] }!""#, Span::new(10, 41)).unwrap(),
+        );
+
+        // Comments in interpolated expressions can contain `}`.
+        assert_lex!(
+            r#""${ 1 /* } /* } */ */ }" 2"#,
+            r#"[(Str, "\"${ 1 /* } /* } */ */ }\""), (Number, "2")]"#,
+        );
+        assert_lex!(r#""${ 1 // }" 2"#, r#"[(Error, "\"${ 1 // }\" 2")]"#);
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        let err = |lexeme| {
+            let err = str_parts(lexeme, Span::new(10, 10 + lexeme.len())).unwrap_err();
+            let diag = err.downcast::<Diagnostic>().unwrap();
+            assert_eq!(Code::InvalidEscape, diag.code);
+            (diag.msg, diag.primary.to_string())
+        };
+        assert_eq!(
+            ("unknown escape sequence `\\x`".into(), "13..15".into()),
+            err(r#""ab\x""#),
+        );
+        assert_eq!(
+            ("expected `{` after `\\u`".into(), "11..13".into()),
+            err(r"'\u41'"),
+        );
+        assert_eq!(
+            (
+                "expected `}` to end the Unicode escape".into(),
+                "11..16".into()
+            ),
+            err(r"'\u{41'"),
+        );
+        assert_eq!(
+            ("invalid Unicode escape `\\u{D800}`".into(), "11..19".into()),
+            err(r"'\u{D800}'"),
+        );
+        assert_eq!(
+            ("invalid Unicode escape `\\u{}`".into(), "11..15".into()),
+            err(r"'\u{}'"),
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let got = Lexer::with_offset("var é = 1;", 10)
+            .map(|t| (t.lexeme, t.span.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            r#"[("var", "10..13"), ("é", "14..16"), ("=", "17..18"), ("1", "19..20"), (";", "20..21")]"#,
+            format!("{got:?}"),
+        );
+    }
 }
@@ -11,12 +11,29 @@
 #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
 
 pub(crate) mod cmd;
+pub(crate) mod debugger;
+pub(crate) mod engine;
 pub(crate) mod error;
+pub(crate) mod fmt;
 pub(crate) mod interpreter;
 pub(crate) mod lexer;
+pub(crate) mod lsp;
+pub(crate) mod module;
 pub(crate) mod parser;
+pub(crate) mod repl;
 pub(crate) mod resolver;
 pub(crate) mod run;
+pub(crate) mod source;
 pub(crate) mod util;
+pub(crate) mod vm;
 
-pub use crate::cmd::Dolores;
+pub use crate::{
+    cmd::Dolores,
+    engine::Engine,
+    error::{CallFrame, Code, Diagnostic, Errors, Label, Severity},
+    interpreter::{
+        foreign::{Arity, Foreign},
+        object::Object,
+    },
+    source::Span,
+};
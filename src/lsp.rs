@@ -0,0 +1,544 @@
+mod tests;
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use crate::{
+    error::{Diagnostic, Errors, Severity},
+    interpreter::Interpreter,
+    lexer::{Lexer, Token, TokenType},
+    parser::{Parser, Stmt},
+    resolver::{Bindings, Resolver},
+    source::{SourceMap, Span},
+};
+
+// See: <https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#errorCodes>
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Runs the language server over the standard input and output.
+pub(crate) fn run_lsp() -> Result<()> {
+    serve(io::stdin().lock(), io::stdout().lock())
+}
+
+/// Serves the JSON-RPC messages read from `input` until the client exits,
+/// writing the responses and notifications to `output`.
+pub(crate) fn serve(mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut server = Server::default();
+    while let Some(msg) = read_message(&mut input)? {
+        let msg = match serde_json::from_str::<Value>(&msg) {
+            Ok(it) => it,
+            Err(e) => {
+                let err = json!({ "code": PARSE_ERROR, "message": e.to_string() });
+                write_message(
+                    &mut output,
+                    &json!({ "jsonrpc": "2.0", "id": null, "error": err }),
+                )?;
+                continue;
+            }
+        };
+        let method = msg["method"].as_str().unwrap_or_default();
+        let params = &msg["params"];
+        if method == "exit" {
+            return if server.shut_down {
+                Ok(())
+            } else {
+                Err(anyhow!("the client exited before shutting the server down"))
+            };
+        }
+        let Some(id) = msg.get("id") else {
+            // A notification, which expects no response.
+            for it in server.notify(method, params) {
+                write_message(&mut output, &it)?;
+            }
+            continue;
+        };
+        let res = match server.request(method, params) {
+            Some(Ok(res)) => json!({ "jsonrpc": "2.0", "id": id, "result": res }),
+            Some(Err(e)) => {
+                let err = json!({ "code": INVALID_PARAMS, "message": format!("{e:#}") });
+                json!({ "jsonrpc": "2.0", "id": id, "error": err })
+            }
+            None => {
+                let msg = format!("unsupported method `{method}`");
+                let err = json!({ "code": METHOD_NOT_FOUND, "message": msg });
+                json!({ "jsonrpc": "2.0", "id": id, "error": err })
+            }
+        };
+        write_message(&mut output, &res)?;
+    }
+    Ok(())
+}
+
+/// Reads the content of the next message, or `None` at the end of `input`.
+pub(crate) fn read_message(input: &mut impl BufRead) -> Result<Option<String>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, val)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                len = Some(val.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let len = len.context("found a message without `Content-Length`")?;
+    let mut buf = vec![0; len];
+    input.read_exact(&mut buf)?;
+    Ok(Some(String::from_utf8(buf)?))
+}
+
+pub(crate) fn write_message(output: &mut impl Write, msg: &Value) -> Result<()> {
+    let msg = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{msg}", msg.len())?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Returns the value at the JSON `pointer` in `params`.
+fn param<'v>(params: &'v Value, pointer: &str) -> Result<&'v Value> {
+    params
+        .pointer(pointer)
+        .with_context(|| format!("missing parameter `{pointer}`"))
+}
+
+fn str_param<'v>(params: &'v Value, pointer: &str) -> Result<&'v str> {
+    param(params, pointer)?
+        .as_str()
+        .with_context(|| format!("expected parameter `{pointer}` to be a string"))
+}
+
+#[derive(Debug, Default)]
+struct Server {
+    docs: HashMap<String, Document>,
+    /// If the client has requested a `shutdown`.
+    shut_down: bool,
+}
+
+impl Server {
+    /// Handles the notification `method`, returning the notifications to be
+    /// sent back.
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = |params| str_param(params, "/textDocument/uri").map(str::to_owned);
+        match method {
+            "textDocument/didOpen" => {
+                let (Ok(uri), Ok(text)) = (uri(params), str_param(params, "/textDocument/text"))
+                else {
+                    return vec![];
+                };
+                let doc = Document::new(&uri, text);
+                let diags = doc.publish_diagnostics(&uri);
+                self.docs.insert(uri, doc);
+                vec![diags]
+            }
+            "textDocument/didChange" => {
+                // Only full syncs are advertised, so the last change holds the whole text.
+                let text = (params["contentChanges"].as_array())
+                    .and_then(|it| it.last()?["text"].as_str());
+                let (Ok(uri), Some(text)) = (uri(params), text) else {
+                    return vec![];
+                };
+                let doc = Document::new(&uri, text);
+                let diags = doc.publish_diagnostics(&uri);
+                self.docs.insert(uri, doc);
+                vec![diags]
+            }
+            "textDocument/didClose" => {
+                let Ok(uri) = uri(params) else {
+                    return vec![];
+                };
+                self.docs.remove(&uri);
+                vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                })]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Handles the request `method`, or returns `None` if it's not supported.
+    fn request(&mut self, method: &str, params: &Value) -> Option<Result<Value>> {
+        let res = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    // Full syncs only.
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                    "renameProvider": true,
+                },
+                "serverInfo": {
+                    "name": clap::crate_name!(),
+                    "version": clap::crate_version!(),
+                },
+            })),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.at_position(params).map(|(uri, doc, off)| {
+                doc.definition(off)
+                    .map_or(Value::Null, |decl| doc.location(&uri, decl.span))
+            }),
+            "textDocument/references" => self.at_position(params).map(|(uri, doc, off)| {
+                let with_decl = params
+                    .pointer("/context/includeDeclaration")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(true);
+                let refs = doc.references(off).unwrap_or_default();
+                let refs = refs.into_iter().skip(usize::from(!with_decl));
+                Value::Array(refs.map(|it| doc.location(&uri, it.span)).collect())
+            }),
+            "textDocument/hover" => self
+                .at_position(params)
+                .map(|(_, doc, off)| doc.hover(off).unwrap_or(Value::Null)),
+            "textDocument/documentSymbol" => self
+                .document(params)
+                .map(|(_, doc)| Value::Array(doc.symbols(&doc.stmts))),
+            "textDocument/rename" => self.rename(params),
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    /// Returns the document referred to in `params`.
+    fn document(&self, params: &Value) -> Result<(String, &Document)> {
+        let uri = str_param(params, "/textDocument/uri")?;
+        let doc = (self.docs.get(uri)).with_context(|| format!("unknown document `{uri}`"))?;
+        Ok((uri.to_owned(), doc))
+    }
+
+    /// Returns the document and the offset of the position referred to in
+    /// `params`.
+    fn at_position(&self, params: &Value) -> Result<(String, &Document, usize)> {
+        let (uri, doc) = self.document(params)?;
+        let off = doc.offset(param(params, "/position")?)?;
+        Ok((uri, doc, off))
+    }
+
+    fn rename(&self, params: &Value) -> Result<Value> {
+        let (uri, doc, off) = self.at_position(params)?;
+        let new_name = str_param(params, "/newName")?;
+        let tokens = Lexer::with_offset(new_name, 0).collect_vec();
+        if !matches!(&tokens[..], [it] if it.ty == TokenType::Identifier && it.lexeme == new_name) {
+            bail!("`{new_name}` is not a valid identifier");
+        }
+        let Some(refs) = doc.references(off) else {
+            return Ok(Value::Null);
+        };
+        let edits = refs
+            .iter()
+            .map(|it| json!({ "range": doc.range(it.span), "newText": new_name }))
+            .collect_vec();
+        Ok(json!({ "changes": { uri: edits } }))
+    }
+}
+
+/// An open document, together with the results of analyzing it.
+#[derive(Debug)]
+struct Document {
+    text: String,
+    /// The offset of the text in its `SourceMap`.
+    start: usize,
+    /// The offsets of the beginnings of the lines of the text.
+    line_starts: Vec<usize>,
+    /// The tokens lexed, excluding comments.
+    tokens: Vec<Token>,
+    /// The statements parsed, which might be incomplete if there are syntax
+    /// errors.
+    stmts: Vec<Stmt>,
+    bindings: Bindings,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    /// Analyzes the given text, collecting the diagnostics from the lexer,
+    /// the parser and, if there is no syntax error, the resolver.
+    fn new(uri: &str, text: &str) -> Self {
+        let mut sources = SourceMap::default();
+        let start = sources.add(uri, text);
+        let (stmts, errors) = Parser::new(Lexer::with_offset(text, start)).parse_all();
+        let mut resolver = Resolver::new(Interpreter::default());
+        for it in &stmts {
+            resolver.resolve_stmt(it.clone());
+        }
+        let bindings = resolver.bindings();
+        let diagnostics = if errors.is_empty() {
+            match resolver.finish().1 {
+                Ok(warnings) => warnings,
+                Err(e) => diagnostics_of(&e),
+            }
+        } else {
+            errors.iter().flat_map(diagnostics_of).collect()
+        };
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let tokens = (Lexer::with_offset(text, start))
+            .filter(|it| !it.ty.is_trivia())
+            .collect();
+        Self {
+            text: text.into(),
+            start,
+            line_starts,
+            tokens,
+            stmts,
+            bindings,
+            diagnostics,
+        }
+    }
+
+    /// Returns the LSP position of the byte at `offset` in the `SourceMap`,
+    /// whose character is counted in UTF-16 code units.
+    fn position(&self, offset: usize) -> Value {
+        let idx = offset.saturating_sub(self.start).min(self.text.len());
+        let line = self.line_starts.partition_point(|&it| it <= idx) - 1;
+        let character = self.text[self.line_starts[line]..idx]
+            .encode_utf16()
+            .count();
+        json!({ "line": line, "character": character })
+    }
+
+    /// Returns the offset in the `SourceMap` of the LSP `position`, which is
+    /// clamped to the end of its line.
+    fn offset(&self, position: &Value) -> Result<usize> {
+        let get = |key| {
+            (position[key].as_u64())
+                .and_then(|it| usize::try_from(it).ok())
+                .with_context(|| format!("expected `{key}` to be a position"))
+        };
+        let (line, character) = (get("line")?, get("character")?);
+        let Some(&line_start) = self.line_starts.get(line) else {
+            return Ok(self.start + self.text.len());
+        };
+        let rest = self.text[line_start..]
+            .split('\n')
+            .next()
+            .unwrap_or_default();
+        let mut units = 0;
+        let idx = (rest.char_indices())
+            .find(|&(_, c)| {
+                units += c.len_utf16();
+                units > character
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        Ok(self.start + line_start + idx)
+    }
+
+    fn range(&self, span: Span) -> Value {
+        json!({ "start": self.position(span.start), "end": self.position(span.end) })
+    }
+
+    fn location(&self, uri: &str, span: Span) -> Value {
+        json!({ "uri": uri, "range": self.range(span) })
+    }
+
+    /// Returns the declaration of the name at `offset`, which might be the
+    /// name itself.
+    fn definition(&self, offset: usize) -> Option<&Token> {
+        let covers = |it: &Token| (it.span.start..=it.span.end).contains(&offset);
+        let Bindings { decls, uses } = &self.bindings;
+        (uses
+            .iter()
+            .find_map(|(name, decl)| covers(name).then_some(decl)))
+        .or_else(|| decls.iter().find(|it| covers(it)))
+    }
+
+    /// Returns the declaration of the name at `offset` followed by all its
+    /// uses in order.
+    fn references(&self, offset: usize) -> Option<Vec<&Token>> {
+        let decl = self.definition(offset)?;
+        let uses = (self.bindings.uses.iter())
+            .filter_map(|(name, it)| (it == decl).then_some(name))
+            .sorted_by_key(|it| it.span.start);
+        Some(std::iter::once(decl).chain(uses).collect())
+    }
+
+    /// Returns the hover showing the declaration of the name at `offset`.
+    fn hover(&self, offset: usize) -> Option<Value> {
+        let decl = self.definition(offset)?;
+        let pos = decl.span.start - self.start;
+        let line_start = self.text[..pos].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.text[pos..]
+            .find('\n')
+            .map_or(self.text.len(), |i| pos + i);
+        let line = self.text[line_start..line_end].trim();
+        let line = line.strip_suffix('{').unwrap_or(line).trim_end();
+        Some(json!({
+            "contents": { "kind": "markdown", "value": format!("```lox\n{line}\n```") },
+            "range": self.range(decl.span),
+        }))
+    }
+
+    /// Returns the span of the whole class or function declaration of `name`,
+    /// from its keyword, if any, to the `}` closing its body.
+    fn decl_span(&self, name: &Token) -> Span {
+        let Some(idx) = self.tokens.iter().position(|it| it == name) else {
+            return name.span;
+        };
+        let start = match idx.checked_sub(1).map(|i| &self.tokens[i]) {
+            Some(kw) if matches!(kw.ty, TokenType::Class | TokenType::Fun) => kw.span.start,
+            _ => name.span.start,
+        };
+        // The body might be unterminated if there are syntax errors.
+        let mut depth = 0_usize;
+        let end = (self.tokens[idx..].iter())
+            .find(|it| match it.ty {
+                TokenType::LeftBrace => {
+                    depth += 1;
+                    false
+                }
+                TokenType::RightBrace if depth <= 1 => true,
+                TokenType::RightBrace => {
+                    depth -= 1;
+                    false
+                }
+                _ => false,
+            })
+            .map_or(self.start + self.text.len(), |it| it.span.end);
+        Span::new(start, end)
+    }
+
+    /// Returns the symbols of the classes and functions declared in `stmts`,
+    /// where those nested in a declaration are its children.
+    fn symbols(&self, stmts: &[Stmt]) -> Vec<Value> {
+        // See: <https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#symbolKind>
+        const CLASS: u8 = 5;
+        const METHOD: u8 = 6;
+        const CONSTRUCTOR: u8 = 9;
+        const FUNCTION: u8 = 12;
+        let symbol = |name: &Token, kind, detail: String, children| {
+            json!({
+                "name": name.lexeme,
+                "detail": detail,
+                "kind": kind,
+                "range": self.range(self.decl_span(name)),
+                "selectionRange": self.range(name.span),
+                "children": children,
+            })
+        };
+        let params = |params: &[Token]| format!("({})", params.iter().join(", "));
+        let mut res = vec![];
+        for stmt in stmts {
+            match stmt {
+                Stmt::Class {
+                    name,
+                    superclass,
+                    methods,
+                } => {
+                    let methods = methods.iter().filter_map(|it| {
+                        let Stmt::Fun {
+                            name,
+                            params: ps,
+                            body,
+                        } = it
+                        else {
+                            return None;
+                        };
+                        let kind = if name.lexeme == "init" {
+                            CONSTRUCTOR
+                        } else {
+                            METHOD
+                        };
+                        Some(symbol(name, kind, params(ps), self.symbols(body)))
+                    });
+                    let detail = superclass
+                        .as_ref()
+                        .map_or_else(String::new, |it| format!("< {it}"));
+                    res.push(symbol(name, CLASS, detail, methods.collect()));
+                }
+                Stmt::Fun {
+                    name,
+                    params: ps,
+                    body,
+                } => res.push(symbol(name, FUNCTION, params(ps), self.symbols(body))),
+                Stmt::Block(stmts) => res.extend(self.symbols(stmts)),
+                Stmt::If {
+                    then_stmt,
+                    else_stmt,
+                    ..
+                } => {
+                    res.extend(self.symbols(std::slice::from_ref(then_stmt)));
+                    if let Some(it) = else_stmt {
+                        res.extend(self.symbols(std::slice::from_ref(it)));
+                    }
+                }
+                Stmt::Try {
+                    body,
+                    catch,
+                    finally,
+                    ..
+                } => {
+                    res.extend(self.symbols(body));
+                    if let Some((_, body)) = catch {
+                        res.extend(self.symbols(body));
+                    }
+                    if let Some(body) = finally {
+                        res.extend(self.symbols(body));
+                    }
+                }
+                Stmt::While { body, .. } => res.extend(self.symbols(std::slice::from_ref(body))),
+                _ => (),
+            }
+        }
+        res
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Value {
+        let diags = (self.diagnostics.iter())
+            .map(|it| {
+                let related = (it.secondary.iter())
+                    .map(|it| json!({ "location": self.location(uri, it.span), "message": it.msg }))
+                    .collect_vec();
+                let msg = std::iter::once(&it.msg).chain(&it.notes).join("\n");
+                json!({
+                    "range": self.range(it.primary),
+                    "severity": match it.severity {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                    },
+                    "code": it.code.as_str(),
+                    "source": clap::crate_name!(),
+                    "message": msg,
+                    "relatedInformation": related,
+                })
+            })
+            .collect_vec();
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diags },
+        })
+    }
+}
+
+/// Returns the diagnostics found in the error `e`.
+fn diagnostics_of(e: &anyhow::Error) -> Vec<Diagnostic> {
+    if let Some(Errors(errors)) = e.downcast_ref() {
+        return errors.iter().flat_map(diagnostics_of).collect();
+    }
+    // The outermost error might be a context, which is only accessible from
+    // `e` itself.
+    (e.downcast_ref::<Diagnostic>())
+        .or_else(|| e.chain().find_map(|it| it.downcast_ref()))
+        .cloned()
+        .into_iter()
+        .collect()
+}
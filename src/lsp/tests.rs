@@ -0,0 +1,302 @@
+#![cfg(test)]
+
+use indoc::indoc;
+use itertools::Itertools;
+use pretty_assertions::assert_eq;
+use serde_json::{json, Value};
+
+use super::*;
+use crate::error::Code;
+
+const URI: &str = "file:///main.lox";
+
+const SRC: &str = indoc! {r#"
+    fun fib(n) {
+        if (n < 2) return n;
+        return fib(n - 1) + fib(n - 2);
+    }
+    var x = fib(10);
+    {
+        var y = "${x}!";
+        print y;
+    }
+    class A < B {
+        init(a) { this.a = a; }
+        get() { fun helper() {} return helper; }
+    }
+    var s = "😀"; print s;
+"#};
+
+/// Runs a session with the given messages, which is then shut down,
+/// returning the messages sent by the server.
+fn session(msgs: &[Value]) -> Vec<Value> {
+    let shutdown = json!({ "jsonrpc": "2.0", "id": "shutdown", "method": "shutdown" });
+    let exit = json!({ "jsonrpc": "2.0", "method": "exit" });
+    let input = (msgs.iter().chain([&shutdown, &exit]))
+        .map(|it| {
+            let it = it.to_string();
+            format!("Content-Length: {}\r\n\r\n{it}", it.len())
+        })
+        .join("");
+    let mut output = vec![];
+    serve(input.as_bytes(), &mut output).unwrap();
+    let mut output = &output[..];
+    let mut res = vec![];
+    while let Some(msg) = read_message(&mut output).unwrap() {
+        res.push(serde_json::from_str(&msg).unwrap());
+    }
+    assert_eq!(
+        Some(&json!({ "jsonrpc": "2.0", "id": "shutdown", "result": null })),
+        res.pop().as_ref(),
+    );
+    res
+}
+
+/// Runs a session with the given messages, returning the responses.
+fn responses(msgs: &[Value]) -> Vec<Value> {
+    let mut res = session(msgs);
+    res.retain(|it| it.get("id").is_some());
+    res
+}
+
+fn open(text: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": { "uri": URI, "languageId": "lox", "version": 1, "text": text },
+        },
+    })
+}
+
+fn request(id: u64, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+fn at(line: u64, character: u64) -> Value {
+    json!({
+        "textDocument": { "uri": URI },
+        "position": { "line": line, "character": character },
+    })
+}
+
+/// Returns the starting positions of the given ranges or locations.
+fn starts(vals: &Value) -> Vec<(u64, u64)> {
+    (vals.as_array().unwrap().iter())
+        .map(|it| {
+            let start = it.get("range").unwrap_or(it)["start"].clone();
+            (
+                start["line"].as_u64().unwrap(),
+                start["character"].as_u64().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn lifecycle() {
+    let res = session(&[
+        request(1, "initialize", json!({ "capabilities": {} })),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        request(2, "textDocument/formatting", json!({})),
+        request(3, "textDocument/hover", at(0, 0)),
+    ]);
+    let caps = &res[0]["result"]["capabilities"];
+    assert_eq!(json!(1), caps["textDocumentSync"]);
+    assert_eq!(json!(true), caps["renameProvider"]);
+    assert_eq!(json!(METHOD_NOT_FOUND), res[1]["error"]["code"]);
+    assert_eq!(json!(INVALID_PARAMS), res[2]["error"]["code"]);
+    assert_eq!(3, res.len());
+
+    // The client must not exit before shutting the server down.
+    let exit = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+    let input = format!("Content-Length: {}\r\n\r\n{exit}", exit.len());
+    assert!(serve(input.as_bytes(), vec![]).is_err());
+}
+
+#[test]
+fn diagnostics() {
+    let change = |text| {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": text }],
+            },
+        })
+    };
+    let res = session(&[
+        open("var s = \"abc;"),
+        change("print 1"),
+        change("return 1;"),
+        change("fun f(a) {\n  var b = 1;\n}"),
+        change(SRC),
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didClose",
+            "params": { "textDocument": { "uri": URI } },
+        }),
+    ]);
+    let got = (res.iter())
+        .map(|it| {
+            assert_eq!("textDocument/publishDiagnostics", it["method"]);
+            assert_eq!(URI, it["params"]["uri"]);
+            let diags = it["params"]["diagnostics"].as_array().unwrap();
+            (diags.iter())
+                .map(|it| {
+                    (
+                        it["code"].as_str().unwrap(),
+                        it["severity"].as_u64().unwrap(),
+                    )
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+    let expected = vec![
+        vec![(Code::InvalidToken.as_str(), 1)],
+        vec![(Code::UnexpectedToken.as_str(), 1)],
+        vec![(Code::ReturnOutOfFun.as_str(), 1)],
+        vec![
+            (Code::UnusedParam.as_str(), 2),
+            (Code::UnusedVariable.as_str(), 2),
+        ],
+        vec![],
+        vec![],
+    ];
+    assert_eq!(expected, got);
+    assert_eq!(
+        vec![(0, 6), (1, 6)],
+        starts(&res[3]["params"]["diagnostics"])
+    );
+    let msg = res[3]["params"]["diagnostics"][0]["message"]
+        .as_str()
+        .unwrap();
+    assert_eq!(
+        "unused parameter `a`\nprefix it with an underscore to silence this warning: `_a`",
+        msg
+    );
+}
+
+#[test]
+fn definition() {
+    let res = responses(&[
+        open(SRC),
+        // `fib` used in its own body.
+        request(1, "textDocument/definition", at(2, 12)),
+        // `n` used as a parameter.
+        request(2, "textDocument/definition", at(1, 22)),
+        // `x` used in an interpolation, including the position after it.
+        request(3, "textDocument/definition", at(6, 16)),
+        // `s` after an emoji, whose columns count UTF-16 code units.
+        request(4, "textDocument/definition", at(13, 20)),
+        // Declarations refer to themselves.
+        request(5, "textDocument/definition", at(4, 4)),
+        // Nothing is declared by `print`.
+        request(6, "textDocument/definition", at(7, 5)),
+    ]);
+    let got = (res.iter())
+        .map(|it| {
+            assert_eq!(URI, it["result"]["uri"]);
+            starts(&json!([it["result"]]))[0]
+        })
+        .take(5)
+        .collect_vec();
+    assert_eq!(vec![(0, 4), (0, 8), (4, 4), (13, 4), (4, 4)], got);
+    assert_eq!(Value::Null, res[5]["result"]);
+}
+
+#[test]
+fn references() {
+    let mut ctx = at(2, 12);
+    ctx["context"] = json!({ "includeDeclaration": false });
+    let res = responses(&[
+        open(SRC),
+        request(1, "textDocument/references", at(2, 15)),
+        request(2, "textDocument/references", ctx),
+        request(3, "textDocument/references", at(6, 8)),
+    ]);
+    let got = res.iter().map(|it| starts(&it["result"])).collect_vec();
+    let expected = vec![
+        vec![(0, 8), (1, 8), (1, 22), (2, 15), (2, 28)],
+        vec![(2, 11), (2, 24), (4, 8)],
+        vec![(6, 8), (7, 10)],
+    ];
+    assert_eq!(expected, got);
+}
+
+#[test]
+fn hover() {
+    let res = responses(&[
+        open(SRC),
+        request(1, "textDocument/hover", at(4, 9)),
+        request(2, "textDocument/hover", at(6, 15)),
+        request(3, "textDocument/hover", at(3, 0)),
+    ]);
+    let contents = |it: &Value| it["result"]["contents"]["value"].clone();
+    assert_eq!(json!("```lox\nfun fib(n)\n```"), contents(&res[0]));
+    assert_eq!(vec![(0, 4)], starts(&json!([res[0]["result"]])));
+    assert_eq!(json!("```lox\nvar x = fib(10);\n```"), contents(&res[1]));
+    assert_eq!(Value::Null, res[2]["result"]);
+}
+
+#[test]
+fn document_symbols() {
+    fn outline(syms: &Value) -> Vec<String> {
+        (syms.as_array().unwrap().iter())
+            .map(|it| {
+                let children = outline(&it["children"]);
+                format!(
+                    "{} {}{} {}",
+                    it["kind"],
+                    it["name"].as_str().unwrap(),
+                    it["detail"].as_str().unwrap(),
+                    children.join(" "),
+                )
+                .trim_end()
+                .to_owned()
+            })
+            .collect()
+    }
+    let res = responses(&[
+        open(SRC),
+        request(1, "textDocument/documentSymbol", at(0, 0)),
+    ]);
+    let expected = vec!["12 fib(n)", "5 A< B 9 init(a) 6 get() 12 helper()"];
+    assert_eq!(expected, outline(&res[0]["result"]));
+    let syms = &res[0]["result"];
+    let range = |start: (u64, u64), end: (u64, u64)| {
+        json!({
+            "start": { "line": start.0, "character": start.1 },
+            "end": { "line": end.0, "character": end.1 },
+        })
+    };
+    assert_eq!(range((0, 0), (3, 1)), syms[0]["range"]);
+    assert_eq!(range((0, 4), (0, 7)), syms[0]["selectionRange"]);
+    assert_eq!(range((9, 0), (12, 1)), syms[1]["range"]);
+    assert_eq!(range((9, 6), (9, 7)), syms[1]["selectionRange"]);
+    // Methods have no keyword.
+    assert_eq!(range((10, 4), (10, 27)), syms[1]["children"][0]["range"]);
+}
+
+#[test]
+fn rename() {
+    let rename = |id, line, character, new_name| {
+        let mut params = at(line, character);
+        params["newName"] = json!(new_name);
+        request(id, "textDocument/rename", params)
+    };
+    let res = responses(&[
+        open(SRC),
+        rename(1, 4, 4, "y"),
+        rename(2, 3, 0, "y"),
+        rename(3, 4, 4, "fun"),
+        rename(4, 4, 4, "1x"),
+    ]);
+    let edits = &res[0]["result"]["changes"][URI];
+    assert_eq!(vec![(4, 4), (6, 15)], starts(edits));
+    assert!((edits.as_array().unwrap().iter()).all(|it| it["newText"] == "y"));
+    assert_eq!(Value::Null, res[1]["result"]);
+    assert_eq!(json!(INVALID_PARAMS), res[2]["error"]["code"]);
+    assert_eq!(json!(INVALID_PARAMS), res[3]["error"]["code"]);
+}
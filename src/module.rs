@@ -0,0 +1,137 @@
+//! Loading modules for `import` declarations, shared by all backends.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use itertools::Itertools;
+
+use crate::{
+    bail,
+    error::Code,
+    interpreter::{Env, Module},
+    lexer::Lexer,
+    parser::Parser,
+    report,
+    run::Backend,
+    source::{SourceMap, Span},
+    util::MutCell,
+};
+
+/// The modules of a session, each of which is run at most once.
+#[derive(Debug, Clone)]
+pub(crate) struct Modules {
+    /// The builtins visible from every module, which is the outer
+    /// environment of the globals of each module.
+    pub(crate) builtins: MutCell<Env>,
+    /// The modules loaded so far, by canonical path.
+    loaded: HashMap<PathBuf, Module>,
+    /// The modules being loaded, outermost first.
+    loading: Vec<PathBuf>,
+}
+
+impl Modules {
+    #[must_use]
+    pub(crate) fn new(builtins: &MutCell<Env>) -> Self {
+        Self {
+            builtins: builtins.clone(),
+            loaded: HashMap::new(),
+            loading: vec![],
+        }
+    }
+
+    /// Creates the global environment of a new module.
+    #[must_use]
+    pub(crate) fn new_globals(&self) -> MutCell<Env> {
+        Env::from_outer(&self.builtins).shared()
+    }
+}
+
+impl Default for Modules {
+    fn default() -> Self {
+        Self::new(&Env::default().shared())
+    }
+}
+
+/// Imports the module at `path` for the `import` declaration at `span`,
+/// running it first if it has not been loaded yet.
+pub(crate) fn import(backend: &mut impl Backend, path: &str, span: Span) -> Result<Module> {
+    let ctx = "while importing a module";
+    let path = locate(backend.sources(), path, span)?;
+    let modules = backend.modules();
+    if let Some(module) = modules.loaded.get(&path) {
+        return Ok(module.clone());
+    }
+    if let Some(idx) = modules.loading.iter().position(|it| *it == path) {
+        let cycle = (modules.loading[idx..].iter())
+            .chain([&path])
+            .map(|it| format!("`{}`", it.display()))
+            .join(" -> ");
+        bail!(Code::CyclicImport, span, ctx, "cyclic import: {}", cycle);
+    }
+
+    let name = path.display().to_string();
+    let src = std::fs::read_to_string(&path).map_err(|e| {
+        report!(
+            Code::ImportFailed,
+            span,
+            ctx,
+            "cannot read module `{name}`: {e}",
+        )
+    })?;
+    let offset = backend.sources().add(&name, &src);
+    let stmts = Parser::new(Lexer::with_offset(&src, offset)).parse()?;
+    backend.modules().loading.push(path.clone());
+    let globals = backend.exec_module(stmts);
+    let modules = backend.modules();
+    modules.loading.pop();
+    let module = Module::new(&name, globals?);
+    modules.loaded.insert(path, module.clone());
+    Ok(module)
+}
+
+/// Runs the entry file at `path` with `run`, registering it as a module being
+/// loaded, so that importing it back is reported as a cyclic import instead
+/// of running it again.
+///
+/// The entry file is then loaded as the module of the globals of `backend`.
+pub(crate) fn run_entry<B: Backend, T>(
+    backend: &mut B,
+    path: &Path,
+    run: impl FnOnce(&mut B) -> T,
+) -> T {
+    // A source that is not a file, e.g. `<stdin>`, cannot be imported.
+    let Ok(path) = path.canonicalize() else {
+        return run(backend);
+    };
+    backend.modules().loading.push(path.clone());
+    let res = run(backend);
+    let module = Module::new(&path.display().to_string(), backend.globals().clone());
+    let modules = backend.modules();
+    modules.loading.pop();
+    modules.loaded.insert(path, module);
+    res
+}
+
+/// Finds the file of the module `path` imported at `span`, relative to the
+/// importing file, or to the working directory if the importing source is not
+/// a file, e.g. `<stdin>`.
+fn locate(sources: &SourceMap, path: &str, span: Span) -> Result<PathBuf> {
+    let importer = (sources.file(span.start))
+        .map(|it| Path::new(&it.name))
+        .filter(|it| it.is_file());
+    let dir = importer
+        .and_then(Path::parent)
+        .unwrap_or_else(|| Path::new(""));
+    dir.join(path).canonicalize().map_err(|e| {
+        report!(
+            Code::ImportFailed,
+            span,
+            "while importing a module",
+            "cannot find module `{path}`: {e}",
+        )
+        .into()
+    })
+}
@@ -1,9 +1,9 @@
 pub(crate) mod expr;
 pub(crate) mod stmt;
 
-use std::{fmt::Display, iter::Peekable};
+use std::{fmt::Display, iter::Peekable, mem};
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use itertools::Itertools;
 
 pub(crate) use self::{
@@ -13,16 +13,23 @@ pub(crate) use self::{
 use crate::lexer::Lexer;
 #[allow(clippy::enum_glob_use)]
 use crate::{
-    error::report,
+    bail,
+    error::{Code, Errors},
     lexer::{
         Token,
         TokenType::{self, *},
     },
+    report,
+    source::Span,
 };
 
 pub(crate) struct Parser<'s> {
     tokens: Peekable<Lexer<'s>>,
     prev: Option<Token>,
+    /// The errors recovered from so far.
+    errors: Vec<anyhow::Error>,
+    /// If no token has been consumed since the last `sync`.
+    synced: bool,
 }
 
 impl<'s> Parser<'s> {
@@ -30,16 +37,22 @@ impl<'s> Parser<'s> {
         Self {
             tokens: tokens.peekable(),
             prev: None,
+            errors: vec![],
+            synced: false,
         }
     }
 
+    /// Peeks the next token, skipping the comments before it, since they are
+    /// trivia to the parser.
     fn peek(&mut self) -> Option<&Token> {
+        while self.tokens.next_if(|t| t.ty.is_trivia()).is_some() {}
         self.tokens.peek()
     }
 
     fn advance(&mut self) -> Option<&Token> {
         let new_prev = self.peek()?.clone();
         self.prev.replace(new_prev);
+        self.synced = false;
         self.tokens.next();
         self.prev.as_ref()
     }
@@ -48,6 +61,12 @@ impl<'s> Parser<'s> {
         self.prev.as_ref()
     }
 
+    /// Returns the span of the previous token, which is where most syntax
+    /// errors are reported.
+    fn prev_span(&self) -> Span {
+        self.previous().map_or_else(Span::default, |t| t.span)
+    }
+
     fn check(&mut self, ty: TokenType) -> Option<&Token> {
         self.peek().filter(|&t| t.ty == ty)
     }
@@ -64,30 +83,59 @@ impl<'s> Parser<'s> {
 
     /// Consumes a specific token or throws an error.
     fn consume(&mut self, tys: &[TokenType], ctx: &str, msg: impl Display) -> Result<Token> {
+        let span = self.prev_span();
         self.test(tys)
             .cloned()
-            .with_context(|| report(self.previous().unwrap().pos, ctx, msg))
+            .ok_or_else(|| report!(Code::UnexpectedToken, span, ctx, msg).into())
     }
 
+    /// Skips the rest of the current statement after an error.
+    ///
+    /// This stops right after a `;`, or right before the beginning of another
+    /// statement (including a block) or the end of a block. Calling this again
+    /// without consuming any token in between does nothing.
     fn sync(&mut self) {
-        let stmt_begin = [Class, Fun, Var, For, If, While, Print, Return];
-        loop {
+        if self.synced {
+            return;
+        }
+        let stmt_begin = [
+            Class, Fun, Var, Import, From, For, If, While, Print, Return, Throw, Try, LeftBrace,
+            RightBrace,
+        ];
+        while let Some(ty) = self.peek().map(|it| it.ty) {
+            if stmt_begin.contains(&ty) {
+                break;
+            }
             self.advance();
-            let curr_ty = self.peek().map(|it| it.ty);
-            let synced = curr_ty.is_none() // Reached the end of the source.
-                || self.previous().unwrap().ty == Semicolon // Passed the end of the statement.
-                || stmt_begin.contains(&curr_ty.unwrap()); // Reached the beginning of another statement.
-            if synced {
+            if ty == Semicolon {
                 break;
             }
         }
+        self.synced = true;
     }
 
-    pub(crate) fn many<T>(
-        &mut self,
-        mut parser: impl FnMut(&mut Self) -> Result<T>,
-    ) -> Result<Vec<T>> {
-        std::iter::from_fn(|| self.peek().is_some().then(|| parser(self))).try_collect()
+    /// Parses declarations till the token `till` (or the end of the source if
+    /// `None`), recovering from errors by skipping to the next declaration.
+    ///
+    /// The errors are memorized in `self.errors`, while the declarations that
+    /// failed to parse are left out of the result.
+    pub(crate) fn decls(&mut self, till: Option<TokenType>) -> Vec<Stmt> {
+        let mut res = vec![];
+        while let Some(t) = self.peek().filter(|t| Some(t.ty) != till) {
+            let start = t.span;
+            match self.decl() {
+                Ok(stmt) => res.push(stmt),
+                Err(e) => {
+                    self.errors.push(e);
+                    // Make sure that some progress is made.
+                    if self.peek().map(|t| t.span) == Some(start) {
+                        self.advance();
+                    }
+                    self.sync();
+                }
+            }
+        }
+        res
     }
 
     pub(crate) fn many_till<T>(
@@ -115,7 +163,53 @@ impl<'s> Parser<'s> {
         Ok(res)
     }
 
+    /// Parses the whole source, returning the declarations parsed (leaving
+    /// out the ill-formed ones) together with all the errors found.
+    pub(crate) fn parse_all(&mut self) -> (Vec<Stmt>, Vec<anyhow::Error>) {
+        let stmts = self.decls(None);
+        (stmts, mem::take(&mut self.errors))
+    }
+
+    /// Parses the whole source, failing with all the errors found if any.
     pub(crate) fn parse(&mut self) -> Result<Vec<Stmt>> {
-        self.many(Self::decl)
+        match self.parse_all() {
+            (stmts, errors) if errors.is_empty() => Ok(stmts),
+            (_, errors) => Err(Errors::combine(errors)),
+        }
+    }
+
+    /// Parses a single expression which should span the whole source.
+    pub(crate) fn parse_expr(&mut self) -> Result<Expr> {
+        let res = self.expr().and_then(|expr| {
+            if let Some(t) = self.peek() {
+                bail!(
+                    Code::UnexpectedToken,
+                    t.span,
+                    "while parsing an Expression",
+                    "unexpected trailing token `{}`",
+                    t.lexeme,
+                );
+            }
+            Ok(expr)
+        });
+        self.finish(res)
+    }
+
+    /// Parses a single expression at the beginning of the source, ignoring the
+    /// tokens after it.
+    pub(crate) fn parse_leading_expr(&mut self) -> Result<Expr> {
+        let res = self.expr();
+        self.finish(res)
+    }
+
+    /// Fails with all the errors found so far, including the one in `res`.
+    fn finish<T>(&mut self, res: Result<T>) -> Result<T> {
+        let mut errors = mem::take(&mut self.errors);
+        match res {
+            Ok(res) if errors.is_empty() => return Ok(res),
+            Ok(_) => (),
+            Err(e) => errors.push(e),
+        }
+        Err(Errors::combine(errors))
     }
 }
@@ -10,14 +10,42 @@ use crate::util::disp_slice;
 #[allow(clippy::enum_glob_use)]
 use crate::{
     bail,
+    error::Code,
     lexer::{
-        Token,
+        lex_error, str_parts, Lexer, StrPart, Token,
         TokenType::{self, *},
     },
+    source::Span,
 };
 
 const MAX_FUN_ARG_COUNT: usize = 255;
 
+/// Parses the lexeme of a `Number` token, which is an `Int` unless it has a
+/// fractional part or an exponent, or is a decimal integer too large for an
+/// `Int`.
+///
+/// Integers can be written in hex (`0x`), binary (`0b`) or octal (`0o`), and
+/// digits can be separated with `_`.
+fn number_lit(lexeme: &str) -> Result<Lit, String> {
+    let digits = lexeme.replace('_', "");
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x") => (16, &digits[2..]),
+        Some("0b") => (2, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        _ if digits.contains(['.', 'e', 'E']) => {
+            return digits.parse().map(Lit::Number).map_err(|e| e.to_string());
+        }
+        _ => {
+            return (digits.parse().map(Lit::Int))
+                .or_else(|_| digits.parse().map(Lit::Number))
+                .map_err(|e| e.to_string());
+        }
+    };
+    i64::from_str_radix(digits, radix)
+        .map(Lit::Int)
+        .map_err(|e| e.to_string())
+}
+
 #[derive(Debug, Clone)]
 pub(crate) enum Expr {
     Assign {
@@ -39,13 +67,48 @@ pub(crate) enum Expr {
     Get {
         obj: Box<Expr>,
         name: Token,
+        /// If this is an optional chaining `?.`, which short-circuits the rest
+        /// of the chain to `nil` if `obj` is `nil`.
+        optional: bool,
     },
     Grouping(Box<Expr>),
+    /// A String literal with interpolated expressions, whose parts are
+    /// concatenated after being stringified.
+    Interpolation(Vec<Expr>),
+    Index {
+        obj: Box<Expr>,
+        idx: Box<Expr>,
+        /// The trailing `RightBracket` of the subscript.
+        /// Its position is memorized for error reports.
+        end: Token,
+    },
+    IndexSet {
+        obj: Box<Expr>,
+        idx: Box<Expr>,
+        end: Token,
+        /// The binary operator of a compound assignment, which is applied to
+        /// the old element and `to` without evaluating `obj` and `idx` twice.
+        op: Option<Token>,
+        to: Box<Expr>,
+    },
     Lambda {
+        /// The `fun` keyword of the Lambda expression.
+        /// Its position is memorized for error reports.
+        kw: Token,
         params: Vec<Token>,
         body: Vec<Stmt>,
     },
+    List(Vec<Expr>),
     Literal(Lit),
+    Map {
+        entries: Vec<(Expr, Expr)>,
+        /// The span of each key in `entries`.
+        /// They are memorized for error reports.
+        keys: Vec<Span>,
+        /// The trailing `RightBrace` of the Map literal.
+        /// Its position is memorized for error reports.
+        end: Token,
+    },
     Logical {
         lhs: Box<Expr>,
         op: Token,
@@ -54,12 +117,20 @@ pub(crate) enum Expr {
     Set {
         obj: Box<Expr>,
         name: Token,
+        /// The binary operator of a compound assignment, which is applied to
+        /// the old property and `to` without evaluating `obj` twice.
+        op: Option<Token>,
         to: Box<Expr>,
     },
     Super {
         kw: Token,
         method: Token,
     },
+    Ternary {
+        cond: Box<Expr>,
+        then_expr: Box<Expr>,
+        else_expr: Box<Expr>,
+    },
     This(Token),
     Unary {
         op: Token,
@@ -85,15 +156,71 @@ impl Display for Expr {
                     write!(f, "({} {})", callee, args.iter().join(" "))
                 }
             }
-            Get { obj, name } => write!(f, "(. {} {})", obj, name),
+            Get {
+                obj,
+                name,
+                optional,
+            } => {
+                let op = if *optional { "?." } else { "." };
+                write!(f, "({op} {obj} {name})")
+            }
             Grouping(expr) => write!(f, "{}", expr),
-            Lambda { params, body } => {
+            Interpolation(parts) => write!(f, "(interp {})", parts.iter().join(" ")),
+            Index { obj, idx, .. } => write!(f, "(index {obj} {idx})"),
+            IndexSet {
+                obj,
+                idx,
+                op: None,
+                to,
+                ..
+            } => write!(f, "(index-set! {obj} {idx} {to})"),
+            IndexSet {
+                obj,
+                idx,
+                op: Some(op),
+                to,
+                ..
+            } => write!(
+                f,
+                "(index-set! {obj} {idx} ({op} (index {obj} {idx}) {to}))"
+            ),
+            Lambda { params, body, .. } => {
                 let (params, body) = (disp_slice(params, false), disp_slice(body, true));
                 write!(f, "(lambda ({}) {})", params, body)
             }
+            List(elems) => {
+                if elems.is_empty() {
+                    write!(f, "(list)")
+                } else {
+                    write!(f, "(list {})", elems.iter().join(" "))
+                }
+            }
             Literal(lit) => write!(f, "{}", lit),
-            Set { obj, name, to } => write!(f, "(.set! {} {} {})", obj, name, to),
+            Map { entries, .. } => {
+                write!(f, "(map")?;
+                entries
+                    .iter()
+                    .try_for_each(|(k, v)| write!(f, " ({k} {v})"))?;
+                write!(f, ")")
+            }
+            Set {
+                obj,
+                name,
+                op: None,
+                to,
+            } => write!(f, "(.set! {obj} {name} {to})"),
+            Set {
+                obj,
+                name,
+                op: Some(op),
+                to,
+            } => write!(f, "(.set! {obj} {name} ({op} (. {obj} {name}) {to}))"),
             Super { method, .. } => write!(f, "(. (super) {})", method),
+            Ternary {
+                cond,
+                then_expr,
+                else_expr,
+            } => write!(f, "(?: {cond} {then_expr} {else_expr})"),
             This(_) => write!(f, "(this)"),
             Unary { op, rhs } => write!(f, "({} {})", op, rhs),
             Variable(var) => write!(f, "{}", var),
@@ -101,6 +228,36 @@ impl Display for Expr {
     }
 }
 
+impl Expr {
+    /// Returns the span of the leftmost token memorized in this expression, if
+    /// any, which locates it e.g. in a debugger.
+    pub(crate) fn span(&self) -> Option<Span> {
+        #[allow(clippy::enum_glob_use)]
+        use Expr::*;
+
+        match self {
+            Assign { name: tk, .. }
+            | Lambda { kw: tk, .. }
+            | Super { kw: tk, .. }
+            | This(tk)
+            | Unary { op: tk, .. }
+            | Variable(tk) => Some(tk.span),
+            Binary { lhs, op, .. } | Logical { lhs, op, .. } => lhs.span().or(Some(op.span)),
+            Call {
+                callee: obj, end, ..
+            }
+            | Index { obj, end, .. }
+            | IndexSet { obj, end, .. } => obj.span().or(Some(end.span)),
+            Get { obj, name, .. } | Set { obj, name, .. } => obj.span().or(Some(name.span)),
+            Grouping(inner) => inner.span(),
+            Interpolation(exprs) | List(exprs) => exprs.iter().find_map(Self::span),
+            Literal(_) => None,
+            Map { keys, end, .. } => keys.first().copied().or(Some(end.span)),
+            Ternary { cond, .. } => cond.span(),
+        }
+    }
+}
+
 impl Default for Expr {
     fn default() -> Self {
         Self::Literal(Lit::Nil)
@@ -111,6 +268,7 @@ impl Default for Expr {
 pub(crate) enum Lit {
     Nil,
     Bool(bool),
+    Int(i64),
     Number(f64),
     Str(String),
 }
@@ -119,8 +277,9 @@ impl Display for Lit {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Lit::Bool(b) => write!(f, "{}", b),
+            Lit::Int(i) => write!(f, "{i}"),
             Lit::Number(n) => write!(f, "{}", n),
-            Lit::Str(s) => write!(f, r#""{}""#, s),
+            Lit::Str(s) => write!(f, "{s:?}"),
             Lit::Nil => write!(f, "nil"),
         }
     }
@@ -133,21 +292,55 @@ impl Parser<'_> {
     }
 
     fn assignment_expr(&mut self) -> Result<Expr> {
-        let lhs = self.logic_or_expr()?;
-        if self.test(&[Equal]).is_some() {
+        let lhs = self.ternary_expr()?;
+        let assign_tys = [
+            Equal,
+            PlusEqual,
+            MinusEqual,
+            StarEqual,
+            SlashEqual,
+            PercentEqual,
+        ];
+        if let Some(eq) = self.test(&assign_tys) {
             // Assignment expression detected.
+            // For compound assignments, the operator is kept as a binary one
+            // spanning the whole `op=`.
+            let op = eq.ty.compound_op().map(|ty| Token {
+                ty,
+                lexeme: eq.lexeme.trim_end_matches('=').into(),
+                span: eq.span,
+            });
             let mut rhs = || self.assignment_expr();
             match lhs {
                 Expr::Variable(name) => {
-                    let val = Box::new(rhs()?);
+                    let mut val = Box::new(rhs()?);
+                    if let Some(op) = op {
+                        let lhs = Box::new(Expr::Variable(name.clone()));
+                        val = Box::new(Expr::Binary { lhs, op, rhs: val });
+                    }
                     return Ok(Expr::Assign { name, val });
                 }
-                Expr::Get { obj, name } => {
+                Expr::Get {
+                    obj,
+                    name,
+                    optional: false,
+                } => {
+                    let to = Box::new(rhs()?);
+                    return Ok(Expr::Set { obj, name, op, to });
+                }
+                Expr::Index { obj, idx, end } => {
                     let to = Box::new(rhs()?);
-                    return Ok(Expr::Set { obj, name, to });
+                    return Ok(Expr::IndexSet {
+                        obj,
+                        idx,
+                        end,
+                        op,
+                        to,
+                    });
                 }
                 _ => bail!(
-                    self.previous().unwrap().pos,
+                    Code::InvalidAssignTarget,
+                    self.previous().unwrap().span,
                     "while parsing a Assignment expression",
                     "can only assign to a variable",
                 ),
@@ -156,6 +349,37 @@ impl Parser<'_> {
         Ok(lhs)
     }
 
+    fn ternary_expr(&mut self) -> Result<Expr> {
+        let cond = self.coalesce_expr()?;
+        if self.test(&[Question]).is_none() {
+            return Ok(cond);
+        }
+        let then_expr = Box::new(self.expr()?);
+        self.consume(
+            &[Colon],
+            "while parsing a Ternary expression",
+            "expected `:` after the `then` branch",
+        )?;
+        let else_expr = Box::new(self.ternary_expr()?);
+        Ok(Expr::Ternary {
+            cond: Box::new(cond),
+            then_expr,
+            else_expr,
+        })
+    }
+
+    #[allow(clippy::similar_names)]
+    fn coalesce_expr(&mut self) -> Result<Expr> {
+        let mut res = self.logic_or_expr()?;
+        while let Some(op) = self.test(&[QuestionQuestion]) {
+            let op = op.clone();
+            let lhs = Box::new(res);
+            let rhs = Box::new(self.logic_or_expr()?);
+            res = Expr::Logical { lhs, op, rhs }
+        }
+        Ok(res)
+    }
+
     #[allow(clippy::similar_names)]
     fn logic_or_expr(&mut self) -> Result<Expr> {
         let mut res = self.logic_and_expr()?;
@@ -199,70 +423,118 @@ impl Parser<'_> {
         self.recursive_descent_binary(&[BangEqual, EqualEqual], Self::comparison_expr)
     }
 
-    fn comparison_expr(&mut self) -> Result<Expr> {
-        if let Some(op) = self.test(&[BangEqual, EqualEqual]) {
-            let (pos, lexeme) = (op.pos, op.lexeme.clone());
+    /// Bails if the next token is a binary operator in `tys` of the upper
+    /// precedence level, i.e. a binary operator with no LHS.
+    fn no_lhs(
+        &mut self,
+        tys: &[TokenType],
+        ctx: &str,
+        descend_parse: fn(&mut Self) -> Result<Expr>,
+    ) -> Result<()> {
+        if let Some(op) = self.test(tys) {
+            let (span, lexeme) = (op.span, op.lexeme.clone());
             // Consume the ill-formed RHS.
-            let _rhs = self.comparison_expr()?;
+            let _rhs = descend_parse(self)?;
             bail!(
-                pos,
-                "while parsing an Comparison expression",
+                Code::MissingOperand,
+                span,
+                ctx,
                 "found binary operator `{}` with no LHS",
                 lexeme,
             );
         }
-        self.recursive_descent_binary(&[Greater, GreaterEqual, Less, LessEqual], Self::term_expr)
+        Ok(())
+    }
+
+    fn comparison_expr(&mut self) -> Result<Expr> {
+        self.no_lhs(
+            &[BangEqual, EqualEqual],
+            "while parsing an Comparison expression",
+            Self::comparison_expr,
+        )?;
+        self.recursive_descent_binary(&[Greater, GreaterEqual, Less, LessEqual], Self::bit_or_expr)
+    }
+
+    fn bit_or_expr(&mut self) -> Result<Expr> {
+        self.no_lhs(
+            &[Greater, GreaterEqual, Less, LessEqual],
+            "while parsing a Bitwise Or expression",
+            Self::bit_or_expr,
+        )?;
+        self.recursive_descent_binary(&[Pipe], Self::bit_xor_expr)
+    }
+
+    fn bit_xor_expr(&mut self) -> Result<Expr> {
+        self.no_lhs(
+            &[Pipe],
+            "while parsing a Bitwise Xor expression",
+            Self::bit_xor_expr,
+        )?;
+        self.recursive_descent_binary(&[Caret], Self::bit_and_expr)
+    }
+
+    fn bit_and_expr(&mut self) -> Result<Expr> {
+        self.no_lhs(
+            &[Caret],
+            "while parsing a Bitwise And expression",
+            Self::bit_and_expr,
+        )?;
+        self.recursive_descent_binary(&[Amp], Self::shift_expr)
+    }
+
+    fn shift_expr(&mut self) -> Result<Expr> {
+        self.no_lhs(&[Amp], "while parsing a Shift expression", Self::shift_expr)?;
+        self.recursive_descent_binary(&[LessLess, GreaterGreater], Self::term_expr)
     }
 
     fn term_expr(&mut self) -> Result<Expr> {
-        if let Some(op) = self.test(&[Greater, GreaterEqual, Less, LessEqual]) {
-            let (pos, lexeme) = (op.pos, op.lexeme.clone());
-            // Consume the ill-formed RHS.
-            let _rhs = self.term_expr()?;
-            bail!(
-                pos,
-                "while parsing an Term expression",
-                "found binary operator `{}` with no LHS",
-                lexeme,
-            );
-        }
+        self.no_lhs(
+            &[LessLess, GreaterGreater],
+            "while parsing an Term expression",
+            Self::term_expr,
+        )?;
         self.recursive_descent_binary(&[Plus, Minus], Self::factor_expr)
     }
 
     fn factor_expr(&mut self) -> Result<Expr> {
         // `Minus` is special: no LHS is completely fine.
-        if let Some(op) = self.test(&[Plus]) {
-            let (pos, lexeme) = (op.pos, op.lexeme.clone());
-            // Consume the ill-formed RHS.
-            let _rhs = self.factor_expr()?;
-            bail!(
-                pos,
-                "while parsing an Factor expression",
-                "found binary operator `{}` with no LHS",
-                lexeme,
-            );
-        }
-        self.recursive_descent_binary(&[Slash, Star], Self::unary_expr)
+        self.no_lhs(
+            &[Plus],
+            "while parsing an Factor expression",
+            Self::factor_expr,
+        )?;
+        self.recursive_descent_binary(&[Slash, Star, Percent], Self::unary_expr)
     }
 
     fn unary_expr(&mut self) -> Result<Expr> {
-        if let Some(op) = self.test(&[Slash, Star]) {
-            let (pos, lexeme) = (op.pos, op.lexeme.clone());
-            // Consume the ill-formed RHS.
-            let _rhs = self.unary_expr()?;
-            bail!(
-                pos,
-                "while parsing an Unary expression",
-                "found binary operator `{}` with no LHS",
-                lexeme,
-            );
-        }
-        if let Some(op) = self.test(&[Bang, Minus]) {
+        self.no_lhs(
+            &[Slash, Star, Percent, StarStar],
+            "while parsing an Unary expression",
+            Self::unary_expr,
+        )?;
+        if let Some(op) = self.test(&[Bang, Minus, Tilde]) {
             let op = op.clone();
             let rhs = Box::new(self.unary_expr()?);
             return Ok(Expr::Unary { op, rhs });
         }
-        self.call_expr()
+        self.power_expr()
+    }
+
+    /// Parses a right-associative `**` expression, which binds tighter than
+    /// unary operators on its left, but not on its right, e.g. `-2 ** -1`
+    /// means `-(2 ** (-1))`.
+    fn power_expr(&mut self) -> Result<Expr> {
+        let lhs = self.call_expr()?;
+        if let Some(op) = self.test(&[StarStar]) {
+            let op = op.clone();
+            let rhs = Box::new(self.unary_expr()?);
+            return Ok(Expr::Binary {
+                lhs: Box::new(lhs),
+                op,
+                rhs,
+            });
+        }
+        Ok(lhs)
     }
 
     fn call_expr(&mut self) -> Result<Expr> {
@@ -275,15 +547,29 @@ impl Parser<'_> {
                     args,
                     end: self.previous().unwrap().clone(),
                 };
-            } else if self.test(&[Dot]).is_some() {
+            } else if let Some(dot) = self.test(&[Dot, QuestionDot]) {
+                let (optional, dot) = (dot.ty == QuestionDot, dot.lexeme.clone());
                 let name = self.consume(
                     &[Identifier],
                     "while parsing a Get expression",
-                    "expect property name after `.`",
+                    format!("expect property name after `{dot}`"),
                 )?;
                 res = Expr::Get {
                     obj: Box::new(res),
                     name,
+                    optional,
+                }
+            } else if self.test(&[LeftBracket]).is_some() {
+                let idx = Box::new(self.expr()?);
+                let end = self.consume(
+                    &[RightBracket],
+                    "while parsing an Index expression",
+                    "expected `]` to end the subscript",
+                )?;
+                res = Expr::Index {
+                    obj: Box::new(res),
+                    idx,
+                    end,
                 }
             } else {
                 break;
@@ -307,23 +593,55 @@ impl Parser<'_> {
             }
         }
         if self.test(&[RightParen]).is_none() {
+            let span = self.prev_span();
             self.sync();
             bail!(
-                self.previous().unwrap().pos,
+                Code::UnexpectedToken,
+                span,
                 ctx,
                 "expected `)` to end the parameter list"
             );
         }
         if args.len() > MAX_FUN_ARG_COUNT {
             bail!(
-                self.previous().unwrap().pos,
+                Code::TooManyParams,
+                self.prev_span(),
                 ctx,
-                format!("cannot have more than {} parameters", MAX_FUN_ARG_COUNT)
+                "cannot have more than {} parameters",
+                MAX_FUN_ARG_COUNT,
             )
         }
         Ok(args)
     }
 
+    /// Parses a `Str` token, whose interpolated expressions are parsed by new
+    /// parsers on their own.
+    fn str_lit(token: &Token) -> Result<Expr> {
+        let ctx = "while parsing a String interpolation";
+        let parts = str_parts(&token.lexeme, token.span)?;
+        if let [StrPart::Lit(s)] = &parts[..] {
+            return Ok(Expr::Literal(Lit::Str(s.clone())));
+        }
+        let parts = parts
+            .into_iter()
+            .map(|it| match it {
+                StrPart::Lit(s) => Ok(Expr::Literal(Lit::Str(s))),
+                StrPart::Interp(src, offset) if src.trim().is_empty() => bail!(
+                    Code::UnexpectedToken,
+                    // Covers the `${}` around `src`.
+                    Span::new(offset - 2, offset + src.len() + 1),
+                    ctx,
+                    "expected an expression to interpolate",
+                ),
+                StrPart::Interp(src, offset) => {
+                    Parser::new(Lexer::with_offset(src, offset)).parse_expr()
+                }
+            })
+            .try_collect()?;
+        Ok(Expr::Interpolation(parts))
+    }
+
+    #[allow(clippy::too_many_lines)]
     fn primary_expr(&mut self) -> Result<Expr> {
         macro_rules! bail_if_matches {
             ( $( $pat:pat = $ty:expr => $res:expr ),+ $(,)? ) => {{
@@ -337,24 +655,18 @@ impl Parser<'_> {
             _ = False => Expr::Literal(Lit::Bool(false)),
             _ = True => Expr::Literal(Lit::Bool(true)),
             _ = Nil => Expr::Literal(Lit::Nil),
-            s = Str => Expr::Literal(Lit::Str({
-                s.lexeme
-                    .strip_prefix('"')
-                    .and_then(|s| s.strip_suffix('"'))
-                    .unwrap()
-                    .into()
-            })),
+            s = Str => Self::str_lit(s)?,
             n = Number => {
                 let lexeme = &n.lexeme;
-                let val = lexeme.parse();
-                if let Err(e) = &val {
-                    bail!(n.pos, &format!("while parsing Number `{}`", lexeme), e);
+                match number_lit(lexeme) {
+                    Ok(lit) => Expr::Literal(lit),
+                    Err(e) => bail!(Code::InvalidNumber, n.span, format!("while parsing Number `{lexeme}`"), e),
                 }
-                Expr::Literal(Lit::Number(val.unwrap()))
             },
             t = This => Expr::This(t.clone()),
             i = Identifier => Expr::Variable(i.clone()),
-            _ = Fun => {
+            kw = Fun => {
+                let kw = kw.clone();
                 let ctx = "while parsing a Lambda expression";
                 self.consume(&[LeftParen], ctx, "expected `(` to begin the parameter list")?;
                 let params =
@@ -369,17 +681,49 @@ impl Parser<'_> {
                 } else {
                     unreachable!()
                 };
-                Expr::Lambda { params, body }
+                Expr::Lambda { kw, params, body }
             },
             lp = LeftParen => {
-                let pos = lp.pos;
+                let span = lp.span;
                 let inner = self.expr()?;
                 if self.test(&[RightParen]).is_none() {
                     self.sync();
-                    bail!(pos, "while parsing a parenthesized Group", "`)` expected");
+                    bail!(Code::UnexpectedToken, span, "while parsing a parenthesized Group", "`)` expected");
                 }
                 Expr::Grouping(Box::new(inner))
             },
+            _ = LeftBracket => {
+                let mut elems = vec![];
+                while self.check(RightBracket).is_none() {
+                    elems.push(self.expr()?);
+                    if self.test(&[Comma]).is_none() {
+                        break;
+                    }
+                }
+                self.consume(
+                    &[RightBracket],
+                    "while parsing a List literal",
+                    "expected `]` to end the List",
+                )?;
+                Expr::List(elems)
+            },
+            // At the beginning of a statement, `{` is always parsed as a Block instead.
+            _ = LeftBrace => {
+                let ctx = "while parsing a Map literal";
+                let (mut entries, mut keys) = (vec![], vec![]);
+                while self.check(RightBrace).is_none() {
+                    let first = self.peek().map_or_else(Span::default, |t| t.span);
+                    let key = self.expr()?;
+                    keys.push(first.to(self.prev_span()));
+                    self.consume(&[Colon], ctx, "expected `:` after Map key")?;
+                    entries.push((key, self.expr()?));
+                    if self.test(&[Comma]).is_none() {
+                        break;
+                    }
+                }
+                let end = self.consume(&[RightBrace], ctx, "expected `}` to end the Map")?;
+                Expr::Map { entries, keys, end }
+            },
             sup = Super => {
                 let kw = sup.clone();
                 let ctx = "while parsing a superclass method";
@@ -390,12 +734,22 @@ impl Parser<'_> {
         };
 
         if let Some(t) = self.peek() {
+            if t.ty == Error {
+                return Err(lex_error(t).into());
+            }
             bail!(
-                t.pos,
-                &format!("while parsing `{}`", &t.lexeme),
+                Code::UnexpectedToken,
+                t.span,
+                format!("while parsing `{}`", t.lexeme),
                 "unexpected token",
             );
         }
-        bail!((0, 0), "while parsing", "token index out of range");
+        let end = self.prev_span().end;
+        bail!(
+            Code::UnexpectedToken,
+            Span::new(end, end),
+            "while parsing",
+            "unexpected end of input",
+        );
     }
 }
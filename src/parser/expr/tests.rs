@@ -152,3 +152,157 @@ fn class_method() {
 fn class_super() {
     assert_expr("super.method()", "((. (super) method))");
 }
+
+#[test]
+fn list() {
+    assert_expr("[]", "(list)");
+    assert_expr("[1, [2, 3], a,]", "(list 1 (list 2 3) a)");
+}
+
+#[test]
+fn index() {
+    assert_expr("xs[1 + 2][0]", "(index (index xs (+ 1 2)) 0)");
+    assert_expr("f()[0].bar", "(. (index (f) 0) bar)");
+    assert_expr("xs[i] = ys[j] = 0", "(index-set! xs i (index-set! ys j 0))");
+}
+
+#[test]
+#[should_panic(expected = "expected `]` to end the subscript")]
+fn index_unclosed() {
+    assert_expr("xs[1", "");
+}
+
+#[test]
+fn map() {
+    assert_expr("{}", "(map)");
+    assert_expr(
+        r#"{"a": 1, b: [2], 3: {},}"#,
+        r#"(map ("a" 1) (b (list 2)) (3 (map)))"#,
+    );
+}
+
+#[test]
+#[should_panic(expected = "expected `:` after Map key")]
+fn map_no_colon() {
+    assert_expr(r#"{"a" 1}"#, "");
+}
+
+#[test]
+fn string_escapes() {
+    assert_expr(r#""a\tb\n""#, r#""a\tb\n""#);
+    assert_expr(r#"'it\'s' + "\"q\"""#, r#"(+ "it's" "\"q\"")"#);
+    assert_expr(r"'\\ \0 \r'", r#""\\ \0 \r""#);
+    assert_expr(r#""\u{48}\u{e9}\u{1F600}""#, r#""Hé😀""#);
+}
+
+#[test]
+#[should_panic(expected = "unknown escape sequence `\\q`")]
+fn string_unknown_escape() {
+    assert_expr(r#""a\qb""#, "");
+}
+
+#[test]
+#[should_panic(expected = "invalid Unicode escape `\\u{110000}`")]
+fn string_invalid_unicode_escape() {
+    assert_expr(r#""\u{110000}""#, "");
+}
+
+#[test]
+fn string_interpolation() {
+    assert_expr(
+        r#""Hello ${name}, you are ${age + 1}""#,
+        r#"(interp "Hello " name ", you are " (+ age 1))"#,
+    );
+    assert_expr(r#""${f("a")}""#, r#"(interp (f "a"))"#);
+    assert_expr(r#""\${a}""#, r#""${a}""#);
+}
+
+#[test]
+#[should_panic(expected = "expected an expression to interpolate")]
+fn string_interpolation_empty() {
+    assert_expr(r#""a ${ } b""#, "");
+}
+
+#[test]
+fn number_literals() {
+    assert_expr("0xFF + 0b1010 - 0o17", "(- (+ 255 10) 15)");
+    assert_expr("1_000_000 * 0x_dead_BEEF", "(* 1000000 3735928559)");
+    assert_expr("1e3 + 2.5E-1 + .5", "(+ (+ 1000 0.25) 0.5)");
+}
+
+#[test]
+#[should_panic(expected = "invalid digit found in string")]
+fn number_invalid_digit() {
+    assert_expr("0b12", "");
+}
+
+#[test]
+fn number_large_decimal() {
+    assert_expr(
+        "9_223_372_036_854_775_807 + 9_223_372_036_854_775_808",
+        "(+ 9223372036854775807 9223372036854776000)",
+    );
+    assert_expr("99999999999999999999", "100000000000000000000");
+}
+
+#[test]
+#[should_panic(expected = "number too large to fit in target type")]
+fn number_too_large() {
+    assert_expr("0x8000_0000_0000_0000", "");
+}
+
+#[test]
+fn arith_and_bitwise_ops() {
+    assert_expr(
+        "1 | 2 ^ 3 & 4 << 5 + 6 % 7",
+        "(| 1 (^ 2 (& 3 (<< 4 (+ 5 (% 6 7))))))",
+    );
+    assert_expr("a == b | c < d", "(== a (< (| b c) d))");
+    assert_expr("~a >> 1", "(>> (~ a) 1)");
+    assert_expr("2 ** 3 ** 2 * 4", "(* (** 2 (** 3 2)) 4)");
+    assert_expr("-2 ** -1", "(- (** 2 (- 1)))");
+}
+
+#[test]
+#[should_panic(expected = "found binary operator `&` with no LHS")]
+fn bitwise_no_lhs() {
+    assert_expr("a ^ & b", "");
+}
+
+#[test]
+fn compound_assignment() {
+    assert_expr("a += b -= 1", "(assign! a (+ a (assign! b (- b 1))))");
+    assert_expr("a.b *= 2", "(.set! a b (* (. a b) 2))");
+    assert_expr("a[i] %= 2", "(index-set! a i (% (index a i) 2))");
+}
+
+#[test]
+#[should_panic(expected = "can only assign to a variable")]
+fn compound_assignment_bad_target() {
+    assert_expr("a + b /= 2", "");
+}
+
+#[test]
+fn ternary_and_coalesce() {
+    assert_expr("a ? b : c ? d : e", "(?: a b (?: c d e))");
+    assert_expr("a or b ? c = 1 : d", "(?: (or a b) (assign! c 1) d)");
+    assert_expr("a ?? b or c ?? d", "(?? (?? a (or b c)) d)");
+    assert_expr("x = a ?? b ? c : d", "(assign! x (?: (?? a b) c d))");
+}
+
+#[test]
+#[should_panic(expected = "expected `:` after the `then` branch")]
+fn ternary_no_colon() {
+    assert_expr("a ? b", "");
+}
+
+#[test]
+fn optional_chaining() {
+    assert_expr("a?.b.c(1)?.d[0]", "(index (?. ((. (?. a b) c) 1) d) 0)");
+}
+
+#[test]
+#[should_panic(expected = "can only assign to a variable")]
+fn optional_chaining_assign() {
+    assert_expr("a?.b = 1", "");
+}
@@ -3,13 +3,11 @@ mod tests;
 use std::fmt::Display;
 
 use anyhow::{Context, Result};
-use itertools::Itertools;
-use tap::TapFallible;
 
 use super::{Expr, Lit, Parser};
 #[allow(clippy::enum_glob_use)]
-use crate::lexer::{Token, TokenType::*};
-use crate::{bail, error::report, util::disp_slice};
+use crate::lexer::{str_parts, StrPart, Token, TokenType::*};
+use crate::{bail, error::Code, report, source::Span, util::disp_slice};
 
 #[derive(Debug, Clone)]
 pub(crate) enum Stmt {
@@ -30,26 +28,85 @@ pub(crate) enum Stmt {
         body: Vec<Stmt>,
     },
     If {
+        kw: Token,
         cond: Expr,
         then_stmt: Box<Stmt>,
         else_stmt: Option<Box<Stmt>>,
     },
-    Jump(Token),
-    Print(Expr),
+    /// An `import "path" as alias;` or a `from "path" import names;`
+    /// declaration.
+    Import {
+        /// The `Str` token of the module path, whose lexeme has been decoded.
+        /// Its position is memorized for error reports.
+        path: Token,
+        alias: Option<Token>,
+        names: Vec<Token>,
+    },
+    /// A `break` or `continue` statement, which targets the loop of `label`
+    /// if any, or the innermost loop otherwise.
+    Jump {
+        kw: Token,
+        label: Option<Token>,
+    },
+    Print {
+        kw: Token,
+        val: Expr,
+    },
     Return {
         kw: Token,
         val: Option<Expr>,
     },
+    Throw {
+        kw: Token,
+        val: Expr,
+    },
+    /// A `try` statement, which has at least one of the `catch` and `finally`
+    /// clauses.
+    Try {
+        kw: Token,
+        body: Vec<Stmt>,
+        /// The exception variable and the body of the `catch` clause.
+        catch: Option<(Token, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    },
     Var {
         name: Token,
         init: Option<Expr>,
     },
     While {
+        /// The `while` keyword, or the `for` keyword of a desugared `for` loop.
+        kw: Token,
+        label: Option<Token>,
         cond: Expr,
         body: Box<Stmt>,
+        /// The increment clause of a desugared `for` loop, which is evaluated
+        /// after each iteration, even one ended by `continue`.
+        incr: Option<Expr>,
     },
 }
 
+impl Stmt {
+    /// Returns the span of a token locating this statement, e.g. its keyword
+    /// or the name it declares, if any.
+    pub(crate) fn span(&self) -> Option<Span> {
+        match self {
+            Stmt::Block(stmts) => stmts.iter().find_map(Self::span),
+            Stmt::Expression(expr) => expr.span(),
+            Stmt::Class { name, .. } | Stmt::Fun { name, .. } | Stmt::Var { name, .. } => {
+                Some(name.span)
+            }
+            Stmt::Import { path: kw, .. }
+            | Stmt::If { kw, .. }
+            | Stmt::Jump { kw, .. }
+            | Stmt::Print { kw, .. }
+            | Stmt::Return { kw, .. }
+            | Stmt::Throw { kw, .. }
+            | Stmt::Try { kw, .. }
+            | Stmt::While { kw, .. } => Some(kw.span),
+        }
+    }
+}
+
 impl Display for Stmt {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -74,27 +131,78 @@ impl Display for Stmt {
                 cond,
                 then_stmt,
                 else_stmt,
+                ..
             } => {
                 let else_stmt = else_stmt
                     .as_ref()
                     .map_or_else(String::new, |i| format!(" {}", i));
                 write!(f, "(if {} {}{})", cond, then_stmt, else_stmt)
             }
-            Stmt::Jump(t) => write!(f, "({})", t.lexeme),
-            Stmt::Print(expr) => write!(f, "(print {})", expr),
+            Stmt::Import {
+                path, alias, names, ..
+            } => match alias {
+                Some(alias) => write!(f, "(import {:?} {alias})", path.lexeme),
+                None => write!(
+                    f,
+                    "(import {:?} ({}))",
+                    path.lexeme,
+                    disp_slice(names, false)
+                ),
+            },
+            Stmt::Jump { kw, label } => match label {
+                Some(label) => write!(f, "({} {})", kw.lexeme, label),
+                None => write!(f, "({})", kw.lexeme),
+            },
+            Stmt::Print { val, .. } => write!(f, "(print {val})"),
             Stmt::Return { kw, val } => {
                 let val = val
                     .as_ref()
                     .map_or_else(String::new, |sup| format!(" {}", sup));
                 write!(f, "({}{})", kw, val)
             }
+            Stmt::Throw { val, .. } => write!(f, "(throw {val})"),
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                let catch = catch.as_ref().map_or_else(String::new, |(name, body)| {
+                    format!(" (catch {} (begin {}))", name, disp_slice(body, true))
+                });
+                let finally = finally.as_ref().map_or_else(String::new, |body| {
+                    format!(" (finally (begin {}))", disp_slice(body, true))
+                });
+                write!(
+                    f,
+                    "(try (begin {}){}{})",
+                    disp_slice(body, true),
+                    catch,
+                    finally
+                )
+            }
             Stmt::Var { name, init } => {
                 let init = init
                     .as_ref()
                     .map_or_else(String::new, |i| format!(" {}", i));
                 write!(f, "(var {}{})", name, init)
             }
-            Stmt::While { cond, body } => write!(f, "(while {} {})", cond, body),
+            Stmt::While {
+                label,
+                cond,
+                body,
+                incr,
+                ..
+            } => {
+                let incr = incr
+                    .as_ref()
+                    .map_or_else(String::new, |i| format!(" {}", i));
+                let while_loop = format!("(while {cond} {body}{incr})");
+                match label {
+                    Some(label) => write!(f, "({label}: {while_loop})"),
+                    None => write!(f, "{while_loop}"),
+                }
+            }
         }
     }
 }
@@ -102,14 +210,15 @@ impl Display for Stmt {
 // ** Recursive Descent for Stmt and Decl **
 impl Parser<'_> {
     pub(crate) fn decl(&mut self) -> Result<Stmt> {
-        match self.test(&[Class, Fun, Var]) {
+        match self.test(&[Class, Fun, Var, Import, From]) {
             Some(t) if t.ty == Class => self.class_decl(),
             Some(t) if t.ty == Fun => self.fun_decl(),
             Some(t) if t.ty == Var => self.var_decl(),
+            Some(t) if t.ty == Import => self.import_decl(),
+            Some(t) if t.ty == From => self.import_from_decl(),
             None => self.stmt(),
             _ => unreachable!(),
         }
-        .tap_err(|_| self.sync())
     }
 
     fn class_decl(&mut self) -> Result<Stmt> {
@@ -125,12 +234,14 @@ impl Parser<'_> {
         self.consume(&[LeftBrace], ctx, "expected `{` after class name")?;
         let methods = self.many_till(Self::fun_decl, RightBrace)?;
         if self.test(&[RightBrace]).is_none() {
+            let span = self.prev_span();
             self.sync();
             let ctx = "while parsing Class method list";
             bail!(
-                self.previous().unwrap().pos,
+                Code::UnexpectedToken,
+                span,
                 ctx,
-                "expected `}` to end the class body",
+                "expected `}}` to end the class body",
             )
         }
         Ok(Stmt::Class {
@@ -171,28 +282,78 @@ impl Parser<'_> {
         Ok(Stmt::Var { name, init })
     }
 
+    fn import_decl(&mut self) -> Result<Stmt> {
+        let ctx = "while parsing an Import declaration";
+        let path = self.module_path(ctx)?;
+        self.consume(&[As], ctx, "expected `as` after the module path")?;
+        let alias = self.consume(&[Identifier], ctx, "expected module name after `as`")?;
+        self.consume(&[Semicolon], ctx, "expected `;` after the module name")?;
+        Ok(Stmt::Import {
+            path,
+            alias: Some(alias),
+            names: vec![],
+        })
+    }
+
+    fn import_from_decl(&mut self) -> Result<Stmt> {
+        let ctx = "while parsing an Import declaration";
+        let path = self.module_path(ctx)?;
+        self.consume(&[Import], ctx, "expected `import` after the module path")?;
+        let mut names = vec![];
+        loop {
+            names.push(self.consume(&[Identifier], ctx, "expected a name to import")?);
+            if self.test(&[Comma]).is_none() {
+                break;
+            }
+        }
+        self.consume(&[Semicolon], ctx, "expected `;` after the imported names")?;
+        Ok(Stmt::Import {
+            path,
+            alias: None,
+            names,
+        })
+    }
+
+    /// Parses the path of a module, which should be a `Str` literal without
+    /// interpolations.
+    fn module_path(&mut self, ctx: &str) -> Result<Token> {
+        let mut path = self.consume(&[Str], ctx, "expected a module path String")?;
+        match &str_parts(&path.lexeme, path.span)?[..] {
+            [StrPart::Lit(s)] => path.lexeme = s.clone(),
+            _ => bail!(
+                Code::UnexpectedToken,
+                path.span,
+                ctx,
+                "a module path cannot be interpolated",
+            ),
+        }
+        Ok(path)
+    }
+
     pub(crate) fn stmt(&mut self) -> Result<Stmt> {
-        match self.test(&[Break, Continue, Return, If, While, For, Print, LeftBrace]) {
+        match self.test(&[
+            Break, Continue, Return, Throw, Try, If, While, For, Print, LeftBrace,
+        ]) {
             Some(t) if [Break, Continue].contains(&t.ty) => self.jump_stmt(),
             Some(t) if t.ty == Return => self.return_stmt(),
+            Some(t) if t.ty == Throw => self.throw_stmt(),
+            Some(t) if t.ty == Try => self.try_stmt(),
             Some(t) if t.ty == If => self.if_stmt(),
-            Some(t) if t.ty == While => self.while_stmt(),
-            Some(t) if t.ty == For => self.for_stmt(),
+            Some(t) if t.ty == While => self.while_stmt(None),
+            Some(t) if t.ty == For => self.for_stmt(None),
             Some(t) if t.ty == Print => self.print_stmt(),
             Some(t) if t.ty == LeftBrace => self.block_stmt(),
-            None => self.expression_stmt(),
+            None => self.expression_or_labelled_stmt(),
             _ => unreachable!(),
         }
     }
 
     fn jump_stmt(&mut self) -> Result<Stmt> {
+        let ctx = "while parsing an Jump statement";
         let kw = self.previous().unwrap().clone();
-        self.consume(
-            &[Semicolon],
-            "while parsing an Jump statement",
-            "expected `;` at the end",
-        )?;
-        Ok(Stmt::Jump(kw))
+        let label = self.test(&[Identifier]).cloned();
+        self.consume(&[Semicolon], ctx, "expected `;` at the end")?;
+        Ok(Stmt::Jump { kw, label })
     }
 
     fn return_stmt(&mut self) -> Result<Stmt> {
@@ -211,12 +372,74 @@ impl Parser<'_> {
         Ok(Stmt::Return { kw, val })
     }
 
+    fn throw_stmt(&mut self) -> Result<Stmt> {
+        let ctx = "while parsing a Throw statement";
+        let kw = self.previous().unwrap().clone();
+        let val = self.expr().with_context(|| {
+            report!(
+                Code::UnexpectedToken,
+                self.prev_span(),
+                ctx,
+                "nothing to throw",
+            )
+        })?;
+        self.consume(&[Semicolon], ctx, "expected `;` after a value")?;
+        Ok(Stmt::Throw { kw, val })
+    }
+
+    fn try_stmt(&mut self) -> Result<Stmt> {
+        let ctx = "while parsing a Try statement";
+        let kw = self.previous().unwrap().clone();
+        let body = self.clause_body(ctx, "expected `{` after `try`")?;
+        let catch = if self.test(&[Catch]).is_some() {
+            let name = self.parens(
+                |this| this.consume(&[Identifier], ctx, "expected exception variable name"),
+                "the exception variable",
+            )?;
+            let body = self.clause_body(ctx, "expected `{` after the exception variable")?;
+            Some((name, body))
+        } else {
+            None
+        };
+        let finally = if self.test(&[Finally]).is_some() {
+            Some(self.clause_body(ctx, "expected `{` after `finally`")?)
+        } else {
+            None
+        };
+        if catch.is_none() && finally.is_none() {
+            bail!(
+                Code::UnexpectedToken,
+                self.prev_span(),
+                ctx,
+                "expected `catch` or `finally` after the Try block",
+            );
+        }
+        Ok(Stmt::Try {
+            kw,
+            body,
+            catch,
+            finally,
+        })
+    }
+
+    /// Parses the block of a clause in a Try statement.
+    fn clause_body(&mut self, ctx: &str, msg: &str) -> Result<Vec<Stmt>> {
+        self.consume(&[LeftBrace], ctx, msg)?;
+        if let Stmt::Block(stmts) = self.block_stmt()? {
+            Ok(stmts)
+        } else {
+            unreachable!()
+        }
+    }
+
     fn if_stmt(&mut self) -> Result<Stmt> {
         let ctx = "while parsing an If statement";
+        let kw = self.previous().unwrap().clone();
         let cond = self.parens(Self::expr, "the Predicate")?;
         let then_stmt = Box::new(self.stmt().with_context(|| {
-            report(
-                self.previous().unwrap().pos,
+            report!(
+                Code::UnexpectedToken,
+                self.prev_span(),
                 ctx,
                 "nothing in the Then branch",
             )
@@ -227,27 +450,37 @@ impl Parser<'_> {
             None
         };
         Ok(Stmt::If {
+            kw,
             cond,
             then_stmt,
             else_stmt,
         })
     }
 
-    fn while_stmt(&mut self) -> Result<Stmt> {
+    fn while_stmt(&mut self, label: Option<Token>) -> Result<Stmt> {
         let ctx = "while parsing a While statement";
+        let kw = self.previous().unwrap().clone();
         let cond = self.parens(Self::expr, "the Predicate")?;
         let body = Box::new(self.stmt().with_context(|| {
-            report(
-                self.previous().unwrap().pos,
+            report!(
+                Code::UnexpectedToken,
+                self.prev_span(),
                 ctx,
                 "nothing in the loop body",
             )
         })?);
-        Ok(Stmt::While { cond, body })
+        Ok(Stmt::While {
+            kw,
+            label,
+            cond,
+            body,
+            incr: None,
+        })
     }
 
-    fn for_stmt(&mut self) -> Result<Stmt> {
+    fn for_stmt(&mut self, label: Option<Token>) -> Result<Stmt> {
         let ctx = "while parsing a For statement";
+        let kw = self.previous().unwrap().clone();
         let (init, cond, incr) = self.parens(
             |this| {
                 let init = match this.test(&[Semicolon, Var]) {
@@ -273,23 +506,27 @@ impl Parser<'_> {
             "the Predicate Clauses",
         )?;
         let body = Box::new(self.stmt().with_context(|| {
-            report(
-                self.previous().unwrap().pos,
+            report!(
+                Code::UnexpectedToken,
+                self.prev_span(),
                 ctx,
                 "nothing in the loop body",
             )
         })?);
 
         // Desugaring begins...
-        // for (init; cond; incr) { body; } => { init; while (cond) { body; incr; } }
-        let body = Box::new(Stmt::Block(if let Some(incr) = incr {
-            vec![*body, Stmt::Expression(incr)]
-        } else {
-            vec![*body]
-        }));
-
+        // for (init; cond; incr) body
+        //     => { init; while (cond) body (incr after each iteration) }
+        // ... where `incr` is kept as a field of the While statement, so that it
+        // also runs after an iteration ended by `continue`.
         let cond = cond.unwrap_or(Expr::Literal(Lit::Bool(true)));
-        let while_loop = Stmt::While { cond, body };
+        let while_loop = Stmt::While {
+            kw,
+            label,
+            cond,
+            body,
+            incr,
+        };
 
         Ok(Stmt::Block(if let Some(init) = init {
             vec![init, while_loop]
@@ -299,9 +536,11 @@ impl Parser<'_> {
     }
 
     fn print_stmt(&mut self) -> Result<Stmt> {
-        let rhs = self.expr().with_context(|| {
-            report(
-                self.previous().unwrap().pos,
+        let kw = self.previous().unwrap().clone();
+        let val = self.expr().with_context(|| {
+            report!(
+                Code::UnexpectedToken,
+                self.prev_span(),
                 "while parsing a Print statement",
                 "nothing to print",
             )
@@ -311,11 +550,38 @@ impl Parser<'_> {
             "while parsing an Print statement",
             "expected `;` after a value",
         )?;
-        Ok(Stmt::Print(rhs))
+        Ok(Stmt::Print { kw, val })
+    }
+
+    /// Parses an expression statement, or a loop with a label like `outer:`,
+    /// which begins with what looks like a Variable expression.
+    fn expression_or_labelled_stmt(&mut self) -> Result<Stmt> {
+        let ctx = "while parsing a labelled statement";
+        let expr = self.expr()?;
+        match expr {
+            Expr::Variable(label) if self.test(&[Colon]).is_some() => {
+                match self.test(&[While, For]) {
+                    Some(t) if t.ty == While => self.while_stmt(Some(label)),
+                    Some(t) if t.ty == For => self.for_stmt(Some(label)),
+                    _ => bail!(
+                        Code::UnexpectedToken,
+                        self.prev_span(),
+                        ctx,
+                        "expected a loop after the label `{}`",
+                        label.lexeme,
+                    ),
+                }
+            }
+            expr => self.expression_stmt_rest(expr),
+        }
     }
 
     fn expression_stmt(&mut self) -> Result<Stmt> {
         let expr = self.expr()?;
+        self.expression_stmt_rest(expr)
+    }
+
+    fn expression_stmt_rest(&mut self, expr: Expr) -> Result<Stmt> {
         self.consume(
             &[Semicolon],
             "while parsing an Expression statement",
@@ -326,13 +592,7 @@ impl Parser<'_> {
 
     pub(crate) fn block_stmt(&mut self) -> Result<Stmt> {
         // When parsing statements here, we need an 1-token lookahead.
-        let stmts = std::iter::from_fn(|| {
-            self.peek()
-                .filter(|t| t.ty != RightBrace)
-                .is_some()
-                .then(|| self.decl())
-        })
-        .try_collect()?;
+        let stmts = self.decls(Some(RightBrace));
         self.consume(
             &[RightBrace],
             "while parsing a Block statement",
@@ -2,10 +2,11 @@
 #![allow(clippy::enum_glob_use)]
 
 use indoc::indoc;
+use itertools::Itertools;
 use pretty_assertions::assert_eq;
 
 use super::*;
-use crate::lexer::Lexer;
+use crate::{error::Errors, lexer::Lexer};
 
 fn assert_stmts(src: &str, expected: &[&str]) {
     let tokens = Lexer::new(src);
@@ -75,14 +76,35 @@ fn while_stmt() {
 fn for_stmt() {
     assert_stmts(
         "for (i = product = 1; i <= 5; i = i + 1) { product = product * i; }",
-        &["(begin (assign! i (assign! product 1)) (while (<= i 5) (begin (begin (assign! product (* product i))) (assign! i (+ i 1)))))"],
+        &["(begin (assign! i (assign! product 1)) (while (<= i 5) (begin (assign! product (* product i))) (assign! i (+ i 1))))"],
     );
     assert_stmts(
         "for (;;) { product = product * i; }",
-        &["(begin (while true (begin (begin (assign! product (* product i))))))"],
+        &["(begin (while true (begin (assign! product (* product i)))))"],
     );
 }
 
+#[test]
+fn labelled_loops() {
+    assert_stmts(
+        indoc! {"
+            outer: for (var i = 0; i < 3; i = i + 1) {
+                inner: while (true) {
+                    if (i == 1) continue outer;
+                    break inner;
+                }
+            }
+        "},
+        &["(begin (var i 0) (outer: (while (< i 3) (begin (inner: (while true (begin (if (== i 1) (continue outer)) (break inner))))) (assign! i (+ i 1)))))"],
+    );
+}
+
+#[test]
+#[should_panic(expected = "expected a loop after the label `outer`")]
+fn labelled_non_loop() {
+    assert_stmts("outer: print 1;", &[]);
+}
+
 #[test]
 fn jump_stmt() {
     assert_stmts(
@@ -110,6 +132,50 @@ fn for_stmt_typo() {
     );
 }
 
+#[test]
+fn import_decl() {
+    assert_stmts(
+        r#"import "lib/m.lox" as m; from 'm.lox' import a, b;"#,
+        &[r#"(import "lib/m.lox" m)"#, r#"(import "m.lox" (a b))"#],
+    );
+}
+
+#[test]
+#[should_panic(expected = "expected `as` after the module path")]
+fn import_decl_no_alias() {
+    assert_stmts(r#"import "m.lox";"#, &[]);
+}
+
+#[test]
+#[should_panic(expected = "a module path cannot be interpolated")]
+fn import_decl_interpolated() {
+    assert_stmts(r#"from "${m}.lox" import a;"#, &[]);
+}
+
+#[test]
+fn try_stmt() {
+    assert_stmts(
+        "try { throw 1; } catch (e) { print e; } finally { print 2; }",
+        &["(try (begin (throw 1)) (catch e (begin (print e))) (finally (begin (print 2))))"],
+    );
+    assert_stmts(
+        "try { f(); } finally {}",
+        &["(try (begin (f)) (finally (begin '())))"],
+    );
+}
+
+#[test]
+#[should_panic(expected = "expected `catch` or `finally` after the Try block")]
+fn try_stmt_no_clause() {
+    assert_stmts("try { f(); }", &[]);
+}
+
+#[test]
+#[should_panic(expected = "expected `(` before the exception variable")]
+fn try_stmt_no_parens() {
+    assert_stmts("try { f(); } catch e {}", &[]);
+}
+
 #[test]
 fn foo() {
     assert_stmts("foo;", &["foo"]);
@@ -211,3 +277,106 @@ fn class_decl_super() {
 fn class_decl_no_super() {
     assert_stmts("class Foo < {}", &[""]);
 }
+
+#[test]
+fn brace_at_stmt_start_is_block() {
+    assert_stmts(
+        r#"{} var m = {"a": {}}; ({});"#,
+        &["(begin '())", r#"(var m (map ("a" (map))))"#, "(map)"],
+    );
+}
+
+fn assert_recovered(src: &str, expected: &[&str], expected_errs: &[&str]) {
+    let (stmts, errs) = Parser::new(Lexer::new(src)).parse_all();
+    let got = stmts.iter().map(|i| format!("{}", i)).collect_vec();
+    assert_eq!(expected, got);
+    let got_errs = errs.iter().map(|e| format!("{e:#}")).collect_vec();
+    assert_eq!(expected_errs.len(), got_errs.len(), "{got_errs:#?}");
+    for (expected, got) in expected_errs.iter().zip(&got_errs) {
+        assert!(
+            got.contains(expected),
+            "`{got}` should contain `{expected}`"
+        );
+    }
+}
+
+#[test]
+fn recover_from_errors() {
+    assert_recovered(
+        indoc! {r#"
+            var a = 1
+            print a;
+            fun f(x { print x; }
+            print (1 + 2;
+            print "ok";
+        "#},
+        &["(print a)", "(begin (print x))", r#"(print "ok")"#],
+        &[
+            "expected `;` after a value",
+            "expected `)` to end the parameter list",
+            "`)` expected",
+        ],
+    );
+}
+
+#[test]
+fn recover_from_errors_in_blocks() {
+    assert_recovered(
+        indoc! {r#"
+            {
+                var b = ;
+                print b;
+            }
+            while (true) { break }
+            }
+            print "ok";
+        "#},
+        &[
+            "(begin (print b))",
+            "(while true (begin '()))",
+            r#"(print "ok")"#,
+        ],
+        &[
+            "unexpected token",
+            "expected `;` at the end",
+            "unexpected token",
+        ],
+    );
+}
+
+#[test]
+fn parse_reports_all_errors() {
+    let err = Parser::new(Lexer::new("print 1 print 2; var;"))
+        .parse()
+        .unwrap_err();
+    let errs = &err.downcast_ref::<Errors>().unwrap().0;
+    assert_eq!(2, errs.len());
+}
+
+#[test]
+fn comments() {
+    assert_stmts(
+        indoc! {"
+            // A comment at the beginning.
+            var /* inline */ a = 1; // Trailing.
+            /* Nested /* block */ comment
+               spanning lines. */
+            print a /* before `;` */;
+            // A comment at the end."},
+        &["(var a 1)", "(print a)"],
+    );
+}
+
+#[test]
+fn unterminated_comments() {
+    assert_recovered(
+        "print 1; /* /* */\nprint 2;",
+        &["(print 1)"],
+        &["unterminated block comment"],
+    );
+    assert_recovered(
+        "print 'a;\nprint 2;",
+        &["(print 2)"],
+        &["unterminated String literal"],
+    );
+}
@@ -0,0 +1,333 @@
+//! The interactive prompt, i.e. the REPL.
+
+mod tests;
+
+use std::{
+    borrow::Cow,
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use anyhow::Result;
+use gc::Gc;
+use itertools::Itertools;
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    validate::Validator, Context, Editor, Helper,
+};
+
+use crate::{
+    error::{render_error, Diagnostic},
+    interpreter::Interpreter,
+    lexer::{Lexer, TokenType},
+    parser::Parser,
+    run::{run_str, Backend, BackendKind},
+    source::SourceMap,
+    vm::Vm,
+};
+
+const HELP: &str = "\
+:help         show this help
+:load FILE    run the Lox file FILE in this session
+:reset        forget everything defined in this session
+:env          show the global variables
+:ast EXPR     show the syntax tree of the expression EXPR
+:time INPUT   run INPUT and show the time it takes
+Lines with unclosed brackets are continued on the next one, which can be
+cancelled with Ctrl-C. Press Ctrl-D to exit.
+";
+
+/// The meta commands of the REPL, completed as keywords.
+const COMMANDS: &[&str] = &[":help", ":load", ":reset", ":env", ":ast", ":time"];
+
+const KEYWORDS: &[&str] = &[
+    "and", "as", "break", "catch", "class", "continue", "else", "false", "finally", "fun", "for",
+    "from", "if", "import", "nil", "or", "print", "return", "super", "this", "throw", "true",
+    "try", "var", "while",
+];
+
+pub(crate) fn run_prompt(backend: BackendKind) -> Result<()> {
+    fn inner(repl: &mut Repl<impl Backend>) -> Result<()> {
+        let mut editor = Editor::<LoxHelper>::new();
+        editor.set_helper(Some(LoxHelper::new(repl.names())));
+        let history = history_path();
+        if let Some(path) = &history {
+            // The history file does not exist in the first session.
+            let _ = editor.load_history(path);
+        }
+        let res = loop {
+            match editor.readline(repl.prompt()) {
+                // Ctrl-C cancels the incomplete input if any, and exits otherwise.
+                Err(ReadlineError::Interrupted) => {
+                    if !repl.cancel() {
+                        break Ok(());
+                    }
+                }
+                Err(ReadlineError::Eof) => break Ok(()),
+                Err(e) => break Err(e.into()),
+                Ok(ln) => {
+                    if !ln.trim().is_empty() {
+                        editor.add_history_entry(&ln);
+                    }
+                    repl.feed(&ln, &mut io::stdout())?;
+                    if let Some(helper) = editor.helper_mut() {
+                        helper.names = repl.names();
+                    }
+                }
+            }
+        };
+        if let Some(path) = &history {
+            editor.save_history(path)?;
+        }
+        res
+    }
+
+    match backend {
+        BackendKind::Tree => inner(&mut Repl::<Interpreter>::default()),
+        BackendKind::Bytecode => inner(&mut Repl::<Vm>::default()),
+    }
+}
+
+/// Returns the path of the history file, i.e. `.dolores_history` in the home
+/// directory, if any.
+fn history_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".dolores_history"))
+}
+
+/// A REPL session, running the lines fed with a backend.
+#[derive(Debug, Default)]
+pub(crate) struct Repl<B> {
+    backend: B,
+    /// The lines of the input so far, if it is incomplete.
+    pending: String,
+}
+
+impl<B: Backend> Repl<B> {
+    /// Returns the prompt of the next line.
+    pub(crate) fn prompt(&self) -> &'static str {
+        if self.pending.is_empty() {
+            ">>> "
+        } else {
+            "... "
+        }
+    }
+
+    /// Cancels the incomplete input, returning if there was any.
+    pub(crate) fn cancel(&mut self) -> bool {
+        !std::mem::take(&mut self.pending).is_empty()
+    }
+
+    /// Returns the global names defined so far, including the builtins.
+    pub(crate) fn names(&mut self) -> Vec<String> {
+        let globals = self.backend.globals();
+        std::iter::successors(Some(Gc::clone(globals)), |env| env.borrow().outer.clone())
+            .flat_map(|env| env.borrow().dict.keys().cloned().collect_vec())
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// Feeds a line of input, which is run together with the previous ones
+    /// once no bracket is left unclosed, writing the results to `out`.
+    pub(crate) fn feed(&mut self, line: &str, out: &mut impl Write) -> Result<()> {
+        if self.pending.is_empty() {
+            if let Some(cmd) = line.trim().strip_prefix(':') {
+                let (cmd, arg) = cmd.split_once(char::is_whitespace).unwrap_or((cmd, ""));
+                return self.command(cmd, arg.trim(), out);
+            }
+        }
+        self.pending.push_str(line);
+        self.pending.push('\n');
+        if is_incomplete(&self.pending) {
+            return Ok(());
+        }
+        let src = std::mem::take(&mut self.pending);
+        self.run("<stdin>", &src, true, out)
+    }
+
+    /// Runs the meta command `cmd` with the argument `arg`.
+    fn command(&mut self, cmd: &str, arg: &str, out: &mut impl Write) -> Result<()> {
+        match (cmd, arg) {
+            ("help", _) => write!(out, "{HELP}")?,
+            ("load", "") => writeln!(out, "usage: :load FILE")?,
+            ("load", path) => match fs::read_to_string(path) {
+                Ok(src) => self.run(path, &src, false, out)?,
+                Err(e) => writeln!(out, "failed to read `{path}`: {e}")?,
+            },
+            ("reset", _) => {
+                self.backend = B::default();
+                writeln!(out, "the session has been reset")?;
+            }
+            ("env", _) => {
+                let globals = self.backend.globals().borrow();
+                if globals.dict.is_empty() {
+                    writeln!(out, "no global variables")?;
+                }
+                for (name, val) in globals.dict.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+                    writeln!(out, "{name} = {val}")?;
+                }
+            }
+            ("ast", "") => writeln!(out, "usage: :ast EXPR")?,
+            ("ast", src) => {
+                // The expression is parsed on its own, so that it is not added to
+                // the session.
+                let mut sources = SourceMap::default();
+                let offset = sources.add("<stdin>", src);
+                match Parser::new(Lexer::with_offset(src, offset)).parse_expr() {
+                    Ok(expr) => writeln!(out, "{expr}")?,
+                    Err(e) => write!(out, "{}", render_error(&e, &sources))?,
+                }
+            }
+            ("time", "") => writeln!(out, "usage: :time INPUT")?,
+            ("time", src) => {
+                let start = Instant::now();
+                self.run("<stdin>", src, true, out)?;
+                writeln!(out, "took {:?}", start.elapsed())?;
+            }
+            _ => writeln!(out, "unknown command `:{cmd}`, see `:help`")?,
+        }
+        Ok(())
+    }
+
+    /// Runs the source `src` named `name`, writing the warnings found before
+    /// running it, and then its value or error, to `out`.
+    fn run(&mut self, name: &str, src: &str, repl_mode: bool, out: &mut impl Write) -> Result<()> {
+        let warn = |it: &Diagnostic, sources: &SourceMap| {
+            let _ = write!(out, "{}", it.render(sources));
+            let _ = out.flush();
+        };
+        match run_str(name, src, &mut self.backend, repl_mode, warn) {
+            Ok(res) if !res.is_empty() => writeln!(out, "<<< {res}")?,
+            Ok(_) => (),
+            Err(e) => write!(out, "{}", render_error(&e, self.backend.sources()))?,
+        }
+        Ok(())
+    }
+}
+
+/// Returns if `src` ends with unclosed brackets or block comments, so that it
+/// should be continued on the next line.
+///
+/// Brackets in String literals and comments are not counted.
+pub(crate) fn is_incomplete(src: &str) -> bool {
+    let mut depth = 0_isize;
+    for token in Lexer::with_offset(src, 0) {
+        match token.ty {
+            TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => depth -= 1,
+            TokenType::Error if token.lexeme.starts_with("/*") => return true,
+            _ => (),
+        }
+    }
+    depth > 0
+}
+
+/// The line editor helper, completing the keywords and global names, and
+/// highlighting Lox code by token types.
+#[derive(Debug, Default)]
+pub(crate) struct LoxHelper {
+    /// The global names defined so far, including the builtins.
+    names: Vec<String>,
+}
+
+impl LoxHelper {
+    pub(crate) fn new(names: Vec<String>) -> Self {
+        Self { names }
+    }
+
+    /// Returns the start of the word ending at `pos` in `line`, and the
+    /// candidates completing it.
+    pub(crate) fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        if before.starts_with(':') && !before.contains(char::is_whitespace) {
+            let cmds = COMMANDS.iter().filter(|it| it.starts_with(before));
+            return (0, cmds.map(|&it| it.to_owned()).collect());
+        }
+        let start = before
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let word = &before[start..];
+        // Properties are unknown before running the code.
+        if word.is_empty() || before[..start].trim_end().ends_with('.') {
+            return (start, vec![]);
+        }
+        let names = (KEYWORDS.iter().copied())
+            .chain(self.names.iter().map(String::as_str))
+            .filter(|it| it.starts_with(word))
+            .sorted()
+            .dedup()
+            .map(str::to_owned)
+            .collect();
+        (start, names)
+    }
+}
+
+/// Returns the ANSI escape code to highlight the tokens of type `ty` with, if
+/// any.
+fn style(ty: TokenType) -> Option<&'static str> {
+    #[allow(clippy::enum_glob_use)]
+    use TokenType::*;
+
+    Some(match ty {
+        And | As | Break | Catch | Class | Continue | Else | Finally | For | From | Fun | If
+        | Import | Or | Print | Return | Super | This | Throw | Try | Var | While => "\x1b[35m",
+        False | Nil | True | Number => "\x1b[33m",
+        Str => "\x1b[32m",
+        SingleLineComment | MultiLineComment => "\x1b[90m",
+        Error => "\x1b[31m",
+        _ => return None,
+    })
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Highlighter for LoxHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if line.trim_start().starts_with(':') {
+            return line.into();
+        }
+        let mut res = String::with_capacity(line.len());
+        let mut last = 0;
+        for token in Lexer::with_offset(line, 0) {
+            let Some(style) = style(token.ty) else {
+                continue;
+            };
+            let (start, end) = (token.span.start, token.span.end);
+            // The line ending of a comment is kept out of the highlight.
+            let lexeme = line[start..end].trim_end_matches(['\r', '\n']);
+            res.push_str(&line[last..start]);
+            res.push_str(style);
+            res.push_str(lexeme);
+            res.push_str("\x1b[0m");
+            last = start + lexeme.len();
+        }
+        res.push_str(&line[last..]);
+        res.into()
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
@@ -0,0 +1,166 @@
+#![cfg(test)]
+
+use indoc::indoc;
+use pretty_assertions::assert_eq;
+use rustyline::history::History;
+
+use super::*;
+
+/// Feeds the given lines to a new REPL session, returning the prompt after
+/// each line and what has been written.
+fn session<B: Backend>(lines: &[&str]) -> Vec<(&'static str, String)> {
+    let mut repl = Repl::<B>::default();
+    (lines.iter())
+        .map(|ln| {
+            let mut out = vec![];
+            repl.feed(ln, &mut out).unwrap();
+            (repl.prompt(), String::from_utf8(out).unwrap())
+        })
+        .collect()
+}
+
+/// Same as [`session`], but with both backends, checking that they agree
+/// with each other.
+fn assert_session(expected: &[(&str, &str, &str)]) {
+    let lines = expected.iter().map(|it| it.0).collect_vec();
+    let tree = session::<Interpreter>(&lines);
+    assert_eq!(tree, session::<Vm>(&lines));
+    let expected = (expected.iter())
+        .map(|&(_, prompt, out)| (prompt, out.to_owned()))
+        .collect_vec();
+    assert_eq!(expected, tree);
+}
+
+#[test]
+fn multi_line() {
+    assert_session(&[
+        ("fun f(n) {", "... ", ""),
+        ("  var xs = [", "... ", ""),
+        ("    n, n + 1,", "... ", ""),
+        ("  ];", "... ", ""),
+        ("", "... ", ""),
+        ("  return xs;", "... ", ""),
+        ("}", ">>> ", ""),
+        ("f(1)", ">>> ", "<<< [1, 2]\n"),
+        // Brackets in String literals and comments are not counted.
+        (r#"var s = "{(" + '[';"#, ">>> ", ""),
+        ("// {", ">>> ", ""),
+        ("s /* ( ", "... ", ""),
+        (" */ == \"{([\"", ">>> ", "<<< true\n"),
+    ]);
+}
+
+#[test]
+fn unbalanced() {
+    let got = session::<Interpreter>(&["var x = 1; }"]);
+    assert_eq!(">>> ", got[0].0);
+    assert!(got[0].1.contains("Error"), "{}", got[0].1);
+
+    let mut repl = Repl::<Interpreter>::default();
+    let mut out = vec![];
+    repl.feed("if (true) {", &mut out).unwrap();
+    assert_eq!("... ", repl.prompt());
+    assert!(repl.cancel());
+    assert_eq!(">>> ", repl.prompt());
+    assert!(!repl.cancel());
+    repl.feed("1 + 1", &mut out).unwrap();
+    assert_eq!("<<< 2\n", String::from_utf8(out).unwrap());
+}
+
+#[test]
+fn commands() {
+    assert_session(&[
+        (":env", ">>> ", "no global variables\n"),
+        ("var b = 2; var a = [b];", ">>> ", ""),
+        (":env", ">>> ", "a = [2]\nb = 2\n"),
+        (":ast -1 + 2 * a.b", ">>> ", "(+ (- 1) (* 2 (. a b)))\n"),
+        (":ast", ">>> ", "usage: :ast EXPR\n"),
+        ("  :reset  ", ">>> ", "the session has been reset\n"),
+        (":env", ">>> ", "no global variables\n"),
+        (":nope 1", ">>> ", "unknown command `:nope`, see `:help`\n"),
+        (":load", ">>> ", "usage: :load FILE\n"),
+        (":time", ">>> ", "usage: :time INPUT\n"),
+    ]);
+
+    let got = session::<Interpreter>(&[":help", ":ast 1 +", ":time 1 + 2", ":time var x = 1;"]);
+    assert!(got[0].1.contains(":load FILE"));
+    assert!(got[1].1.contains("unexpected end of input"), "{}", got[1].1);
+    assert!(got[2].1.starts_with("<<< 3\ntook "), "{}", got[2].1);
+    assert!(got[3].1.starts_with("took "), "{}", got[3].1);
+
+    // Commands are only recognized at the start of the input.
+    let got = session::<Interpreter>(&["[", ":env", "]"]);
+    assert_eq!(">>> ", got[2].0);
+    assert!(got[2].1.contains("Error"), "{}", got[2].1);
+}
+
+#[test]
+fn load() {
+    let dir = std::env::temp_dir().join(format!("dolores-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("lib.lox");
+    std::fs::write(
+        &path,
+        "fun twice(x) { return x * 2; }\nvar unused = fun () {\n  var y = 1;\n};",
+    )
+    .unwrap();
+    let path = path.display().to_string();
+    let got = session::<Interpreter>(&[&format!(":load {path}"), "twice(21)"]);
+    let warning = got[0].1.as_str();
+    assert!(warning.contains("unused local variable `y`"), "{warning}");
+    assert!(warning.contains(&format!("{path}:3:7")), "{warning}");
+    assert_eq!("<<< 42\n", got[1].1);
+
+    let got = session::<Interpreter>(&[":load nope.lox"]);
+    assert!(
+        got[0].1.starts_with("failed to read `nope.lox`: "),
+        "{}",
+        got[0].1
+    );
+}
+
+#[test]
+fn completion() {
+    let mut repl = Repl::<Interpreter>::default();
+    repl.feed("var fox = 1; class Foo {}", &mut vec![]).unwrap();
+    let helper = LoxHelper::new(repl.names());
+    let complete = |line: &str| {
+        let history = History::new();
+        helper
+            .complete(line, line.len(), &Context::new(&history))
+            .unwrap()
+    };
+    let names = |it: &[&str]| it.iter().map(|&it| it.to_owned()).collect_vec();
+    assert_eq!(
+        (
+            4,
+            names(&["false", "finally", "floor", "for", "fox", "from", "fun"])
+        ),
+        complete("1 + f")
+    );
+    assert_eq!((0, names(&["Foo"])), complete("F"));
+    assert_eq!((6, names(&["for", "fox"])), complete("print fo"));
+    assert_eq!((0, names(&["len"])), complete("le"));
+    assert_eq!((4, vec![]), complete("fox."));
+    assert_eq!((4, vec![]), complete("fox.f"));
+    assert_eq!((0, names(&[":load"])), complete(":lo"));
+    assert_eq!((6, vec![]), complete(":load "));
+}
+
+#[test]
+fn highlight() {
+    let helper = LoxHelper::default();
+    let line = r#"var x = "${1}" + nil; // done"#;
+    let expected = indoc! {"
+        \x1b[35mvar\x1b[0m x = \x1b[32m\"${1}\"\x1b[0m + \x1b[33mnil\x1b[0m; \x1b[90m// done\x1b[0m
+    "};
+    assert_eq!(expected.trim_end(), helper.highlight(line, 0));
+    assert_eq!(
+        "@",
+        helper
+            .highlight("@", 0)
+            .replace("\x1b[31m", "")
+            .replace("\x1b[0m", "")
+    );
+    assert_eq!(":ast var", helper.highlight(":ast var", 0));
+}
@@ -4,15 +4,41 @@ mod stmt;
 use std::{collections::HashMap, mem};
 
 use anyhow::Result;
+use itertools::Itertools;
 
-use crate::{interpreter::Interpreter, lexer::Token, parser::Stmt};
+use crate::{
+    error::{Code, Diagnostic, Errors},
+    interpreter::Interpreter,
+    lexer::{Token, TokenType},
+    parser::Stmt,
+};
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub(crate) struct Resolver {
     pub(crate) interpreter: Interpreter,
     scopes: Vec<Scope>,
     jump_ctx: JumpContext,
     class_ctx: ClassContext,
+    /// The semantic errors found so far.
+    errors: Vec<Diagnostic>,
+    /// The warnings found so far.
+    warnings: Vec<Diagnostic>,
+    /// The names declared and used so far.
+    bindings: Bindings,
+    /// The first declaration of each global name.
+    globals: HashMap<String, Token>,
+    /// The names used without a local declaration.
+    free: Vec<Token>,
+}
+
+/// The names declared and used in a program, collected for editor tooling.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Bindings {
+    /// The tokens declaring names, including the global ones.
+    pub(crate) decls: Vec<Token>,
+    /// The token declaring the name of each variable expression or assignment
+    /// target, if known.
+    pub(crate) uses: HashMap<Token, Token>,
 }
 
 // See: <https://www.craftinginterpreters.com/resolving-and-binding.html#resolving-variable-declarations>
@@ -25,12 +51,46 @@ pub(crate) enum ResolutionState {
     Defined,
 }
 
-pub(crate) type Scope = HashMap<String, ResolutionState>;
+/// The kind of a name bound in a [`Scope`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BindingKind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+    /// `this` or `super`.
+    Implicit,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Binding {
+    pub(crate) state: ResolutionState,
+    pub(crate) kind: BindingKind,
+    /// The token declaring this binding, if not implicit.
+    pub(crate) decl: Option<Token>,
+    /// If this binding is referred to after its declaration.
+    pub(crate) used: bool,
+}
 
-#[derive(Debug, Clone, Copy, Default)]
+impl Binding {
+    pub(crate) fn implicit() -> Self {
+        Self {
+            state: ResolutionState::Defined,
+            kind: BindingKind::Implicit,
+            decl: None,
+            used: false,
+        }
+    }
+}
+
+pub(crate) type Scope = HashMap<String, Binding>;
+
+#[derive(Debug, Clone, Default)]
 pub(crate) struct JumpContext {
     pub(crate) fun_ty: Option<FunctionContextType>,
-    pub(crate) in_loop: bool,
+    /// The labels of the loops enclosing the current statement in the current
+    /// function, from the outermost to the innermost.
+    pub(crate) loops: Vec<Option<String>>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -53,67 +113,220 @@ impl Resolver {
     pub(crate) fn new(interpreter: Interpreter) -> Self {
         Self {
             interpreter,
-            ..Self::default()
+            scopes: vec![],
+            jump_ctx: JumpContext::default(),
+            class_ctx: None,
+            errors: vec![],
+            warnings: vec![],
+            bindings: Bindings::default(),
+            globals: HashMap::new(),
+            free: vec![],
         }
     }
 
+    fn error(&mut self, diag: Diagnostic) {
+        self.errors.push(diag);
+    }
+
+    fn warn(&mut self, diag: Diagnostic) {
+        self.warnings.push(diag);
+    }
+
     fn begin_scope(&mut self) -> &mut Scope {
         self.scopes.push(HashMap::new());
         self.scopes.last_mut().unwrap()
     }
 
-    fn end_scope(&mut self) -> Option<Scope> {
-        self.scopes.pop()
+    /// Ends the innermost scope, warning about the variables and parameters
+    /// never used in it.
+    fn end_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        let unused = scope
+            .into_iter()
+            .filter(|(name, it)| !it.used && !name.starts_with('_'))
+            .filter_map(|(_, it)| Some((it.kind, it.decl?)))
+            .sorted_by_key(|(_, decl)| decl.span.start);
+        for (kind, decl) in unused {
+            let (code, what) = match kind {
+                BindingKind::Variable => (Code::UnusedVariable, "local variable"),
+                BindingKind::Parameter => (Code::UnusedParam, "parameter"),
+                _ => continue,
+            };
+            self.warn(
+                Diagnostic::warning(
+                    code,
+                    decl.span,
+                    "while resolving a scope",
+                    format!("unused {what} `{}`", decl.lexeme),
+                )
+                .with_note(format!(
+                    "prefix it with an underscore to silence this warning: `_{}`",
+                    decl.lexeme,
+                )),
+            );
+        }
     }
 
-    /// Sets the resolution state of the given `token` in the currently smallest
-    /// scope, returning the last state if exists.
-    fn set_state(&mut self, token: &Token, state: ResolutionState) -> Option<ResolutionState> {
+    /// Declares the given `token` in the innermost scope, warning if it
+    /// redeclares a local variable of that scope or shadows one of an
+    /// enclosing scope.
+    fn declare(&mut self, token: &Token, kind: BindingKind) {
+        self.bindings.decls.push(token.clone());
+        let Some((last, enclosing)) = self.scopes.split_last() else {
+            (self.globals)
+                .entry(token.lexeme.clone())
+                .or_insert_with(|| token.clone());
+            return;
+        };
+        let redeclared = last.get(&token.lexeme).and_then(|it| it.decl.clone());
+        let shadowed =
+            (enclosing.iter().rev()).find_map(|scope| scope.get(&token.lexeme)?.decl.clone());
+        if let Some(redeclared) = redeclared {
+            self.warn(
+                Diagnostic::warning(
+                    Code::RedeclaredLocal,
+                    token.span,
+                    "while resolving a declaration",
+                    format!("`{}` is already declared in this scope", token.lexeme),
+                )
+                .with_label(redeclared.span, "previously declared here"),
+            );
+        } else if let Some(shadowed) = shadowed {
+            self.warn(
+                Diagnostic::warning(
+                    Code::ShadowedLocal,
+                    token.span,
+                    "while resolving a declaration",
+                    format!("`{}` shadows a local variable", token.lexeme),
+                )
+                .with_label(shadowed.span, "previously declared here"),
+            );
+        }
+        let binding = Binding {
+            state: ResolutionState::Declared,
+            kind,
+            decl: Some(token.clone()),
+            used: false,
+        };
         self.scopes
             .last_mut()
-            .and_then(|last| last.insert(token.lexeme.clone(), state))
+            .unwrap()
+            .insert(token.lexeme.clone(), binding);
     }
 
-    fn declare(&mut self, token: &Token) -> Option<ResolutionState> {
-        self.set_state(token, ResolutionState::Declared)
+    fn define(&mut self, token: &Token) {
+        if let Some(it) = (self.scopes.last_mut()).and_then(|last| last.get_mut(&token.lexeme)) {
+            it.state = ResolutionState::Defined;
+        }
     }
 
-    fn define(&mut self, token: &Token) -> Option<ResolutionState> {
-        self.set_state(token, ResolutionState::Defined)
+    /// Resolves the variable `name` to its local declaration, if any, marking
+    /// it as used if it is `read`, rather than only assigned to.
+    fn resolve_local(&mut self, name: &Token, read: bool) {
+        let found = (self.scopes.iter_mut().rev().enumerate())
+            .find_map(|(distance, scope)| Some((distance, scope.get_mut(&name.lexeme)?)));
+        match found {
+            Some((distance, binding)) => {
+                binding.used |= read;
+                if let Some(decl) = &binding.decl {
+                    (self.bindings.uses).insert(name.clone(), decl.clone());
+                }
+                self.interpreter.locals.insert(name.clone(), distance);
+            }
+            None if name.ty == TokenType::Identifier => self.free.push(name.clone()),
+            None => (),
+        }
     }
 
-    fn resolve_local(&mut self, name: &Token) {
-        self.scopes
-            .iter()
-            .rev()
-            .enumerate()
-            .find_map(|(distance, scope)| {
-                scope
-                    .contains_key(&name.lexeme)
-                    .then(|| self.interpreter.locals.insert(name.clone(), distance))
-            });
+    /// Resolves the statements in a block, warning about unreachable code.
+    fn resolve_block(&mut self, stmts: Vec<Stmt>) {
+        let mut stmts = stmts.into_iter().peekable();
+        let mut warned = false;
+        while let Some(stmt) = stmts.next() {
+            if let Stmt::Return { kw, .. } | Stmt::Throw { kw, .. } | Stmt::Jump { kw, .. } = &stmt
+            {
+                if !warned && stmts.peek().is_some() {
+                    warned = true;
+                    self.warn(Diagnostic::warning(
+                        Code::UnreachableCode,
+                        kw.span,
+                        "while resolving a Block statement",
+                        format!("unreachable code after `{}`", kw.lexeme),
+                    ));
+                }
+            }
+            self.resolve_stmt(stmt);
+        }
     }
 
-    pub(crate) fn resolve_lambda(
-        &mut self,
-        ctx: JumpContext,
-        params: &[Token],
-        body: Vec<Stmt>,
-    ) -> Result<()> {
+    pub(crate) fn resolve_lambda(&mut self, ctx: JumpContext, params: &[Token], body: Vec<Stmt>) {
         let old_ctx = mem::replace(&mut self.jump_ctx, ctx);
         self.begin_scope();
         for it in params {
-            self.declare(it);
+            self.declare(it, BindingKind::Parameter);
             self.define(it);
         }
-        body.into_iter().try_for_each(|it| self.resolve_stmt(it))?;
+        self.resolve_block(body);
         self.end_scope();
         self.jump_ctx = old_ctx;
-        Ok(())
     }
 
-    pub(crate) fn resolve(mut self, stmts: impl IntoIterator<Item = Stmt>) -> Result<Interpreter> {
-        stmts.into_iter().try_for_each(|it| self.resolve_stmt(it))?;
-        Ok(self.interpreter)
+    /// Enters a scope for each of the given lists of names from the outermost
+    /// one, so that code can be resolved as if it were nested in existing
+    /// environments, e.g. when it's evaluated in a debugger.
+    pub(crate) fn enter_scopes(&mut self, scopes: impl IntoIterator<Item = Vec<String>>) {
+        for names in scopes {
+            let scope = self.begin_scope();
+            for name in names {
+                let binding = Binding {
+                    state: ResolutionState::Defined,
+                    kind: BindingKind::Variable,
+                    decl: None,
+                    used: true,
+                };
+                scope.insert(name, binding);
+            }
+        }
+        let has = |name| self.scopes.iter().any(|it| it.contains_key(name));
+        if has("super") {
+            self.class_ctx = Some(ClassContextType::Subclass);
+        } else if has("this") {
+            self.class_ctx = Some(ClassContextType::Class);
+        }
+    }
+
+    pub(crate) fn resolve(
+        mut self,
+        stmts: impl IntoIterator<Item = Stmt>,
+    ) -> (Interpreter, Result<Vec<Diagnostic>>) {
+        stmts.into_iter().for_each(|it| self.resolve_stmt(it));
+        self.finish()
+    }
+
+    /// Returns the bindings found so far, where a name without a local
+    /// declaration refers to the first global declaration of the same name.
+    pub(crate) fn bindings(&self) -> Bindings {
+        let mut res = self.bindings.clone();
+        for name in &self.free {
+            if let Some(decl) = self.globals.get(&name.lexeme) {
+                res.uses.insert(name.clone(), decl.clone());
+            }
+        }
+        res
+    }
+
+    /// Returns the interpreter with the resolution results, together with the
+    /// warnings found if there is no error.
+    pub(crate) fn finish(self) -> (Interpreter, Result<Vec<Diagnostic>>) {
+        let res = if self.errors.is_empty() {
+            Ok(self.warnings)
+        } else {
+            Err(Errors::combine(
+                self.errors.into_iter().map_into().collect(),
+            ))
+        };
+        (self.interpreter, res)
     }
 }
@@ -1,72 +1,102 @@
-use anyhow::Result;
-
 use super::{ClassContextType, FunctionContextType, JumpContext, ResolutionState, Resolver};
-use crate::{parser::Expr, semantic_bail};
+use crate::{error::Code, parser::Expr, report};
 
 impl Resolver {
-    pub(crate) fn resolve_expr(&mut self, expr: Expr) -> Result<()> {
+    pub(crate) fn resolve_expr(&mut self, expr: Expr) {
         match expr {
             Expr::Assign { name, val } => {
-                self.resolve_expr(*val)?;
-                self.resolve_local(&name);
+                self.resolve_expr(*val);
+                self.resolve_local(&name, false);
             }
             Expr::Binary { lhs, rhs, .. } | Expr::Logical { lhs, rhs, .. } => {
-                self.resolve_expr(*lhs)?;
-                self.resolve_expr(*rhs)?;
+                self.resolve_expr(*lhs);
+                self.resolve_expr(*rhs);
             }
             Expr::Call { callee, args, .. } => {
-                self.resolve_expr(*callee)?;
-                args.into_iter().try_for_each(|it| self.resolve_expr(it))?;
+                self.resolve_expr(*callee);
+                for it in args {
+                    self.resolve_expr(it);
+                }
+            }
+            Expr::Get { obj, .. } => self.resolve_expr(*obj),
+            Expr::Grouping(inner) => self.resolve_expr(*inner),
+            Expr::Interpolation(parts) => {
+                for it in parts {
+                    self.resolve_expr(it);
+                }
             }
-            Expr::Get { obj, .. } => self.resolve_expr(*obj)?,
-            Expr::Grouping(inner) => self.resolve_expr(*inner)?,
-            Expr::Lambda { params, body } => {
+            Expr::Index { obj, idx, .. } => {
+                self.resolve_expr(*obj);
+                self.resolve_expr(*idx);
+            }
+            Expr::IndexSet { obj, idx, to, .. } => {
+                self.resolve_expr(*to);
+                self.resolve_expr(*obj);
+                self.resolve_expr(*idx);
+            }
+            Expr::Lambda { params, body, .. } => {
                 let ctx = JumpContext {
                     fun_ty: Some(FunctionContextType::Function),
-                    in_loop: false,
+                    loops: vec![],
                 };
-                self.resolve_lambda(ctx, &params, body)?;
+                self.resolve_lambda(ctx, &params, body);
             }
+            Expr::List(elems) => elems.into_iter().for_each(|it| self.resolve_expr(it)),
             Expr::Literal(_) => (),
+            Expr::Map { entries, .. } => entries.into_iter().for_each(|(k, v)| {
+                self.resolve_expr(k);
+                self.resolve_expr(v);
+            }),
             Expr::Set { obj, to, .. } => {
-                self.resolve_expr(*to)?;
-                self.resolve_expr(*obj)?;
+                self.resolve_expr(*to);
+                self.resolve_expr(*obj);
             }
             Expr::Super { kw, .. } => {
                 if self.class_ctx != Some(ClassContextType::Subclass) {
-                    semantic_bail!(
-                        kw.pos,
+                    self.error(report!(
+                        Code::SuperOutOfSubclass,
+                        kw.span,
                         "while resolving a superclass method",
                         "found `super` out of subclass context",
-                    )
+                    ));
                 }
-                self.resolve_local(&kw);
+                self.resolve_local(&kw, true);
+            }
+            Expr::Ternary {
+                cond,
+                then_expr,
+                else_expr,
+            } => {
+                self.resolve_expr(*cond);
+                self.resolve_expr(*then_expr);
+                self.resolve_expr(*else_expr);
             }
             Expr::This(kw) => {
                 if self.class_ctx.is_none() {
-                    semantic_bail!(
-                        kw.pos,
+                    self.error(report!(
+                        Code::ThisOutOfClass,
+                        kw.span,
                         "while resolving a This expression",
                         "found `this` out of class context",
-                    )
+                    ));
                 }
-                self.resolve_local(&kw);
+                self.resolve_local(&kw, true);
             }
-            Expr::Unary { rhs, .. } => self.resolve_expr(*rhs)?,
+            Expr::Unary { rhs, .. } => self.resolve_expr(*rhs),
             Expr::Variable(tk) => {
                 if let Some(ResolutionState::Declared) =
-                    self.scopes.last().and_then(|last| last.get(&tk.lexeme))
+                    (self.scopes.last()).and_then(|last| Some(last.get(&tk.lexeme)?.state))
                 {
-                    semantic_bail!(
-                        tk.pos,
+                    self.error(report!(
+                        Code::SelfInit,
+                        tk.span,
                         "while resolving a Variable expression",
                         "cannot read local Variable `{}` in its own initializer",
                         tk.lexeme
-                    )
+                    ));
                 }
-                self.resolve_local(&tk);
+                self.resolve_local(&tk, true);
             }
         }
-        Ok(())
     }
 }
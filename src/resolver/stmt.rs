@@ -1,20 +1,22 @@
 use std::mem;
 
-use anyhow::Result;
-
-use super::{ClassContextType, FunctionContextType, JumpContext, ResolutionState, Resolver};
+use super::{Binding, BindingKind, ClassContextType, FunctionContextType, JumpContext, Resolver};
 use crate::{
+    error::Code,
     parser::{Expr, Stmt},
-    semantic_bail,
+    report,
 };
 
 impl Resolver {
     #[allow(clippy::too_many_lines)]
-    pub(crate) fn resolve_stmt(&mut self, stmt: Stmt) -> Result<()> {
+    pub(crate) fn resolve_stmt(&mut self, stmt: Stmt) {
+        if let Some(coverage) = &self.interpreter.coverage {
+            coverage.add(&stmt);
+        }
         match stmt {
             Stmt::Block(stmts) => {
                 self.begin_scope();
-                stmts.into_iter().try_for_each(|it| self.resolve_stmt(it))?;
+                self.resolve_block(stmts);
                 self.end_scope();
             }
             Stmt::Class {
@@ -31,29 +33,30 @@ impl Resolver {
                         ClassContextType::Class
                     }),
                 );
-                self.declare(&name);
+                self.declare(&name, BindingKind::Class);
                 self.define(&name);
                 if is_sub {
                     // This is a safe unwrap since `superclass.is_some()`.
                     let sup = superclass.unwrap();
                     if let Expr::Variable(ref sup) = sup {
                         if sup.lexeme == name.lexeme {
-                            semantic_bail!(
-                                sup.pos,
+                            self.error(report!(
+                                Code::SelfInherit,
+                                sup.span,
                                 "while resolving a Class declaration",
                                 "class `{}` cannot inherit from itself",
                                 sup.lexeme,
-                            )
+                            ));
                         }
                     }
-                    self.resolve_expr(sup)?;
+                    self.resolve_expr(sup);
                     // Only subclasses can have `super`.
                     self.begin_scope()
-                        .insert("super".into(), ResolutionState::Defined);
+                        .insert("super".into(), Binding::implicit());
                 }
                 self.begin_scope()
-                    .insert("this".into(), ResolutionState::Defined);
-                methods.into_iter().try_for_each(|it| {
+                    .insert("this".into(), Binding::implicit());
+                for it in methods {
                     if let Stmt::Fun { name, params, body } = it {
                         let fun_ty = Some(if name.lexeme == "init" {
                             FunctionContextType::Initializer
@@ -62,87 +65,145 @@ impl Resolver {
                         });
                         let ctx = JumpContext {
                             fun_ty,
-                            in_loop: false,
+                            loops: vec![],
                         };
-                        self.resolve_lambda(ctx, &params, body)
+                        self.resolve_lambda(ctx, &params, body);
                     } else {
                         unreachable!()
                     }
-                })?;
+                }
                 self.end_scope();
                 if is_sub {
                     self.end_scope();
                 }
                 self.class_ctx = old_ctx;
             }
-            Stmt::Expression(expr) => self.resolve_expr(expr)?,
+            Stmt::Expression(expr) => self.resolve_expr(expr),
             Stmt::Fun { name, params, body } => {
-                self.declare(&name);
+                self.declare(&name, BindingKind::Function);
                 // We define a function's name eagerly to enable hoisting, which is ideal for
                 // usages like recursion. We don't like JavaScript, so we don't
                 // hoist variables.
                 self.define(&name);
                 let ctx = JumpContext {
                     fun_ty: Some(FunctionContextType::Function),
-                    in_loop: false,
+                    loops: vec![],
                 };
-                self.resolve_lambda(ctx, &params, body)?;
+                self.resolve_lambda(ctx, &params, body);
             }
             Stmt::If {
                 cond,
                 then_stmt,
                 else_stmt,
+                ..
             } => {
-                self.resolve_expr(cond)?;
-                self.resolve_stmt(*then_stmt)?;
+                self.resolve_expr(cond);
+                self.resolve_stmt(*then_stmt);
                 if let Some(else_stmt) = else_stmt {
-                    self.resolve_stmt(*else_stmt)?;
+                    self.resolve_stmt(*else_stmt);
                 }
             }
-            Stmt::Jump(kw) => {
-                if !self.jump_ctx.in_loop {
-                    semantic_bail!(
-                        kw.pos,
-                        "while resolving a Jump statement",
+            Stmt::Jump { kw, label } => {
+                let ctx = "while resolving a Jump statement";
+                if self.jump_ctx.loops.is_empty() {
+                    self.error(report!(
+                        Code::JumpOutOfLoop,
+                        kw.span,
+                        ctx,
                         "found `{}` out of loop context",
                         kw.lexeme,
-                    )
+                    ));
+                } else if let Some(label) = label {
+                    let found = self
+                        .jump_ctx
+                        .loops
+                        .iter()
+                        .any(|it| it.as_ref() == Some(&label.lexeme));
+                    if !found {
+                        self.error(report!(
+                            Code::UndefinedLabel,
+                            label.span,
+                            ctx,
+                            "found `{}` to undefined label `{}`",
+                            kw.lexeme,
+                            label.lexeme,
+                        ));
+                    }
                 }
             }
-            Stmt::Print(val) => self.resolve_expr(val)?,
+            Stmt::Print { val, .. } | Stmt::Throw { val, .. } => self.resolve_expr(val),
             Stmt::Return { val, kw } => {
                 if self.jump_ctx.fun_ty.is_none() {
-                    semantic_bail!(
-                        kw.pos,
+                    self.error(report!(
+                        Code::ReturnOutOfFun,
+                        kw.span,
                         "while resolving a Return statement",
                         "found `return` out of function context",
-                    )
+                    ));
                 }
                 if self.jump_ctx.fun_ty == Some(FunctionContextType::Initializer) && val.is_some() {
-                    semantic_bail!(
-                        kw.pos,
+                    self.error(report!(
+                        Code::ReturnInInit,
+                        kw.span,
                         "while resolving a Return statement",
                         "found returned value in initializer context",
-                    )
+                    ));
                 }
                 if let Some(val) = val {
-                    self.resolve_expr(val)?;
+                    self.resolve_expr(val);
+                }
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.begin_scope();
+                self.resolve_block(body);
+                self.end_scope();
+                if let Some((name, body)) = catch {
+                    // The exception variable lives in the same scope as the body.
+                    self.begin_scope();
+                    self.declare(&name, BindingKind::Variable);
+                    self.define(&name);
+                    self.resolve_block(body);
+                    self.end_scope();
+                }
+                if let Some(body) = finally {
+                    self.begin_scope();
+                    self.resolve_block(body);
+                    self.end_scope();
+                }
+            }
+            Stmt::Import { alias, names, .. } => {
+                for name in alias.iter().chain(&names) {
+                    self.declare(name, BindingKind::Variable);
+                    self.define(name);
                 }
             }
             Stmt::Var { name, init } => {
-                self.declare(&name);
+                self.declare(&name, BindingKind::Variable);
                 if let Some(init) = init {
-                    self.resolve_expr(init)?;
+                    self.resolve_expr(init);
                 }
                 self.define(&name);
             }
-            Stmt::While { cond, body } => {
-                let old_in_loop = std::mem::replace(&mut self.jump_ctx.in_loop, true);
-                self.resolve_expr(cond)?;
-                self.resolve_stmt(*body)?;
-                self.jump_ctx.in_loop = old_in_loop;
+            Stmt::While {
+                label,
+                cond,
+                body,
+                incr,
+                ..
+            } => {
+                self.resolve_expr(cond);
+                self.jump_ctx.loops.push(label.map(|it| it.lexeme));
+                self.resolve_stmt(*body);
+                self.jump_ctx.loops.pop();
+                if let Some(incr) = incr {
+                    self.resolve_expr(incr);
+                }
             }
         }
-        Ok(())
     }
 }
@@ -1,63 +1,261 @@
-use std::path::Path;
+use std::{fs, path::Path, rc::Rc};
 
 use anyhow::Result;
-use rustyline::{error::ReadlineError, Editor};
+use clap::ValueEnum;
 
-use crate::{interpreter::Interpreter, lexer::Lexer, parser::Parser};
+use crate::{
+    error::{render_error, Diagnostic},
+    interpreter::{Coverage, Env, Interpreter, Object, Profiler},
+    lexer::Lexer,
+    module::{self, Modules},
+    parser::{Expr, Parser, Stmt},
+    source::SourceMap,
+    util::MutCell,
+    vm::Vm,
+};
 
-pub(crate) fn run_file(path: impl AsRef<Path>) -> Result<()> {
-    let interpreter = &mut Interpreter::default();
-    let contents = std::fs::read_to_string(path)?;
-    run(&contents, interpreter, false);
+/// The available execution backends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub(crate) enum BackendKind {
+    /// The AST-walking interpreter.
+    #[default]
+    Tree,
+    /// The bytecode compiler and virtual machine.
+    Bytecode,
+}
+
+/// The available formats of coverage reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub(crate) enum CoverageFormat {
+    /// A summary of each file, listing the lines never run.
+    #[default]
+    Text,
+    /// The LCOV tracefile format.
+    Lcov,
+}
+
+/// An execution backend, holding the state of a Lox session.
+pub(crate) trait Backend: Default {
+    /// Checks the given statements for semantic errors, returning the
+    /// warnings found.
+    fn resolve_stmts(&mut self, stmts: &[Stmt]) -> Result<Vec<Diagnostic>>;
+    /// Checks the given expression for semantic errors, returning the
+    /// warnings found.
+    fn resolve_expr(&mut self, expr: &Expr) -> Result<Vec<Diagnostic>>;
+    /// Executes the given statements, which should have been resolved.
+    fn exec_stmts(&mut self, stmts: Vec<Stmt>) -> Result<()>;
+    /// Evaluates the given expression, which should have been resolved.
+    fn eval_expr(&mut self, expr: Expr) -> Result<Object>;
+    /// The sources evaluated so far, memorized for error reports.
+    fn sources(&mut self) -> &mut SourceMap;
+    /// The modules imported so far.
+    fn modules(&mut self) -> &mut Modules;
+    /// Resolves and runs the given statements of a module in a new global
+    /// environment, which is returned.
+    ///
+    /// The warnings found in imported modules are not reported.
+    fn exec_module(&mut self, stmts: Vec<Stmt>) -> Result<MutCell<Env>>;
+    /// The global environment, whose outer environment holds the builtins.
+    fn globals(&mut self) -> &MutCell<Env>;
+}
+
+impl Backend for Interpreter {
+    fn resolve_stmts(&mut self, stmts: &[Stmt]) -> Result<Vec<Diagnostic>> {
+        Interpreter::resolve_stmts(self, stmts.to_vec())
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) -> Result<Vec<Diagnostic>> {
+        Interpreter::resolve_expr(self, expr.clone())
+    }
+
+    fn exec_stmts(&mut self, stmts: Vec<Stmt>) -> Result<()> {
+        let res = Interpreter::exec_stmts(self, stmts).map(|_| ());
+        self.thrown.clear();
+        res
+    }
+
+    fn eval_expr(&mut self, expr: Expr) -> Result<Object> {
+        let res = self.eval(expr);
+        self.thrown.clear();
+        res
+    }
+
+    fn sources(&mut self) -> &mut SourceMap {
+        &mut self.sources
+    }
+
+    fn modules(&mut self) -> &mut Modules {
+        &mut self.modules
+    }
+
+    fn exec_module(&mut self, stmts: Vec<Stmt>) -> Result<MutCell<Env>> {
+        Interpreter::exec_module(self, stmts)
+    }
+
+    fn globals(&mut self) -> &MutCell<Env> {
+        &self.globals
+    }
+}
+
+impl Backend for Vm {
+    fn resolve_stmts(&mut self, stmts: &[Stmt]) -> Result<Vec<Diagnostic>> {
+        Vm::resolve_stmts(self, stmts)
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) -> Result<Vec<Diagnostic>> {
+        Vm::resolve_expr(self, expr)
+    }
+
+    fn exec_stmts(&mut self, stmts: Vec<Stmt>) -> Result<()> {
+        let res = Vm::exec_stmts(self, &stmts);
+        self.thrown.clear();
+        res
+    }
+
+    fn eval_expr(&mut self, expr: Expr) -> Result<Object> {
+        let res = self.eval(&expr);
+        self.thrown.clear();
+        res
+    }
+
+    fn sources(&mut self) -> &mut SourceMap {
+        &mut self.sources
+    }
+
+    fn modules(&mut self) -> &mut Modules {
+        &mut self.modules
+    }
+
+    fn exec_module(&mut self, stmts: Vec<Stmt>) -> Result<MutCell<Env>> {
+        Vm::exec_module(self, &stmts)
+    }
+
+    fn globals(&mut self) -> &MutCell<Env> {
+        &self.globals
+    }
+}
+
+pub(crate) fn run_file(path: impl AsRef<Path>, backend: BackendKind) -> Result<()> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)?;
+    match backend {
+        BackendKind::Tree => run_entry(path, &contents, &mut Interpreter::default()),
+        BackendKind::Bytecode => run_entry(path, &contents, &mut Vm::default()),
+    }
     Ok(())
 }
 
-pub(crate) fn run_prompt() -> Result<()> {
-    let interpreter = &mut Interpreter::default();
-    let mut reader = Editor::<()>::new();
-    loop {
-        match reader.readline(">>> ") {
-            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break Ok(()),
-            ln => run(&ln?, interpreter, true),
+/// Runs the source `src` of the entry file at `path`, which is loaded as a
+/// module while it runs.
+fn run_entry(path: &Path, src: &str, backend: &mut impl Backend) {
+    let name = path.display().to_string();
+    module::run_entry(backend, path, |backend| run(&name, src, backend, false));
+}
+
+/// Runs the file at `path` with the tree backend, then reports the calls of
+/// each function if `profile` is set, and the lines run in the format
+/// `coverage` if any.
+///
+/// The reports are written to the standard error, except for the coverage
+/// report, which is written to `coverage_output` if any.
+pub(crate) fn run_file_instrumented(
+    path: impl AsRef<Path>,
+    profile: bool,
+    coverage: Option<CoverageFormat>,
+    coverage_output: Option<&str>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)?;
+    let mut interpreter = Interpreter::default();
+    interpreter.profiler = profile.then(|| Rc::new(Profiler::default()));
+    interpreter.coverage = coverage.map(|_| Rc::new(Coverage::default()));
+    run_entry(path, &contents, &mut interpreter);
+
+    let sources = &interpreter.sources;
+    if let Some(profiler) = &interpreter.profiler {
+        eprint!("{}", profiler.report(sources));
+    }
+    if let (Some(format), Some(recorder)) = (coverage, &interpreter.coverage) {
+        let report = match format {
+            CoverageFormat::Text => recorder.text(sources),
+            CoverageFormat::Lcov => recorder.lcov(sources),
+        };
+        match coverage_output {
+            Some(path) => fs::write(path, report)?,
+            None => eprint!("{report}"),
         }
     }
+    Ok(())
+}
+
+pub(crate) fn run_str<B: Backend>(
+    name: &str,
+    src: &str,
+    backend: &mut B,
+    repl_mode: bool,
+    warn: impl FnMut(&Diagnostic, &SourceMap),
+) -> Result<String> {
+    let obj = eval_str(name, src, backend, repl_mode, warn)?;
+    Ok(obj.map_or_else(String::new, |obj| format!("{obj}")))
 }
 
-pub(crate) fn run_str(src: &str, interpreter: &mut Interpreter, repl_mode: bool) -> Result<String> {
-    let tokens = || Lexer::new(src);
+/// Runs the source string `src` named `name`, returning the value of the
+/// expression if the source is evaluated as one in REPL mode.
+///
+/// The warnings found are passed to `warn` before running the source.
+pub(crate) fn eval_str<B: Backend>(
+    name: &str,
+    src: &str,
+    backend: &mut B,
+    repl_mode: bool,
+    mut warn: impl FnMut(&Diagnostic, &SourceMap),
+) -> Result<Option<Object>> {
+    let mut warn_all = |backend: &mut B, warnings: Vec<Diagnostic>| {
+        for it in &warnings {
+            warn(it, backend.sources());
+        }
+    };
+    // Each source gets its own offsets, so that its tokens never collide with
+    // those seen before.
+    let offset = backend.sources().add(name, src);
+    let tokens = || Lexer::with_offset(src, offset);
     match Parser::new(tokens()).parse() {
         Ok(stmts) => {
-            interpreter.resolve_stmts(stmts.clone())?;
-            interpreter.exec_stmts(stmts)?;
-            Ok("".into())
+            let warnings = backend.resolve_stmts(&stmts)?;
+            warn_all(backend, warnings);
+            backend.exec_stmts(stmts)?;
+            Ok(None)
         }
         Err(e) if repl_mode =>
         // In REPL mode, if the user types an expression instead of a statement, the
         // value of that expression is automatically printed out.
         {
             Parser::new(tokens())
-                .expr()
+                .parse_leading_expr()
                 .map_err(|e1| {
-                    e.context("[REPL] statement parsing failed, falling back to expression parsing")
-                        .context(e1)
+                    let e = e.context(
+                        "[REPL] statement parsing failed, falling back to expression parsing",
+                    );
+                    match e1.downcast::<Diagnostic>() {
+                        Ok(e1) => e.context(e1),
+                        Err(e1) => e.context(e1),
+                    }
                 })
                 .and_then(|expr| {
-                    interpreter.resolve_expr(expr.clone())?;
-                    interpreter.eval(expr)
+                    let warnings = backend.resolve_expr(&expr)?;
+                    warn_all(backend, warnings);
+                    backend.eval_expr(expr).map(Some)
                 })
-                .map(|obj| format!("{}", obj))
         }
         Err(e) => Err(e),
     }
 }
 
-fn run(src: &str, interpreter: &mut Interpreter, repl_mode: bool) {
-    run_str(src, interpreter, repl_mode).map_or_else(
-        |e| println!("{:?}", e),
-        |expr| {
-            if !expr.is_empty() {
-                println!("<<< {}", expr);
-            }
-        },
-    );
+fn run(name: &str, src: &str, backend: &mut impl Backend, repl_mode: bool) {
+    let warn = |it: &Diagnostic, sources: &SourceMap| print!("{}", it.render(sources));
+    match run_str(name, src, backend, repl_mode, warn) {
+        Ok(res) if !res.is_empty() => println!("<<< {res}"),
+        Ok(_) => (),
+        Err(e) => print!("{}", render_error(&e, backend.sources())),
+    }
 }
@@ -0,0 +1,126 @@
+use std::fmt::Display;
+
+/// A range of bytes in a [`SourceMap`], where `start` is inclusive and `end`
+/// is exclusive.
+///
+/// The default span refers to no source at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    #[must_use]
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the smallest span covering both `self` and `other`.
+    #[must_use]
+    pub fn to(self, other: Self) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// A named piece of source code.
+#[derive(Debug, Clone)]
+pub(crate) struct SourceFile {
+    pub(crate) name: String,
+    pub(crate) src: String,
+    /// The offset of the first byte of this file in its `SourceMap`.
+    pub(crate) start: usize,
+}
+
+impl SourceFile {
+    /// Returns the `(line_num, column_num)` pair of the byte at `offset` in the
+    /// text editor standard (index starting from 1), where columns are counted
+    /// in chars.
+    pub(crate) fn pos(&self, offset: usize) -> (usize, usize) {
+        let idx = offset.saturating_sub(self.start).min(self.src.len());
+        let before = &self.src[..idx];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line = before.matches('\n').count() + 1;
+        (line, before[line_start..].chars().count() + 1)
+    }
+
+    /// Returns the `n`th line (index starting from 1) without its line ending,
+    /// together with the offset of its first byte in the `SourceMap`.
+    pub(crate) fn line(&self, n: usize) -> Option<(&str, usize)> {
+        let mut start = 0;
+        for (i, ln) in self.src.split('\n').enumerate() {
+            if i + 1 == n {
+                return Some((ln.trim_end_matches('\r'), self.start + start));
+            }
+            start += ln.len() + 1;
+        }
+        None
+    }
+}
+
+/// All the source code seen in a session, e.g. every line typed in a REPL.
+///
+/// Each file occupies a distinct range of offsets, so that a [`Span`] always
+/// refers to a unique piece of source code.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    /// Adds a new file, returning the offset of its first byte.
+    pub(crate) fn add(&mut self, name: impl Into<String>, src: &str) -> usize {
+        // Leave a gap of one byte before each file, so that the end-of-file
+        // position of a file is not the start of the next one, and that the
+        // default span refers to no file at all.
+        let start = self.files.last().map_or(0, |it| it.start + it.src.len()) + 1;
+        self.files.push(SourceFile {
+            name: name.into(),
+            src: src.into(),
+            start,
+        });
+        start
+    }
+
+    /// Returns the file containing the byte at `offset`, if any.
+    pub(crate) fn file(&self, offset: usize) -> Option<&SourceFile> {
+        let idx = self.files.partition_point(|it| it.start <= offset);
+        let file = self.files.get(idx.checked_sub(1)?)?;
+        (offset <= file.start + file.src.len()).then_some(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let mut sources = SourceMap::default();
+        assert_eq!(1, sources.add("a", "var a = 1;\nprint a;"));
+        assert_eq!(21, sources.add("b", "åäö\r\nb"));
+        assert!(sources.file(0).is_none());
+
+        let a = sources.file(16).unwrap();
+        assert_eq!("a", a.name);
+        assert_eq!((2, 5), a.pos(16));
+        assert_eq!((2, 9), a.pos(20));
+        assert_eq!(Some(("print a;", 12)), a.line(2));
+
+        let b = sources.file(21).unwrap();
+        assert_eq!("b", b.name);
+        assert_eq!((1, 3), b.pos(25));
+        assert_eq!((2, 1), b.pos(29));
+        assert_eq!(Some(("åäö", 21)), b.line(1));
+        assert_eq!(None, b.line(3));
+        assert!(sources.file(31).is_none());
+    }
+}
@@ -0,0 +1,611 @@
+mod chunk;
+pub(crate) mod closure;
+mod compiler;
+mod tests;
+
+use std::{collections::HashMap, mem, rc::Rc};
+
+use anyhow::{anyhow, Result};
+use gc::Gc;
+use itertools::Itertools;
+
+pub(crate) use self::{
+    chunk::{Chunk, Function, Op, UpvalueDesc},
+    closure::{Closure, Upvalue},
+    compiler::Compiler,
+};
+use crate::{
+    bail,
+    error::{with_traceback, CallFrame, Code, Diagnostic},
+    interpreter::{
+        binary_op, catch, check_arity, foreign_error, get_index, get_property, new_map, set_index,
+        unary_op, Class, Env, Instance, Interpreter, Object, Thrown,
+    },
+    module::{self, Modules},
+    parser::{Expr, Stmt},
+    report,
+    resolver::Resolver,
+    source::{SourceMap, Span},
+    util::{rc_cell_of, MutCell},
+};
+
+/// The maximum depth of nested calls.
+const MAX_FRAME_COUNT: usize = 1 << 16;
+
+/// The bytecode virtual machine, an alternative backend to the AST-walking
+/// `Interpreter`.
+#[derive(Debug)]
+pub(crate) struct Vm {
+    pub(crate) globals: MutCell<Env>,
+    stack: Vec<Object>,
+    frames: Vec<Frame>,
+    /// The upvalues still pointing to the stack.
+    open_upvalues: Vec<MutCell<Upvalue>>,
+    /// The exception handlers in effect, innermost last.
+    handlers: Vec<Handler>,
+    /// The errors to be raised again after their `finally` clauses.
+    pending: Vec<anyhow::Error>,
+    /// The values thrown and not caught yet.
+    pub(crate) thrown: Thrown,
+    /// The sources evaluated so far, memorized for error reports.
+    pub(crate) sources: SourceMap,
+    pub(crate) modules: Modules,
+}
+
+#[derive(Debug)]
+struct Frame {
+    closure: Closure,
+    ip: usize,
+    /// The index of the slot 0 of this frame in the stack.
+    base: usize,
+    /// The span of the call expression, memorized for error reports.
+    call_site: Span,
+}
+
+/// An exception handler registered by a `try` statement.
+#[derive(Debug)]
+struct Handler {
+    /// The number of frames below the one registering this handler.
+    depth: usize,
+    /// The height of the stack when this handler is registered.
+    height: usize,
+    /// The number of pending errors when this handler is registered.
+    pending: usize,
+    target: usize,
+    finally: bool,
+}
+
+impl Frame {
+    fn chunk(&self) -> &Chunk {
+        &self.closure.fun.chunk
+    }
+
+    /// The source span of the instruction being executed.
+    fn pos(&self) -> Span {
+        self.chunk().pos[self.ip - 1]
+    }
+
+    fn name(&self, idx: u32) -> &str {
+        &self.chunk().names[idx as usize]
+    }
+
+    fn call_frame(&self) -> CallFrame {
+        let fun = &self.closure.fun;
+        CallFrame {
+            name: fun.name.clone().unwrap_or_else(|| "<lambda>".into()),
+            call_site: self.call_site,
+            defn: fun.defn,
+        }
+    }
+}
+
+impl Vm {
+    #[must_use]
+    pub(crate) fn new(globals: &MutCell<Env>) -> Self {
+        Self {
+            globals: Gc::clone(globals),
+            stack: vec![],
+            frames: vec![],
+            open_upvalues: vec![],
+            handlers: vec![],
+            pending: vec![],
+            thrown: Thrown::default(),
+            sources: SourceMap::default(),
+            modules: Modules::default(),
+        }
+    }
+
+    /// Checks the given statements for semantic errors, returning the warnings
+    /// found.
+    pub(crate) fn resolve_stmts(&self, stmts: &[Stmt]) -> Result<Vec<Diagnostic>> {
+        // The resolver is only run for semantic checks here.
+        let (_, res) = Resolver::new(Interpreter::new(&self.globals)).resolve(stmts.to_vec());
+        res
+    }
+
+    /// Checks the given expression for semantic errors, returning the warnings
+    /// found.
+    pub(crate) fn resolve_expr(&self, expr: &Expr) -> Result<Vec<Diagnostic>> {
+        let mut resolver = Resolver::new(Interpreter::new(&self.globals));
+        resolver.resolve_expr(expr.clone());
+        resolver.finish().1
+    }
+
+    /// Runs the given statements, which should have been resolved.
+    pub(crate) fn exec_stmts(&mut self, stmts: &[Stmt]) -> Result<()> {
+        let fun = Compiler::compile_stmts(stmts)?;
+        self.run(fun, &Gc::clone(&self.globals))?;
+        Ok(())
+    }
+
+    /// Evaluates the given expression, which should have been resolved.
+    pub(crate) fn eval(&mut self, expr: &Expr) -> Result<Object> {
+        let fun = Compiler::compile_expr(expr)?;
+        self.run(fun, &Gc::clone(&self.globals))
+    }
+
+    /// Resolves and runs the given statements of a module in a new global
+    /// environment, which is returned.
+    pub(crate) fn exec_module(&mut self, stmts: &[Stmt]) -> Result<MutCell<Env>> {
+        self.resolve_stmts(stmts)?;
+        let fun = Compiler::compile_stmts(stmts)?;
+        let globals = self.modules.new_globals();
+        // The module is run as a new script on top of the importing one.
+        let frames = mem::take(&mut self.frames);
+        let res = self.run(fun, &globals);
+        self.frames = frames;
+        res.map(|_| globals)
+    }
+
+    /// Runs a script `Function` with the given global environment, returning
+    /// its return value.
+    pub(crate) fn run(&mut self, fun: Function, globals: &MutCell<Env>) -> Result<Object> {
+        let closure = Closure::new(Rc::new(fun), vec![], globals);
+        let base = self.stack.len();
+        self.stack.push(Object::BytecodeFn(closure.clone()));
+        let mut frame = Frame {
+            closure,
+            ip: 0,
+            base,
+            call_site: Span::default(),
+        };
+        let handlers = self.handlers.len();
+        let res = (self.run_frame(&mut frame, handlers))
+            .map_err(|e| with_traceback(e, || self.traceback(&frame)));
+        res.inspect_err(|_| {
+            // Unwind everything of this script so that the VM can be reused.
+            self.close_upvalues(base);
+            self.stack.truncate(base);
+            self.frames.clear();
+            self.handlers.truncate(handlers);
+        })
+    }
+
+    /// Returns the calls in progress, where `frame` is the innermost one.
+    fn traceback(&self, frame: &Frame) -> Vec<CallFrame> {
+        // The script itself is at the bottom of the call stack, and is not a
+        // call.
+        (self.frames.iter().skip(1))
+            .chain((!self.frames.is_empty()).then_some(frame))
+            .map(Frame::call_frame)
+            .collect()
+    }
+
+    /// Runs `frame` till it returns, passing the errors raised to the handlers
+    /// registered after the first `handlers` ones.
+    fn run_frame(&mut self, frame: &mut Frame, handlers: usize) -> Result<Object> {
+        loop {
+            match self.exec_frame(frame) {
+                Err(e) if self.handlers.len() > handlers => self.handle(frame, e, handlers)?,
+                res => return res,
+            }
+        }
+    }
+
+    /// Unwinds the stack to the innermost handler catching the error `e`,
+    /// which is raised again if there is none.
+    fn handle(&mut self, frame: &mut Frame, e: anyhow::Error, handlers: usize) -> Result<()> {
+        // The traceback is attached now in case `e` is raised again.
+        let mut e = with_traceback(e, || self.traceback(frame));
+        while self.handlers.len() > handlers {
+            let handler = self.handlers.pop().unwrap();
+            let exception = if handler.finally {
+                self.pending.truncate(handler.pending);
+                self.pending.push(e);
+                Object::from(handler.pending)
+            } else {
+                match catch(e, &mut self.thrown, &self.sources) {
+                    Ok(exception) => exception,
+                    Err(uncaught) => {
+                        e = uncaught;
+                        continue;
+                    }
+                }
+            };
+            if self.frames.len() > handler.depth {
+                // The handler is in a caller, so the callees are discarded.
+                *frame = self.frames.drain(handler.depth..).next().unwrap();
+            }
+            self.close_upvalues(handler.height);
+            self.stack.truncate(handler.height);
+            self.stack.push(exception);
+            frame.ip = handler.target;
+            return Ok(());
+        }
+        Err(e)
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().unwrap()
+    }
+
+    fn peek(&self) -> &Object {
+        self.stack.last().unwrap()
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn exec_frame(&mut self, frame: &mut Frame) -> Result<Object> {
+        loop {
+            let op = frame.chunk().code[frame.ip];
+            frame.ip += 1;
+            match op {
+                Op::Constant(idx) => {
+                    let obj = frame.chunk().consts[idx as usize].clone();
+                    self.stack.push(obj);
+                }
+                Op::Nil => self.stack.push(Object::Nil),
+                Op::True => self.stack.push(Object::Bool(true)),
+                Op::False => self.stack.push(Object::Bool(false)),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Dup(count) => {
+                    let start = self.stack.len() - count as usize;
+                    self.stack.extend_from_within(start..);
+                }
+                Op::GetLocal(slot) => {
+                    let obj = self.stack[frame.base + slot as usize].clone();
+                    self.stack.push(obj);
+                }
+                Op::SetLocal(slot) => {
+                    self.stack[frame.base + slot as usize] = self.peek().clone();
+                }
+                Op::GetGlobal(idx) => {
+                    let name = frame.name(idx);
+                    let obj = Env::lookup(&frame.closure.globals, name).ok_or_else(|| {
+                        anyhow!(report!(
+                            Code::UndefinedVariable,
+                            frame.pos(),
+                            "while evaluating a Variable expression",
+                            "identifier `{name}` is undefined",
+                        ))
+                    })?;
+                    self.stack.push(obj);
+                }
+                Op::SetGlobal(idx) => {
+                    let name = frame.name(idx);
+                    if Env::lookup(&frame.closure.globals, name).is_none() {
+                        bail!(
+                            Code::UndefinedVariable,
+                            frame.pos(),
+                            "while evaluating an Assignment expression",
+                            "identifier `{}` is undefined",
+                            name,
+                        );
+                    }
+                    let val = self.peek().clone();
+                    frame.closure.globals.borrow_mut().insert_val(name, val);
+                }
+                Op::DefineGlobal(idx) => {
+                    let val = self.pop();
+                    (frame.closure.globals.borrow_mut()).insert_val(frame.name(idx), val);
+                }
+                Op::GetUpvalue(idx) => {
+                    let obj = match &*frame.closure.upvalues[idx as usize].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(obj) => obj.clone(),
+                    };
+                    self.stack.push(obj);
+                }
+                Op::SetUpvalue(idx) => {
+                    let val = self.peek().clone();
+                    match &mut *frame.closure.upvalues[idx as usize].borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = val,
+                        Upvalue::Closed(obj) => *obj = val,
+                    }
+                }
+                Op::GetProperty(idx) => {
+                    let obj = self.pop();
+                    let prop = get_property(frame.pos(), &obj, frame.name(idx))?;
+                    self.stack.push(prop);
+                }
+                Op::SetProperty(idx) => {
+                    let to = self.pop();
+                    let obj = self.pop();
+                    if let Object::Instance(ref i) = obj {
+                        i.set(frame.name(idx), to.clone());
+                        self.stack.push(to);
+                    } else {
+                        bail!(
+                            Code::TypeMismatch,
+                            frame.pos(),
+                            "while evaluating a Set expression",
+                            "the object `{}` cannot have properties",
+                            obj,
+                        );
+                    }
+                }
+                Op::GetSuper(idx) => {
+                    let (sup, this) = (self.pop(), self.pop());
+                    match (&this, &sup) {
+                        (Object::Instance(this), Object::Class(sup)) => {
+                            let name = frame.name(idx);
+                            let method = sup.method(name).ok_or_else(|| {
+                                anyhow!(report!(
+                                    Code::UndefinedProperty,
+                                    frame.pos(),
+                                    "while evaluating a superclass method",
+                                    "property `{name}` undefined for the given object",
+                                ))
+                            })?;
+                            self.stack.push(method.bind(this.clone()));
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                Op::GetIndex => {
+                    let (idx, obj) = (self.pop(), self.pop());
+                    self.stack.push(get_index(frame.pos(), &obj, &idx)?);
+                }
+                Op::SetIndex => {
+                    let (to, idx, obj) = (self.pop(), self.pop(), self.pop());
+                    set_index(frame.pos(), &obj, &idx, to.clone())?;
+                    self.stack.push(to);
+                }
+                Op::Interpolate(count) => {
+                    let parts = self.stack.split_off(self.stack.len() - count as usize);
+                    let res = parts.iter().map(Object::stringify).collect();
+                    self.stack.push(Object::Str(res));
+                }
+                Op::List(count) => {
+                    let elems = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack.push(Object::List(elems.into()));
+                }
+                Op::Map(keys) => {
+                    let keys = &frame.chunk().map_keys[keys as usize];
+                    let flat = self.stack.split_off(self.stack.len() - 2 * keys.len());
+                    let entries = (keys.iter().zip(flat.into_iter().tuples()))
+                        .map(|(&span, (k, v))| (span, k, v));
+                    self.stack.push(new_map(entries)?);
+                }
+                Op::Binary(ty) => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    self.stack.push(binary_op(ty, frame.pos(), &lhs, &rhs)?);
+                }
+                Op::Unary(ty) => {
+                    let rhs = self.pop();
+                    self.stack.push(unary_op(ty, frame.pos(), &rhs)?);
+                }
+                Op::Print => println!("{}", self.pop()),
+                Op::Jump(target) => frame.ip = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if !self.peek().to_bool() {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::JumpIfNil(target) => {
+                    if let Object::Nil = self.peek() {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::Call(argc) => {
+                    if let Some(callee) = self.call(argc as usize, frame.pos())? {
+                        self.frames.push(mem::replace(frame, callee));
+                    }
+                }
+                Op::Closure(idx) => {
+                    let fun = Rc::clone(&frame.chunk().functions[idx as usize]);
+                    let upvalues = (fun.upvalues.iter())
+                        .map(|desc| {
+                            let idx = desc.index as usize;
+                            if desc.is_local {
+                                self.capture_upvalue(frame.base + idx)
+                            } else {
+                                Gc::clone(&frame.closure.upvalues[idx])
+                            }
+                        })
+                        .collect();
+                    let closure = Closure::new(fun, upvalues, &frame.closure.globals);
+                    self.stack.push(Object::BytecodeFn(closure));
+                }
+                Op::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                Op::Return => {
+                    let res = self.pop();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    match self.frames.pop() {
+                        Some(caller) => {
+                            *frame = caller;
+                            self.stack.push(res);
+                        }
+                        None => return Ok(res),
+                    }
+                }
+                Op::Class { name, has_super } => {
+                    let name = frame.name(name);
+                    let superclass = if has_super {
+                        match self.pop() {
+                            Object::Class(ref sup) => Some(sup.clone()),
+                            sup => bail!(
+                                Code::TypeMismatch,
+                                frame.pos(),
+                                "while evaluating a Class declaration",
+                                "class `{}` cannot inherit from non-class value `{}`",
+                                name,
+                                sup,
+                            ),
+                        }
+                    } else {
+                        None
+                    };
+                    let class = Class::new(name, superclass, HashMap::new());
+                    self.stack.push(Object::Class(class));
+                }
+                Op::Method(idx) => {
+                    let method = self.pop();
+                    if let Object::Class(class) = self.peek() {
+                        let name = frame.name(idx).to_owned();
+                        class.methods.borrow_mut().insert(name, method);
+                    } else {
+                        unreachable!()
+                    }
+                }
+                Op::Import(idx) => {
+                    let path = frame.name(idx).to_owned();
+                    let module = module::import(self, &path, frame.pos())?;
+                    self.stack.push(Object::Module(module));
+                }
+                Op::PushHandler { target, finally } => self.handlers.push(Handler {
+                    depth: self.frames.len(),
+                    height: self.stack.len(),
+                    pending: self.pending.len(),
+                    target: target as usize,
+                    finally,
+                }),
+                Op::PopHandler => {
+                    self.handlers.pop();
+                }
+                Op::Throw => {
+                    let obj = self.pop();
+                    return Err(self.thrown.throw(obj, frame.pos()));
+                }
+                Op::Rethrow => {
+                    let Object::Int(idx) = self.pop() else {
+                        unreachable!()
+                    };
+                    let idx = usize::try_from(idx).unwrap();
+                    return Err(self.pending.drain(idx..).next().unwrap());
+                }
+            }
+        }
+    }
+
+    /// Calls the callee below the top `arg_count` objects on the stack.
+    ///
+    /// If the callee is a compiled function, the new call frame is returned
+    /// to be run by the caller. Otherwise, the call is completed with the
+    /// result pushed onto the stack.
+    fn call(&mut self, arg_count: usize, span: Span) -> Result<Option<Frame>> {
+        let base = self.stack.len() - arg_count - 1;
+        let callee = self.stack[base].clone();
+        match callee {
+            Object::BytecodeFn(ref closure) => {
+                self.call_closure(closure.clone(), base, span).map(Some)
+            }
+            Object::ForeignFn(ref foreign) => {
+                let ctx = "while evaluating a foreign function Call expression";
+                if !foreign.arity.accepts(arg_count) {
+                    bail!(
+                        Code::ArityMismatch,
+                        span,
+                        ctx,
+                        "unexpected number of parameters (expected {}, got {})",
+                        foreign.arity,
+                        arg_count,
+                    );
+                }
+                let args = self.stack.split_off(base + 1);
+                self.stack.pop();
+                let res = (foreign.apply(args)).map_err(|e| foreign_error(e, span, ctx))?;
+                self.stack.push(res);
+                Ok(None)
+            }
+            Object::Class(ref class) => {
+                let instance = Instance::from(class.clone());
+                if let Some(Object::BytecodeFn(ref init)) = instance.class.method("init") {
+                    let init = init.clone().bind(instance);
+                    return self.call_closure(init, base, span).map(Some);
+                }
+                check_arity(
+                    span,
+                    "while evaluating a new Class expression",
+                    0,
+                    arg_count,
+                )?;
+                self.stack[base] = Object::Instance(instance);
+                Ok(None)
+            }
+            obj => bail!(
+                Code::TypeMismatch,
+                span,
+                "while evaluating a function Call expression",
+                "the object `{}` is not callable",
+                obj,
+            ),
+        }
+    }
+
+    fn call_closure(&mut self, closure: Closure, base: usize, span: Span) -> Result<Frame> {
+        let ctx = "while evaluating a function Call expression";
+        check_arity(span, ctx, closure.fun.arity, self.stack.len() - base - 1)?;
+        if self.frames.len() >= MAX_FRAME_COUNT {
+            bail!(
+                Code::StackOverflow,
+                span,
+                ctx,
+                "maximum call depth ({}) exceeded",
+                MAX_FRAME_COUNT
+            );
+        }
+        if let Some(this) = &closure.this {
+            self.stack[base] = Object::Instance(*this.clone());
+        }
+        Ok(Frame {
+            closure,
+            ip: 0,
+            base,
+            call_site: span,
+        })
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> MutCell<Upvalue> {
+        let found = (self.open_upvalues.iter())
+            .find(|it| matches!(*it.borrow(), Upvalue::Open(s) if s == slot));
+        if let Some(found) = found {
+            return Gc::clone(found);
+        }
+        let res = rc_cell_of(Upvalue::Open(slot));
+        self.open_upvalues.push(Gc::clone(&res));
+        res
+    }
+
+    /// Moves all variables captured from the stack slots starting from `from`
+    /// to the heap.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|it| {
+            let slot = match *it.borrow() {
+                Upvalue::Open(slot) if slot >= from => slot,
+                _ => return true,
+            };
+            *it.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+            false
+        });
+    }
+}
+
+impl Default for Vm {
+    /// Creates a new VM with the same native functions defined in its global
+    /// environment as in the `Interpreter`'s.
+    fn default() -> Self {
+        let interpreter = Interpreter::default();
+        Self {
+            modules: interpreter.modules,
+            ..Self::new(&interpreter.globals)
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::rc::Rc;
+
+use crate::{interpreter::Object, lexer::TokenType, source::Span};
+
+/// A single bytecode instruction.
+///
+/// Jump targets are absolute indices into `Chunk::code`, slots are relative to
+/// the base of the current call frame, and other operands are indices into
+/// the pools of the current `Chunk`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    /// Pushes `consts[i]`.
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    /// Pushes copies of the given number of objects on the stack top, keeping
+    /// their order.
+    Dup(u32),
+    GetLocal(u32),
+    SetLocal(u32),
+    /// Pushes the value of the global variable `names[i]`.
+    GetGlobal(u32),
+    SetGlobal(u32),
+    /// Pops the stack top into the global variable `names[i]`.
+    DefineGlobal(u32),
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+    GetProperty(u32),
+    SetProperty(u32),
+    /// Pops a superclass and an instance, pushing the method `names[i]` of
+    /// the former bound to the latter.
+    GetSuper(u32),
+    /// Pops an index and an object, pushing the element of the latter.
+    GetIndex,
+    /// Pops a value, an index and an object, setting the element of the
+    /// object before pushing the value back.
+    SetIndex,
+    /// Pops the given number of objects, pushing the concatenation of their
+    /// string representations.
+    Interpolate(u32),
+    /// Pops the given number of objects into a new list.
+    List(u32),
+    /// Pops the key-value pairs of the Map literal whose keys are located at
+    /// `map_keys[i]` into a new map.
+    Map(u32),
+    Binary(TokenType),
+    Unary(TokenType),
+    Print,
+    Jump(u32),
+    /// Jumps if the stack top is falsey, without popping it.
+    JumpIfFalse(u32),
+    /// Jumps if the stack top is `nil`, without popping it.
+    JumpIfNil(u32),
+    /// Calls the callee below the given number of arguments on the stack.
+    Call(u8),
+    /// Pushes a new closure of `functions[i]`, capturing its upvalues.
+    Closure(u32),
+    /// Moves the stack top to the heap if it is captured, then pops it.
+    CloseUpvalue,
+    Return,
+    /// Pushes a new class `names[name]`, popping its superclass first if
+    /// `has_super` is set.
+    Class {
+        name: u32,
+        has_super: bool,
+    },
+    /// Pops a closure into the methods of the class below it as `names[i]`.
+    Method(u32),
+    /// Pushes the module at the path `names[i]`, running it first if it has
+    /// not been loaded yet.
+    Import(u32),
+    /// Registers an exception handler at `target` until the matching
+    /// `PopHandler`.
+    ///
+    /// The handler of a `catch` clause starts with the exception pushed, and
+    /// that of a `finally` clause, which catches every error, starts with the
+    /// index of the pending error pushed instead.
+    PushHandler {
+        target: u32,
+        finally: bool,
+    },
+    PopHandler,
+    /// Pops a value and throws it.
+    Throw,
+    /// Pops the index of a pending error and raises it again.
+    Rethrow,
+}
+
+/// A sequence of bytecode instructions, together with the pools they refer to.
+#[derive(Debug, Default)]
+pub(crate) struct Chunk {
+    pub(crate) code: Vec<Op>,
+    /// The source span of each instruction in `code`, memorized for error
+    /// reports.
+    pub(crate) pos: Vec<Span>,
+    /// The constant pool, which contains literal values only.
+    pub(crate) consts: Vec<Object>,
+    pub(crate) names: Vec<String>,
+    pub(crate) functions: Vec<Rc<Function>>,
+    /// The spans of the keys of each Map literal, memorized for error
+    /// reports.
+    pub(crate) map_keys: Vec<Vec<Span>>,
+}
+
+impl Chunk {
+    pub(crate) fn push(&mut self, op: Op, pos: Span) -> usize {
+        self.code.push(op);
+        self.pos.push(pos);
+        self.code.len() - 1
+    }
+
+    pub(crate) fn add_const(&mut self, obj: Object) -> u32 {
+        self.consts.push(obj);
+        pool_idx(self.consts.len() - 1)
+    }
+
+    pub(crate) fn add_name(&mut self, name: &str) -> u32 {
+        let idx = self
+            .names
+            .iter()
+            .position(|it| it == name)
+            .unwrap_or_else(|| {
+                self.names.push(name.into());
+                self.names.len() - 1
+            });
+        pool_idx(idx)
+    }
+
+    pub(crate) fn add_map_keys(&mut self, keys: Vec<Span>) -> u32 {
+        self.map_keys.push(keys);
+        pool_idx(self.map_keys.len() - 1)
+    }
+
+    pub(crate) fn add_function(&mut self, fun: Function) -> u32 {
+        self.functions.push(Rc::new(fun));
+        pool_idx(self.functions.len() - 1)
+    }
+}
+
+fn pool_idx(idx: usize) -> u32 {
+    idx.try_into().expect("Internal Error: pool index overflow")
+}
+
+/// A compiled function prototype.
+#[derive(Debug, Default)]
+pub(crate) struct Function {
+    pub(crate) name: Option<String>,
+    /// The span of the name of this function, or of the `fun` keyword for
+    /// lambdas, memorized for error reports.
+    pub(crate) defn: Span,
+    pub(crate) arity: usize,
+    pub(crate) chunk: Chunk,
+    /// Where to find the upvalues to capture when creating a closure of this
+    /// function.
+    pub(crate) upvalues: Vec<UpvalueDesc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UpvalueDesc {
+    /// If set, the upvalue captures a local of the enclosing function at slot
+    /// `index`, otherwise it refers to the upvalue `index` of the enclosing
+    /// function.
+    pub(crate) is_local: bool,
+    pub(crate) index: u32,
+}
@@ -0,0 +1,73 @@
+use std::{
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use gc::{Finalize, Gc, Trace};
+use uuid::Uuid;
+
+use super::Function;
+use crate::{
+    interpreter::{Env, Instance, Object},
+    util::MutCell,
+};
+
+/// A compiled function, together with the upvalues it has captured.
+#[derive(Debug, Clone, Trace, Finalize)]
+pub struct Closure {
+    #[unsafe_ignore_trace]
+    pub(crate) uid: Uuid,
+    // A `Function` has no `Gc` pointers inside, since its constant pool only contains literals.
+    #[unsafe_ignore_trace]
+    pub(crate) fun: Rc<Function>,
+    pub(crate) upvalues: Vec<MutCell<Upvalue>>,
+    /// The global environment of the module where this closure is defined.
+    pub(crate) globals: MutCell<Env>,
+    /// The receiver of this closure if it is a bound method.
+    pub(crate) this: Option<Box<Instance>>,
+}
+
+impl Closure {
+    pub(crate) fn new(
+        fun: Rc<Function>,
+        upvalues: Vec<MutCell<Upvalue>>,
+        globals: &MutCell<Env>,
+    ) -> Self {
+        Self {
+            uid: Uuid::new_v4(),
+            fun,
+            upvalues,
+            globals: Gc::clone(globals),
+            this: None,
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn bind(mut self, instance: Instance) -> Self {
+        self.this = Some(Box::new(instance));
+        self
+    }
+}
+
+impl Hash for Closure {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.uid.hash(state);
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        self.uid == other.uid
+    }
+}
+
+impl Eq for Closure {}
+
+/// A variable captured by a closure.
+#[derive(Debug, Clone, Trace, Finalize)]
+pub(crate) enum Upvalue {
+    /// The variable still lives on the stack at the given index.
+    Open(usize),
+    /// The variable has been moved to the heap.
+    Closed(Object),
+}
@@ -0,0 +1,810 @@
+use anyhow::Result;
+
+use super::{Function, Op, UpvalueDesc};
+use crate::{
+    bail,
+    error::Code,
+    lexer::{Token, TokenType as Tk},
+    parser::{Expr, Lit, Stmt},
+    source::Span,
+};
+
+const MAX_LOCAL_COUNT: usize = u16::MAX as usize;
+
+/// The bytecode compiler, turning resolved ASTs into `Function`s.
+///
+/// The source is assumed to have passed the `Resolver` already, so semantic
+/// errors are not checked for once again here.
+#[derive(Debug)]
+pub(crate) struct Compiler {
+    /// The stack of functions being compiled, the innermost one being the
+    /// last.
+    states: Vec<FunState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Debug)]
+struct FunState {
+    fun: Function,
+    kind: FunKind,
+    locals: Vec<Local>,
+    depth: usize,
+    loops: Vec<Loop>,
+    tries: Vec<Try>,
+    /// The source span of the instructions being emitted.
+    pos: Span,
+}
+
+#[derive(Debug)]
+struct Local {
+    name: String,
+    depth: usize,
+    captured: bool,
+}
+
+#[derive(Debug)]
+struct Loop {
+    label: Option<String>,
+    depth: usize,
+    /// The `Jump`s to be patched to the end of the loop.
+    breaks: Vec<usize>,
+    /// The `Jump`s to be patched to the end of the current iteration.
+    continues: Vec<usize>,
+    /// The number of `try` statements around the loop.
+    tries: usize,
+}
+
+/// A `try` statement being compiled.
+#[derive(Debug)]
+struct Try {
+    /// The number of handlers registered by this statement still in effect.
+    handlers: usize,
+    /// The `finally` clause to be run before jumping out of this statement.
+    finally: Option<Vec<Stmt>>,
+}
+
+/// Where to find a variable at runtime.
+enum Slot {
+    Local(u32),
+    Upvalue(u32),
+    Global(u32),
+}
+
+impl FunState {
+    fn new(kind: FunKind, name: Option<&str>, arity: usize) -> Self {
+        // The slot 0 of each call frame is occupied by the callee itself, or by `this`
+        // in methods.
+        let slot0 = match kind {
+            FunKind::Method | FunKind::Initializer => "this",
+            FunKind::Script | FunKind::Function => "",
+        };
+        Self {
+            fun: Function {
+                name: name.map(str::to_owned),
+                arity,
+                ..Function::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: slot0.into(),
+                depth: 0,
+                captured: false,
+            }],
+            depth: 0,
+            loops: vec![],
+            tries: vec![],
+            pos: Span::default(),
+        }
+    }
+}
+
+impl Compiler {
+    /// Compiles a program into a script `Function` returning `nil`.
+    pub(crate) fn compile_stmts(stmts: &[Stmt]) -> Result<Function> {
+        let mut compiler = Self::new();
+        stmts.iter().try_for_each(|it| compiler.stmt(it))?;
+        compiler.emit(Op::Nil);
+        compiler.emit(Op::Return);
+        Ok(compiler.finish())
+    }
+
+    /// Compiles an expression into a script `Function` returning its value.
+    pub(crate) fn compile_expr(expr: &Expr) -> Result<Function> {
+        let mut compiler = Self::new();
+        compiler.expr(expr)?;
+        compiler.emit(Op::Return);
+        Ok(compiler.finish())
+    }
+
+    fn new() -> Self {
+        Self {
+            states: vec![FunState::new(FunKind::Script, None, 0)],
+        }
+    }
+
+    fn finish(mut self) -> Function {
+        self.states.pop().unwrap().fun
+    }
+
+    fn state(&mut self) -> &mut FunState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn set_pos(&mut self, token: &Token) {
+        self.state().pos = token.span;
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let state = self.state();
+        state.fun.chunk.push(op, state.pos)
+    }
+
+    fn next_idx(&mut self) -> u32 {
+        self.state().fun.chunk.code.len().try_into().unwrap()
+    }
+
+    /// Points the jump at `idx` to the next instruction to be emitted.
+    fn patch(&mut self, idx: usize) {
+        let target = self.next_idx();
+        match &mut self.state().fun.chunk.code[idx] {
+            Op::Jump(it)
+            | Op::JumpIfFalse(it)
+            | Op::JumpIfNil(it)
+            | Op::PushHandler { target: it, .. } => *it = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        self.state().fun.chunk.add_name(name)
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.depth -= 1;
+        let depth = state.depth;
+        while let Some(local) = self.state().locals.pop_if(|it| it.depth > depth) {
+            self.emit(if local.captured {
+                Op::CloseUpvalue
+            } else {
+                Op::Pop
+            });
+        }
+    }
+
+    /// Pops the locals deeper than `depth` off the stack without forgetting
+    /// them, which is required before jumping out of their scope.
+    fn discard_locals(&mut self, depth: usize) {
+        let ops = (self.state().locals.iter().rev())
+            .take_while(|it| it.depth > depth)
+            .map(|it| {
+                if it.captured {
+                    Op::CloseUpvalue
+                } else {
+                    Op::Pop
+                }
+            })
+            .collect::<Vec<_>>();
+        for op in ops {
+            self.emit(op);
+        }
+    }
+
+    fn add_local(&mut self, name: &Token) -> Result<()> {
+        let state = self.state();
+        if state.locals.len() > MAX_LOCAL_COUNT {
+            bail!(
+                Code::TooManyLocals,
+                name.span,
+                "while compiling a Variable declaration",
+                "cannot have more than {} local variables in a function",
+                MAX_LOCAL_COUNT,
+            );
+        }
+        let depth = state.depth;
+        state.locals.push(Local {
+            name: name.lexeme.clone(),
+            depth,
+            captured: false,
+        });
+        Ok(())
+    }
+
+    /// Adds a local for a value that cannot be referred to by name, which is
+    /// required for the value to stay on the stack while declaring other
+    /// locals.
+    fn add_hidden_local(&mut self, token: &Token) -> Result<()> {
+        self.add_local(&Token {
+            lexeme: String::new(),
+            ..token.clone()
+        })
+    }
+
+    fn resolve_local(&self, level: usize, name: &str) -> Option<u32> {
+        (self.states[level].locals.iter())
+            .rposition(|it| it.name == name)
+            .map(|it| it.try_into().unwrap())
+    }
+
+    fn resolve_upvalue(&mut self, level: usize, name: &str) -> Option<u32> {
+        let outer = level.checked_sub(1)?;
+        let desc = if let Some(index) = self.resolve_local(outer, name) {
+            self.states[outer].locals[index as usize].captured = true;
+            UpvalueDesc {
+                is_local: true,
+                index,
+            }
+        } else {
+            let index = self.resolve_upvalue(outer, name)?;
+            UpvalueDesc {
+                is_local: false,
+                index,
+            }
+        };
+        let upvalues = &mut self.states[level].fun.upvalues;
+        let idx = (upvalues.iter())
+            .position(|it| it.is_local == desc.is_local && it.index == desc.index)
+            .unwrap_or_else(|| {
+                upvalues.push(desc);
+                upvalues.len() - 1
+            });
+        Some(idx.try_into().unwrap())
+    }
+
+    fn resolve(&mut self, name: &str) -> Slot {
+        let level = self.states.len() - 1;
+        if let Some(idx) = self.resolve_local(level, name) {
+            Slot::Local(idx)
+        } else if let Some(idx) = self.resolve_upvalue(level, name) {
+            Slot::Upvalue(idx)
+        } else {
+            Slot::Global(self.name(name))
+        }
+    }
+
+    fn get_var(&mut self, name: &str) {
+        let op = match self.resolve(name) {
+            Slot::Local(idx) => Op::GetLocal(idx),
+            Slot::Upvalue(idx) => Op::GetUpvalue(idx),
+            Slot::Global(idx) => Op::GetGlobal(idx),
+        };
+        self.emit(op);
+    }
+
+    fn set_var(&mut self, name: &str) {
+        let op = match self.resolve(name) {
+            Slot::Local(idx) => Op::SetLocal(idx),
+            Slot::Upvalue(idx) => Op::SetUpvalue(idx),
+            Slot::Global(idx) => Op::SetGlobal(idx),
+        };
+        self.emit(op);
+    }
+
+    /// Binds the value on the stack top to the variable `name` just declared.
+    fn define_var(&mut self, name: &Token) -> Result<()> {
+        if self.state().depth > 0 {
+            // The value is already in its slot.
+            return self.add_local(name);
+        }
+        let idx = self.name(&name.lexeme);
+        self.emit(Op::DefineGlobal(idx));
+        Ok(())
+    }
+
+    fn function(
+        &mut self,
+        kind: FunKind,
+        name: Option<&str>,
+        params: &[Token],
+        body: &[Stmt],
+    ) -> Result<()> {
+        // The current position is where the function is defined.
+        let pos = self.state().pos;
+        self.states.push(FunState::new(kind, name, params.len()));
+        self.state().pos = pos;
+        self.state().fun.defn = pos;
+        self.begin_scope();
+        params.iter().try_for_each(|it| self.add_local(it))?;
+        body.iter().try_for_each(|it| self.stmt(it))?;
+        self.emit_implicit_return();
+        let fun = self.states.pop().unwrap().fun;
+        let idx = self.state().fun.chunk.add_function(fun);
+        self.emit(Op::Closure(idx));
+        Ok(())
+    }
+
+    /// Emits the code for jumping out of the `try` statements around, except
+    /// for the outermost `n` ones, which unregisters their handlers and runs
+    /// their `finally` clauses.
+    fn exit_tries(&mut self, n: usize) -> Result<()> {
+        let mut exited = vec![];
+        while self.state().tries.len() > n {
+            // A `finally` clause is not in the `try` statement it belongs to.
+            let it = self.state().tries.pop().unwrap();
+            for _ in 0..it.handlers {
+                self.emit(Op::PopHandler);
+            }
+            if let Some(finally) = &it.finally {
+                self.block(finally)?;
+            }
+            exited.push(it);
+        }
+        self.state().tries.extend(exited.into_iter().rev());
+        Ok(())
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<()> {
+        self.begin_scope();
+        stmts.iter().try_for_each(|it| self.stmt(it))?;
+        self.end_scope();
+        Ok(())
+    }
+
+    fn emit_implicit_return(&mut self) {
+        if self.state().kind == FunKind::Initializer {
+            // Special case: for initializers, we implicitly return `this`.
+            self.emit(Op::GetLocal(0));
+        } else {
+            self.emit(Op::Nil);
+        }
+        self.emit(Op::Return);
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Block(stmts) => self.block(stmts)?,
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                self.set_pos(name);
+                if let Some(sup) = superclass {
+                    self.expr(sup)?;
+                }
+                let name_idx = self.name(&name.lexeme);
+                self.set_pos(name);
+                self.emit(Op::Class {
+                    name: name_idx,
+                    has_super: superclass.is_some(),
+                });
+                self.define_var(name)?;
+                if let Some(sup) = superclass {
+                    // Only subclasses can have `super`, which is captured by the methods below.
+                    self.begin_scope();
+                    self.expr(sup)?;
+                    self.add_local(&Token {
+                        lexeme: "super".into(),
+                        ..name.clone()
+                    })?;
+                }
+                self.get_var(&name.lexeme);
+                for method in methods {
+                    if let Stmt::Fun { name, params, body } = method {
+                        let kind = if name.lexeme == "init" {
+                            FunKind::Initializer
+                        } else {
+                            FunKind::Method
+                        };
+                        self.set_pos(name);
+                        self.function(kind, Some(&name.lexeme), params, body)?;
+                        let name_idx = self.name(&name.lexeme);
+                        self.emit(Op::Method(name_idx));
+                    } else {
+                        unreachable!()
+                    }
+                }
+                self.emit(Op::Pop);
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+            }
+            Stmt::Expression(expr) => {
+                self.expr(expr)?;
+                self.emit(Op::Pop);
+            }
+            Stmt::Fun { name, params, body } => {
+                self.set_pos(name);
+                if self.state().depth > 0 {
+                    // We define a function's name eagerly to enable recursion.
+                    self.add_local(name)?;
+                    self.function(FunKind::Function, Some(&name.lexeme), params, body)?;
+                } else {
+                    self.function(FunKind::Function, Some(&name.lexeme), params, body)?;
+                    self.define_var(name)?;
+                }
+            }
+            Stmt::If {
+                cond,
+                then_stmt,
+                else_stmt,
+                ..
+            } => {
+                self.expr(cond)?;
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.stmt(then_stmt)?;
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_else);
+                self.emit(Op::Pop);
+                if let Some(else_stmt) = else_stmt {
+                    self.stmt(else_stmt)?;
+                }
+                self.patch(to_end);
+            }
+            Stmt::Jump { kw, label } => {
+                self.set_pos(kw);
+                // The resolver has made sure that the target loop exists.
+                let target = {
+                    let loops = &self.state().loops;
+                    match label {
+                        Some(label) => (loops.iter())
+                            .rposition(|it| it.label.as_ref() == Some(&label.lexeme))
+                            .unwrap(),
+                        None => loops.len() - 1,
+                    }
+                };
+                let (depth, tries) = {
+                    let lp = &self.state().loops[target];
+                    (lp.depth, lp.tries)
+                };
+                self.exit_tries(tries)?;
+                self.discard_locals(depth);
+                let idx = self.emit(Op::Jump(0));
+                let lp = &mut self.state().loops[target];
+                match kw.ty {
+                    Tk::Break => lp.breaks.push(idx),
+                    Tk::Continue => lp.continues.push(idx),
+                    _ => unreachable!(),
+                }
+            }
+            Stmt::Import { path, alias, names } => {
+                let path_idx = self.name(&path.lexeme);
+                for name in names {
+                    self.set_pos(path);
+                    self.emit(Op::Import(path_idx));
+                    self.set_pos(name);
+                    let idx = self.name(&name.lexeme);
+                    self.emit(Op::GetProperty(idx));
+                    self.define_var(name)?;
+                }
+                if let Some(alias) = alias {
+                    self.set_pos(path);
+                    self.emit(Op::Import(path_idx));
+                    self.set_pos(alias);
+                    self.define_var(alias)?;
+                }
+            }
+            Stmt::Print { val, .. } => {
+                self.expr(val)?;
+                self.emit(Op::Print);
+            }
+            Stmt::Return { kw, val } => {
+                self.set_pos(kw);
+                if let Some(val) = val {
+                    self.expr(val)?;
+                    // The value is kept on the stack while running the
+                    // `finally` clauses.
+                    self.add_hidden_local(kw)?;
+                    self.exit_tries(0)?;
+                    self.state().locals.pop();
+                    self.set_pos(kw);
+                    self.emit(Op::Return);
+                } else {
+                    self.exit_tries(0)?;
+                    self.set_pos(kw);
+                    self.emit_implicit_return();
+                }
+            }
+            Stmt::Throw { kw, val } => {
+                self.expr(val)?;
+                self.set_pos(kw);
+                self.emit(Op::Throw);
+            }
+            Stmt::Try {
+                kw,
+                body,
+                catch,
+                finally,
+            } => {
+                self.set_pos(kw);
+                let to_finally = finally.is_some().then(|| {
+                    self.emit(Op::PushHandler {
+                        target: 0,
+                        finally: true,
+                    })
+                });
+                self.state().tries.push(Try {
+                    handlers: usize::from(finally.is_some()),
+                    finally: finally.clone(),
+                });
+                if let Some((name, handler)) = catch {
+                    self.set_pos(kw);
+                    let to_catch = self.emit(Op::PushHandler {
+                        target: 0,
+                        finally: false,
+                    });
+                    self.state().tries.last_mut().unwrap().handlers += 1;
+                    self.block(body)?;
+                    self.emit(Op::PopHandler);
+                    self.state().tries.last_mut().unwrap().handlers -= 1;
+                    let to_end = self.emit(Op::Jump(0));
+                    self.patch(to_catch);
+                    // The exception has been pushed by the VM.
+                    self.begin_scope();
+                    self.add_local(name)?;
+                    handler.iter().try_for_each(|it| self.stmt(it))?;
+                    self.end_scope();
+                    self.patch(to_end);
+                } else {
+                    self.block(body)?;
+                }
+                self.state().tries.pop();
+                if let (Some(to_finally), Some(finally)) = (to_finally, finally) {
+                    self.emit(Op::PopHandler);
+                    self.block(finally)?;
+                    let to_end = self.emit(Op::Jump(0));
+                    self.patch(to_finally);
+                    // The index of the pending error has been pushed by the VM,
+                    // which is to be popped by `Rethrow`.
+                    self.begin_scope();
+                    self.add_hidden_local(kw)?;
+                    self.block(finally)?;
+                    self.set_pos(kw);
+                    self.emit(Op::Rethrow);
+                    self.state().locals.pop();
+                    self.state().depth -= 1;
+                    self.patch(to_end);
+                }
+            }
+            Stmt::Var { name, init } => {
+                if let Some(init) = init {
+                    self.expr(init)?;
+                } else {
+                    self.emit(Op::Nil);
+                }
+                self.set_pos(name);
+                self.define_var(name)?;
+            }
+            Stmt::While {
+                label,
+                cond,
+                body,
+                incr,
+                ..
+            } => {
+                let start = self.next_idx() as usize;
+                self.expr(cond)?;
+                let to_end = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                let depth = self.state().depth;
+                let tries = self.state().tries.len();
+                self.state().loops.push(Loop {
+                    label: label.as_ref().map(|it| it.lexeme.clone()),
+                    depth,
+                    breaks: vec![],
+                    continues: vec![],
+                    tries,
+                });
+                self.stmt(body)?;
+                let lp = self.state().loops.pop().unwrap();
+                lp.continues.into_iter().for_each(|it| self.patch(it));
+                if let Some(incr) = incr {
+                    self.expr(incr)?;
+                    self.emit(Op::Pop);
+                }
+                self.emit(Op::Jump(start.try_into().unwrap()));
+                self.patch(to_end);
+                self.emit(Op::Pop);
+                lp.breaks.into_iter().for_each(|it| self.patch(it));
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn expr(&mut self, expr: &Expr) -> Result<()> {
+        match expr {
+            Expr::Assign { name, val } => {
+                self.expr(val)?;
+                self.set_pos(name);
+                self.set_var(&name.lexeme);
+            }
+            Expr::Binary { lhs, op, rhs } => {
+                self.expr(lhs)?;
+                self.expr(rhs)?;
+                self.set_pos(op);
+                self.emit(Op::Binary(op.ty));
+            }
+            Expr::Call { .. } | Expr::Get { .. } | Expr::Index { .. } => {
+                let mut nil_jumps = vec![];
+                self.chain(expr, &mut nil_jumps)?;
+                for it in nil_jumps {
+                    self.patch(it);
+                }
+            }
+            Expr::Grouping(inner) => self.expr(inner)?,
+            Expr::IndexSet {
+                obj,
+                idx,
+                end,
+                op,
+                to,
+            } => {
+                self.expr(obj)?;
+                self.expr(idx)?;
+                if let Some(op) = op {
+                    self.emit(Op::Dup(2));
+                    self.set_pos(end);
+                    self.emit(Op::GetIndex);
+                    self.expr(to)?;
+                    self.set_pos(op);
+                    self.emit(Op::Binary(op.ty));
+                } else {
+                    self.expr(to)?;
+                }
+                self.set_pos(end);
+                self.emit(Op::SetIndex);
+            }
+            Expr::Lambda { kw, params, body } => {
+                self.set_pos(kw);
+                self.function(FunKind::Function, None, params, body)?;
+            }
+            Expr::Interpolation(parts) => {
+                parts.iter().try_for_each(|it| self.expr(it))?;
+                self.emit(Op::Interpolate(parts.len().try_into().unwrap()));
+            }
+            Expr::List(elems) => {
+                elems.iter().try_for_each(|it| self.expr(it))?;
+                self.emit(Op::List(elems.len().try_into().unwrap()));
+            }
+            Expr::Literal(lit) => {
+                let op = match lit {
+                    Lit::Nil => Op::Nil,
+                    Lit::Bool(true) => Op::True,
+                    Lit::Bool(false) => Op::False,
+                    lit => Op::Constant(self.state().fun.chunk.add_const(lit.clone().into())),
+                };
+                self.emit(op);
+            }
+            Expr::Map { entries, keys, end } => {
+                entries.iter().try_for_each(|(k, v)| {
+                    self.expr(k)?;
+                    self.expr(v)
+                })?;
+                self.set_pos(end);
+                let keys = self.state().fun.chunk.add_map_keys(keys.clone());
+                self.emit(Op::Map(keys));
+            }
+            Expr::Logical { lhs, op, rhs } => {
+                self.expr(lhs)?;
+                match op.ty {
+                    Tk::And => {
+                        let to_end = self.emit(Op::JumpIfFalse(0));
+                        self.emit(Op::Pop);
+                        self.expr(rhs)?;
+                        self.patch(to_end);
+                    }
+                    Tk::Or => {
+                        let to_rhs = self.emit(Op::JumpIfFalse(0));
+                        let to_end = self.emit(Op::Jump(0));
+                        self.patch(to_rhs);
+                        self.emit(Op::Pop);
+                        self.expr(rhs)?;
+                        self.patch(to_end);
+                    }
+                    Tk::QuestionQuestion => {
+                        let to_rhs = self.emit(Op::JumpIfNil(0));
+                        let to_end = self.emit(Op::Jump(0));
+                        self.patch(to_rhs);
+                        self.emit(Op::Pop);
+                        self.expr(rhs)?;
+                        self.patch(to_end);
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Set { obj, name, op, to } => {
+                self.expr(obj)?;
+                let idx = self.name(&name.lexeme);
+                if let Some(op) = op {
+                    self.emit(Op::Dup(1));
+                    self.set_pos(name);
+                    self.emit(Op::GetProperty(idx));
+                    self.expr(to)?;
+                    self.set_pos(op);
+                    self.emit(Op::Binary(op.ty));
+                } else {
+                    self.expr(to)?;
+                }
+                self.set_pos(name);
+                self.emit(Op::SetProperty(idx));
+            }
+            Expr::Super { kw, method } => {
+                self.set_pos(kw);
+                self.get_var("this");
+                self.get_var("super");
+                self.set_pos(method);
+                let idx = self.name(&method.lexeme);
+                self.emit(Op::GetSuper(idx));
+            }
+            Expr::Ternary {
+                cond,
+                then_expr,
+                else_expr,
+            } => {
+                self.expr(cond)?;
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.expr(then_expr)?;
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_else);
+                self.emit(Op::Pop);
+                self.expr(else_expr)?;
+                self.patch(to_end);
+            }
+            Expr::This(kw) => {
+                self.set_pos(kw);
+                self.get_var("this");
+            }
+            Expr::Unary { op, rhs } => {
+                self.expr(rhs)?;
+                self.set_pos(op);
+                self.emit(Op::Unary(op.ty));
+            }
+            Expr::Variable(name) => {
+                self.set_pos(name);
+                self.get_var(&name.lexeme);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles a link in a chain of calls, properties and indices, e.g.
+    /// `a?.b.c()[d]`, where the jumps of optional chainings on `nil` are
+    /// collected in `nil_jumps` so as to short-circuit the whole chain.
+    fn chain(&mut self, expr: &Expr, nil_jumps: &mut Vec<usize>) -> Result<()> {
+        match expr {
+            Expr::Call { callee, args, end } => {
+                self.chain(callee, nil_jumps)?;
+                args.iter().try_for_each(|it| self.expr(it))?;
+                self.set_pos(end);
+                // The argument count has been checked by the parser.
+                self.emit(Op::Call(args.len().try_into().unwrap()));
+            }
+            Expr::Get {
+                obj,
+                name,
+                optional,
+            } => {
+                self.chain(obj, nil_jumps)?;
+                if *optional {
+                    nil_jumps.push(self.emit(Op::JumpIfNil(0)));
+                }
+                self.set_pos(name);
+                let idx = self.name(&name.lexeme);
+                self.emit(Op::GetProperty(idx));
+            }
+            Expr::Index { obj, idx, end } => {
+                self.chain(obj, nil_jumps)?;
+                self.expr(idx)?;
+                self.set_pos(end);
+                self.emit(Op::GetIndex);
+            }
+            expr => self.expr(expr)?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+#![cfg(test)]
+
+use indoc::indoc;
+use pretty_assertions::assert_eq;
+
+use super::*;
+use crate::{lexer::Lexer, parser::Parser, run::run_str};
+
+fn compile(src: &str) -> Function {
+    let stmts = Parser::new(Lexer::new(src)).parse().unwrap();
+    Compiler::compile_stmts(&stmts).unwrap()
+}
+
+fn assert_eval(pairs: &[(&str, &str)]) {
+    let vm = &mut Vm::default();
+    pairs
+        .iter()
+        .try_for_each(|(src, expected)| {
+            let got = run_str("<stdin>", src, vm, true, |_, _| ())?;
+            assert_eq!(expected, &got, "unexpected output for `{}`", src);
+            anyhow::Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn chunk_basic() {
+    let fun = compile("var a = 1 + 2.5; print a;");
+    assert_eq!(
+        vec![
+            Op::Constant(0),
+            Op::Constant(1),
+            Op::Binary(crate::lexer::TokenType::Plus),
+            Op::DefineGlobal(0),
+            Op::GetGlobal(0),
+            Op::Print,
+            Op::Nil,
+            Op::Return,
+        ],
+        fun.chunk.code,
+    );
+    assert_eq!(
+        vec![Object::Number(1.), Object::Number(2.5)],
+        fun.chunk.consts
+    );
+    assert_eq!(vec!["a"], fun.chunk.names);
+}
+
+#[test]
+fn chunk_locals() {
+    let fun = compile("{ var a = true; { var b = a; a = b; } }");
+    assert_eq!(
+        vec![
+            Op::True,
+            Op::GetLocal(1),
+            Op::GetLocal(2),
+            Op::SetLocal(1),
+            Op::Pop,
+            Op::Pop,
+            Op::Pop,
+            Op::Nil,
+            Op::Return,
+        ],
+        fun.chunk.code,
+    );
+}
+
+#[test]
+fn chunk_upvalues() {
+    let fun = compile("fun f(x) { fun g() { return x; } return g; }");
+    let f = &fun.chunk.functions[0];
+    assert_eq!(Some("f"), f.name.as_deref());
+    assert_eq!(1, f.arity);
+    let g = &f.chunk.functions[0];
+    assert!(matches!(
+        g.upvalues[..],
+        [UpvalueDesc {
+            is_local: true,
+            index: 1
+        }]
+    ));
+    assert_eq!(
+        vec![Op::GetUpvalue(0), Op::Return, Op::Nil, Op::Return],
+        g.chunk.code
+    );
+}
+
+#[test]
+fn closure_loop_capture() {
+    assert_eval(&[
+        (
+            indoc! {"
+                var fs = nil;
+                for (var i = 0; i < 3; i = i + 1) {
+                    var j = i;
+                    var prev = fs;
+                    fs = fun () { if (prev) { return prev() * 10 + j; } return j; };
+                }
+            "},
+            "",
+        ),
+        ("fs()", "12"),
+    ]);
+}
+
+#[test]
+fn closure_shared_upvalue() {
+    assert_eval(&[
+        (
+            indoc! {"
+                var get; var set;
+                {
+                    var a = 1;
+                    get = fun () { return a; };
+                    set = fun (x) { a = x; };
+                    a = 2;
+                }
+            "},
+            "",
+        ),
+        ("get()", "2"),
+        ("set(42)", "nil"),
+        ("get()", "42"),
+    ]);
+}
+
+#[test]
+fn jump_in_nested_blocks() {
+    assert_eval(&[
+        ("var sum = 0;", ""),
+        (
+            indoc! {"
+                for (var i = 0; i < 10; i = i + 1) {
+                    var j = i;
+                    {
+                        var k = j * 2;
+                        if (k > 10) break;
+                        sum = sum + k;
+                    }
+                }
+            "},
+            "",
+        ),
+        ("sum", "30"),
+    ]);
+}
+
+#[test]
+fn reuse_after_error() {
+    let vm = &mut Vm::default();
+    run_str("<stdin>", "var a = 1;", vm, true, |_, _| ()).unwrap();
+    let err = run_str(
+        "<stdin>",
+        "{ var b = 2; a = b + undefined; }",
+        vm,
+        true,
+        |_, _| (),
+    )
+    .unwrap_err();
+    assert!(format!("{:?}", err).contains("identifier `undefined` is undefined"));
+    run_str("<stdin>", "{ var c = 1; a = a + c; }", vm, true, |_, _| ()).unwrap();
+    assert_eq!("2", run_str("<stdin>", "a", vm, true, |_, _| ()).unwrap());
+}
+
+#[test]
+fn deep_recursion() {
+    assert_eval(&[
+        (
+            "fun count(n) { if (n <= 0) { return 0; } return 1 + count(n - 1); }",
+            "",
+        ),
+        ("count(10000)", "10000"),
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "maximum call depth (65536) exceeded")]
+fn infinite_recursion() {
+    assert_eval(&[("fun f() { return f(); }", ""), ("f()", "")]);
+}
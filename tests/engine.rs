@@ -0,0 +1,152 @@
+use std::{cell::Cell, rc::Rc};
+
+use dolores::{Arity, Code, Diagnostic, Engine, Object};
+use pretty_assertions::assert_eq;
+
+#[test]
+fn eval_expr() {
+    let mut engine = Engine::new();
+    assert_eq!(Object::Number(4.), engine.eval("2 + 2").unwrap());
+    assert_eq!(Object::from("lox"), engine.eval(r#""l" + "ox""#).unwrap());
+}
+
+#[test]
+fn eval_trailing_tokens() {
+    let err = Engine::new().eval("2 + 2; 3").unwrap_err();
+    assert!(format!("{err}").contains("unexpected trailing token `;`"));
+}
+
+#[test]
+fn run_program_and_read_globals() {
+    let mut engine = Engine::new();
+    engine
+        .run("var answer; { var i = 6; answer = i * 7; }")
+        .unwrap();
+    assert_eq!(Some(Object::Number(42.)), engine.global("answer"));
+    assert_eq!(None, engine.global("i"));
+}
+
+#[test]
+fn set_globals() {
+    let mut engine = Engine::new();
+    engine.set_global("name", "Dolores");
+    engine.set_global("awake", true);
+    engine.set_global("nothing", ());
+    assert_eq!(
+        "Dolores",
+        String::try_from(engine.eval(r#"awake and name"#).unwrap()).unwrap(),
+    );
+    assert_eq!(Object::Nil, engine.eval("nothing").unwrap());
+}
+
+#[test]
+fn call_fun() {
+    let mut engine = Engine::new();
+    engine
+        .run("fun fact(i) { if (i <= 0) { return 1; } return i * fact(i - 1); }")
+        .unwrap();
+    let got = engine.call("fact", [5.0.into()]).unwrap();
+    assert_eq!(120., f64::try_from(got).unwrap());
+}
+
+#[test]
+fn call_class() {
+    let mut engine = Engine::new();
+    engine
+        .run("class Point { init(x, y) { this.x = x; this.y = y; } }")
+        .unwrap();
+    let point = engine.call("Point", [1.0.into(), 2.0.into()]).unwrap();
+    engine.set_global("p", point);
+    assert_eq!(Object::Number(3.), engine.eval("p.x + p.y").unwrap());
+}
+
+#[test]
+fn call_errors() {
+    let mut engine = Engine::new();
+    let err = engine.call("nope", []).unwrap_err();
+    assert!(format!("{err}").contains("identifier `nope` is undefined"));
+    engine.set_global("one", 1.);
+    let err = engine.call("one", []).unwrap_err();
+    assert!(format!("{err}").contains("the object `1` is not callable"));
+}
+
+#[test]
+fn conversion_errors() {
+    assert!(bool::try_from(Object::Nil).is_err());
+    assert!(String::try_from(Object::Number(1.)).is_err());
+    assert_eq!(1., f64::try_from(Object::Bool(true)).unwrap());
+}
+
+#[test]
+fn register_fn() {
+    let mut engine = Engine::new();
+    engine.register_fn("hypot", Arity::Fixed(2), |args| {
+        let [x, y]: [f64; 2] = [args[0].clone().try_into()?, args[1].clone().try_into()?];
+        Ok(x.hypot(y).into())
+    });
+    assert_eq!(Object::Number(5.), engine.eval("hypot(3, 4)").unwrap());
+    assert_eq!(
+        "<fun: hypot@foreign>",
+        format!("{}", engine.eval("hypot").unwrap())
+    );
+}
+
+#[test]
+fn register_fn_capture() {
+    let mut engine = Engine::new();
+    let count = Rc::new(Cell::new(0.));
+    let count1 = Rc::clone(&count);
+    engine.register_fn("tick", Arity::Fixed(0), move |_| {
+        count1.set(count1.get() + 1.);
+        Ok(count1.get().into())
+    });
+    engine
+        .run("for (var i = 0; i < 3; i = i + 1) tick();")
+        .unwrap();
+    assert_eq!(3., count.get());
+    assert_eq!(Object::Number(4.), engine.call("tick", []).unwrap());
+}
+
+#[test]
+fn register_fn_arity() {
+    let mut engine = Engine::new();
+    engine.register_fn("count", Arity::Variadic(1), |args| {
+        Ok(Object::Number(args.len() as f64))
+    });
+    engine.register_fn("opt", Arity::Optional { min: 1, max: 2 }, |args| {
+        Ok(args.get(1).cloned().into())
+    });
+    assert_eq!(Object::Number(3.), engine.eval("count(1, 2, 3)").unwrap());
+    assert_eq!(Object::Nil, engine.eval("opt(1)").unwrap());
+    assert_eq!(Object::Bool(true), engine.eval("opt(1, true)").unwrap());
+
+    let err = engine.eval("count()").unwrap_err();
+    let diag = err.downcast_ref::<Diagnostic>().unwrap();
+    assert_eq!(Code::ArityMismatch, diag.code);
+    assert_eq!(
+        "while evaluating a foreign function Call expression",
+        diag.ctx,
+    );
+    assert_eq!(
+        "unexpected number of parameters (expected at least 1, got 0)",
+        diag.msg,
+    );
+    assert!(engine.render(&err).contains("--> <input>:1:7"));
+    let err = engine.eval("opt(1, 2, 3)").unwrap_err();
+    assert!(format!("{err}").contains("(expected 1 to 2, got 3)"));
+}
+
+#[test]
+fn register_fn_error() {
+    let mut engine = Engine::new();
+    engine.register_fn("fail", Arity::Fixed(0), |_| {
+        anyhow::bail!("something went wrong")
+    });
+    let err = engine.run("var a = 1;\nfail();").unwrap_err();
+    let diag = err.downcast_ref::<Diagnostic>().unwrap();
+    assert_eq!(Code::Foreign, diag.code);
+    assert_eq!("something went wrong", diag.msg);
+    let rendered = engine.render(&err);
+    assert!(rendered.starts_with("Runtime Error[E0306]: something went wrong"));
+    assert!(rendered.contains("--> <input>:2:6"));
+}